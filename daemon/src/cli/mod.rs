@@ -4,12 +4,15 @@ mod prompt;
 use self::colors::*;
 use crate::notify::notify;
 use apt_cmd::AptUpgradeEvent;
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate, TimeZone};
 use clap::ArgMatches;
 use num_traits::FromPrimitive;
 use pop_upgrade::{
     client,
+    conffiles::{Conffile, Resolution},
     daemon::*,
+    health::{HealthReport, HealthStatus},
+    history::{HistoryEntry, PackageChange},
     misc,
     recovery::{RecoveryEvent, ReleaseFlags as RecoveryReleaseFlags},
     release::{
@@ -17,7 +20,11 @@ use pop_upgrade::{
         systemd::{self, LoaderEntry},
         RefreshOp, UpgradeEvent, UpgradeMethod,
     },
+    repair::RepairReport,
+    schedule::{self, Schedule},
     ubuntu_version::{Codename, Version as UbuntuVersion},
+    updates::{PendingUpdate, Selection},
+    watchdog::Stall,
 };
 use std::{
     convert::TryFrom,
@@ -31,6 +38,10 @@ const FETCH_RESULT_STR: &str = "Package fetch status";
 const FETCH_RESULT_SUCCESS: &str = "cargo has been loaded successfully";
 const FETCH_RESULT_ERROR: &str = "package-fetching aborted";
 
+const PACKAGE_UPGRADE_RESULT_STR: &str = "Package upgrade status";
+const PACKAGE_UPGRADE_RESULT_SUCCESS: &str = "packages upgraded successfully";
+const PACKAGE_UPGRADE_RESULT_ERROR: &str = "package upgrade aborted";
+
 const RECOVERY_RESULT_STR: &str = "Recovery upgrade status";
 const RECOVERY_RESULT_SUCCESS: &str = "recovery partition refueled and ready to go";
 const RECOVERY_RESULT_ERROR: &str = "recovery upgrade aborted";
@@ -132,16 +143,103 @@ impl Client {
             }
             // Update the current system, without performing a release upgrade
             Some(("update", matches)) => {
-                let updates = self.fetch_updates(Vec::new(), matches.get_flag("download-only"))?;
+                if matches.get_flag("list") {
+                    let updates = self.list_updates()?;
+
+                    if updates.is_empty() {
+                        println!("no updates available");
+                    }
+
+                    updates.iter().for_each(write_pending_update);
+                    return Ok(());
+                }
+
+                if matches.get_flag("undo") {
+                    let transaction = self.undo_update()?;
+
+                    for change in &transaction.changes {
+                        let from = change.from.as_deref().unwrap_or("kept, as it is new");
+                        println!(
+                            "{}: {} -> {}",
+                            color_primary(&change.package),
+                            change.to.as_deref().unwrap_or("removed"),
+                            color_secondary(from)
+                        );
+                    }
+
+                    self.event_listen_package_upgrade()?;
+                    return Ok(());
+                }
+
+                let offline = matches.get_flag("offline");
+                let download_only = offline || matches.get_flag("download-only");
+                let globs = |name| {
+                    matches.get_many::<String>(name).into_iter().flatten().cloned().collect()
+                };
+
+                let selection = Selection {
+                    security_only: matches.get_flag("security-only"),
+                    include:       globs("include"),
+                    exclude:       globs("exclude"),
+                };
 
-                let client::Fetched { updates_available, completed, total } = updates;
+                let updates = self.fetch_updates(Vec::new(), download_only, selection)?;
+
+                let client::Fetched { updates_available, completed, total, bytes, deferred } =
+                    updates;
+
+                if deferred != 0 {
+                    println!("{} updates are deferred while they are being phased in", deferred);
+                }
 
                 if !updates_available || total == 0 {
-                    println!("no updates available to fetch");
+                    if !offline {
+                        println!("no updates available to fetch");
+                    }
                 } else {
-                    println!("fetching updates: {} of {} updates fetched", completed, total);
+                    println!(
+                        "fetching updates: {} of {} updates fetched ({})",
+                        completed,
+                        total,
+                        format_bytes(bytes)
+                    );
                     self.event_listen_fetch_updates()?;
                 }
+
+                if offline {
+                    let packages = self.offline_update()?;
+                    println!(
+                        "{} package updates will be installed when the system restarts",
+                        packages
+                    );
+                }
+            }
+            Some(("offline-update", matches)) => {
+                if matches.subcommand_name() == Some("cancel") {
+                    self.offline_update_cancel()?;
+                }
+
+                let (pending, last) = self.offline_update_status()?;
+
+                println!(
+                    "updates will {}be installed when the system restarts",
+                    if pending { "" } else { "not " }
+                );
+
+                log_result(
+                    last.status,
+                    "Last offline update",
+                    "package updates installed successfully",
+                    "package updates failed to install",
+                    &last.why,
+                );
+            }
+            Some(("upgrade-packages", _)) => {
+                self.package_upgrade()?;
+
+                if !self.event_listen_package_upgrade()? {
+                    return Err(anyhow!("package upgrade failed"));
+                }
             }
             // Perform an upgrade to the next release. Supports either systemd or recovery upgrades.
             Some(("upgrade", matches)) => {
@@ -191,8 +289,87 @@ impl Client {
                 self.refresh_os(action)?;
                 println!("reboot to boot into the recovery partition to begin the refresh install");
             }
-            Some(("repair", _)) => {
-                self.release_repair()?;
+            Some(("reinstall-removed", matches)) => {
+                let removed = self.removed_packages()?;
+
+                if removed.is_empty() {
+                    println!("no removed packages are available to reinstall");
+                    return Ok(());
+                }
+
+                let packages = match matches.get_many::<String>("PACKAGES") {
+                    Some(packages) => packages.cloned().collect::<Vec<_>>(),
+                    None => {
+                        println!("packages removed by the upgrade which can be reinstalled:");
+                        for removed in &removed {
+                            println!(
+                                "  {} ({}): {}",
+                                color_primary(&removed.package),
+                                color_secondary(&removed.candidate),
+                                removed.reason
+                            );
+                        }
+
+                        prompt::get_words("Packages to reinstall (none by default): ")
+                    }
+                };
+
+                if let Some(package) = packages
+                    .iter()
+                    .find(|package| !removed.iter().any(|removed| &*removed.package == *package))
+                {
+                    return Err(anyhow!("{} cannot be reinstalled", package));
+                }
+
+                if !packages.is_empty() {
+                    self.reinstall_removed(&packages)?;
+                    println!("reinstalled {}", packages.join(", "));
+                }
+            }
+            Some(("repair", matches)) => {
+                if matches.get_flag("list") {
+                    for check in self.repair_list()? {
+                        println!(
+                            "{} [{}]: {}",
+                            color_primary(&check.id),
+                            color_secondary(&check.stage),
+                            check.description
+                        );
+                    }
+
+                    return Ok(());
+                }
+
+                let only = matches
+                    .get_many::<String>("only")
+                    .map(|only| only.cloned().collect::<Vec<_>>())
+                    .unwrap_or_default();
+
+                let reports = self.repair_run(&only, matches.get_flag("dry-run"))?;
+
+                for report in &reports {
+                    write_repair_report(report);
+                }
+
+                if reports.iter().any(|report| report.error.is_some()) {
+                    return Err(anyhow!("some repair checks failed"));
+                }
+            }
+            Some(("verify", matches)) => {
+                let release =
+                    matches.get_one::<String>("RELEASE").map(String::as_str).unwrap_or_default();
+
+                let report = if matches.get_flag("last") {
+                    self.health_status()?
+                } else {
+                    self.health_check(release)?
+                };
+
+                write_health_report(&report);
+
+                if report.status() == HealthStatus::Failed {
+                    return Err(anyhow!("system health verification failed"));
+                }
             }
             _ => unreachable!(),
         }
@@ -200,6 +377,92 @@ impl Client {
         Ok(())
     }
 
+    /// Lists the new configuration files which are awaiting review, or resolves one of them.
+    pub fn conffiles(&self, matches: &ArgMatches) -> anyhow::Result<()> {
+        let (resolution, path) = match matches.subcommand() {
+            Some((name, matches)) => {
+                let resolution = match name {
+                    "accept" => Resolution::Accept,
+                    "keep" => Resolution::Keep,
+                    "merge" => Resolution::Merge,
+                    _ => unreachable!(),
+                };
+
+                (resolution, matches.get_one::<String>("PATH").cloned().unwrap_or_default())
+            }
+            None => {
+                let conffiles = self.0.conffiles()?;
+
+                if conffiles.is_empty() {
+                    println!("no configuration files to review");
+                }
+
+                for conffile in &conffiles {
+                    write_conffile(conffile, matches.get_flag("diff"));
+                }
+
+                return Ok(());
+            }
+        };
+
+        self.conffile_resolve(&path, resolution)?;
+
+        let resolved = match resolution {
+            Resolution::Accept => "accepted",
+            Resolution::Keep => "discarded",
+            Resolution::Merge => "merged",
+        };
+
+        pintln!((color_primary(&path)) ": " (color_secondary(resolved)));
+
+        Ok(())
+    }
+
+    /// Shows the transactions of apt and the jobs of the daemon, optionally since a date.
+    pub fn history(&self, matches: &ArgMatches) -> anyhow::Result<()> {
+        let since = match matches.get_one::<String>("since") {
+            Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| anyhow!("{} is not a date, such as 2023-10-24", date))?
+                .and_hms_opt(0, 0, 0)
+                .and_then(|date| Local.from_local_datetime(&date).earliest())
+                .map_or(0, |date| date.timestamp()),
+            None => 0,
+        };
+
+        let packages = matches.get_flag("packages");
+
+        for entry in self.0.history(since)? {
+            write_history_entry(&entry, packages);
+        }
+
+        Ok(())
+    }
+
+    /// Shows or replaces the schedule that the daemon installs package updates on.
+    pub fn schedule(&self, matches: &ArgMatches) -> anyhow::Result<()> {
+        let schedule = match matches.subcommand() {
+            Some(("window", matches)) => {
+                let arg = |name| matches.get_one::<String>(name).map(String::as_str);
+                let days = schedule::parse_days(arg("DAYS").unwrap_or_default())?;
+                let start = schedule::parse_time(arg("START").unwrap_or_default())?;
+                let end = schedule::parse_time(arg("END").unwrap_or_default())?;
+                Schedule::window(days, start, end)?
+            }
+            Some(("on-shutdown", _)) => Schedule::on_shutdown(),
+            Some(("disable", _)) => Schedule::default(),
+            _ => {
+                let schedule = self.0.schedule()?;
+                pintln!((color_primary("Package updates")) ": " (color_secondary(schedule)));
+                return Ok(());
+            }
+        };
+
+        self.set_schedule(schedule)?;
+        pintln!((color_primary("Package updates scheduled")) ": " (color_secondary(schedule)));
+
+        Ok(())
+    }
+
     pub fn status(&self, _matches: &ArgMatches) -> anyhow::Result<()> {
         let info = self.0.status()?;
 
@@ -275,8 +538,12 @@ impl Client {
                     &new_status.why,
                 );
             },
-            |_client, signal| {
+            |client, signal| {
                 match signal {
+                    client::Signal::MeteredDownload(bytes) => {
+                        approve_metered_download(client, bytes)?;
+                    }
+                    client::Signal::Stalled(stall) => kill_stalled(client, &stall)?,
                     client::Signal::PackageFetchResult(status) => {
                         log_result(
                             status.status,
@@ -290,13 +557,15 @@ impl Client {
                     }
                     client::Signal::PackageFetched(status) => {
                         println!(
-                            "{} ({}/{}) {}",
+                            "{} ({}/{}) {} [{}]",
                             color_primary("Fetched"),
                             color_info(status.completed),
                             color_info(status.total),
-                            color_secondary(status.package)
+                            color_secondary(&status.package),
+                            fetch_throughput(&status)
                         );
                     }
+                    client::Signal::PackageFetchRetrying(retry) => write_fetch_retry(&retry),
                     client::Signal::PackageUpgrade(event) => {
                         if let Ok(event) = AptUpgradeEvent::from_dbus_map(event.into_iter()) {
                             write_apt_event(event);
@@ -304,6 +573,9 @@ impl Client {
                             error!("failed to unpack the upgrade event");
                         }
                     }
+                    client::Signal::HealthReport(report) => write_health_report(&report),
+                    client::Signal::Progress(progress) => write_progress(&progress),
+                    client::Signal::RepairReport(report) => write_repair_report(&report),
                     _ => (),
                 }
 
@@ -312,6 +584,52 @@ impl Client {
         )
     }
 
+    /// Listens for the progress of a package upgrade, and returns `true` if it succeeded.
+    fn event_listen_package_upgrade(&self) -> Result<bool, client::Error> {
+        self.event_listen(
+            client::Client::package_upgrade_status,
+            |new_status| {
+                log_result(
+                    new_status.status,
+                    PACKAGE_UPGRADE_RESULT_STR,
+                    PACKAGE_UPGRADE_RESULT_SUCCESS,
+                    PACKAGE_UPGRADE_RESULT_ERROR,
+                    &new_status.why,
+                );
+            },
+            |client, signal| {
+                match signal {
+                    client::Signal::PackageUpgradeResult(status) => {
+                        log_result(
+                            status.status,
+                            PACKAGE_UPGRADE_RESULT_STR,
+                            PACKAGE_UPGRADE_RESULT_SUCCESS,
+                            PACKAGE_UPGRADE_RESULT_ERROR,
+                            &status.why,
+                        );
+
+                        return Ok(client::Continue::False);
+                    }
+                    client::Signal::PackageUpgrade(event) => {
+                        if let Ok(event) = AptUpgradeEvent::from_dbus_map(event.into_iter()) {
+                            write_apt_event(event);
+                        } else {
+                            error!("failed to unpack the upgrade event");
+                        }
+                    }
+                    client::Signal::HealthReport(report) => write_health_report(&report),
+                    client::Signal::Progress(progress) => write_progress(&progress),
+                    client::Signal::Stalled(stall) => kill_stalled(client, &stall)?,
+                    _ => (),
+                }
+
+                Ok(client::Continue::True)
+            },
+        )?;
+
+        self.package_upgrade_status().map(|status| status.status == 0)
+    }
+
     fn event_listen_recovery_upgrade(&self) -> Result<(), client::Error> {
         let mut reset = false;
 
@@ -326,8 +644,11 @@ impl Client {
                     &new_status.why,
                 );
             },
-            |_client, signal| {
+            |client, signal| {
                 match signal {
+                    client::Signal::MeteredDownload(bytes) => {
+                        approve_metered_download(client, bytes)?;
+                    }
                     client::Signal::RecoveryDownloadProgress(progress) => {
                         print!(
                             "\r{} {}/{} {}",
@@ -388,6 +709,12 @@ impl Client {
         let recall = &mut false;
         let total = &mut 0;
 
+        // Removals which were signaled before the client was listening are still awaiting approval.
+        let packages = self.pending_removals()?;
+        if !packages.is_empty() {
+            approve_removals(self, &packages)?;
+        }
+
         let result = self.event_listen(
             client::Client::release_upgrade_status,
             |new_status| {
@@ -399,7 +726,7 @@ impl Client {
                     &new_status.why,
                 );
             },
-            |_client, signal| {
+            |client, signal| {
                 use client::Signal;
                 match signal {
                     Signal::PackageFetchResult(status) => {
@@ -414,14 +741,17 @@ impl Client {
 
                     Signal::PackageFetched(package) => {
                         println!(
-                            "{} ({}/{}): {}",
+                            "{} ({}/{}): {} [{}]",
                             color_primary("Fetched"),
                             color_info(package.completed),
                             color_info(package.total),
-                            color_secondary(&package.package)
+                            color_secondary(&package.package),
+                            fetch_throughput(&package)
                         );
                     }
 
+                    Signal::PackageFetchRetrying(retry) => write_fetch_retry(&retry),
+
                     Signal::PackageUpgrade(event) => {
                         match AptUpgradeEvent::from_dbus_map(event.clone().into_iter()) {
                             Ok(event) => write_apt_event(event),
@@ -474,6 +804,9 @@ impl Client {
                         );
                     }
 
+                    Signal::RepairReport(report) => write_repair_report(&report),
+                    Signal::Progress(progress) => write_progress(&progress),
+
                     Signal::ReleaseResult(status) => {
                         if !*recall {
                             log_result(
@@ -500,6 +833,12 @@ impl Client {
                         );
                     }
 
+                    Signal::PendingRemovals(packages) => approve_removals(client, &packages)?,
+
+                    Signal::MeteredDownload(bytes) => approve_metered_download(client, bytes)?,
+
+                    Signal::Stalled(stall) => kill_stalled(client, &stall)?,
+
                     Signal::NoConnection => {
                         println!(
                             "{}",
@@ -624,6 +963,256 @@ fn write_apt_event(event: AptUpgradeEvent) {
     }
 }
 
+fn write_progress(progress: &client::OverallProgress) {
+    let eta = progress.eta.map(|eta| {
+        let secs = eta.as_secs();
+        format!(", {}m {}s remaining", secs / 60, secs % 60)
+    });
+
+    println!(
+        "{}: {}% ({}{})",
+        color_primary("Overall progress"),
+        color_info(progress.percent),
+        color_secondary(&progress.stage),
+        eta.unwrap_or_default()
+    );
+}
+
+/// Asks which of the packages that a release upgrade will remove to keep, and cancels the
+/// upgrade if the removals are declined.
+fn approve_removals(client: &client::Client, packages: &[String]) -> Result<(), client::Error> {
+    println!("{}:", color_primary("The following packages will be removed"));
+
+    for package in packages {
+        println!("    {}", color_secondary(package));
+    }
+
+    let prompt = format!(
+        "    {} ",
+        color_primary("Packages to keep, separated by spaces (blank for none):")
+    );
+
+    let keep = loop {
+        let keep = prompt::get_words(&prompt);
+        match keep.iter().find(|package| !packages.contains(package)) {
+            Some(package) => println!("{} is not being removed", package),
+            None => break keep,
+        }
+    };
+
+    let prompt = format!("    {} y/N", color_primary("Proceed with the upgrade?"));
+
+    if prompt::get_bool(&prompt, false) {
+        client.approve_removals(&keep)
+    } else {
+        client.cancel()
+    }
+}
+
+/// Asks to approve a download over a metered connection, and cancels the task if declined.
+fn approve_metered_download(client: &client::Client, bytes: u64) -> Result<(), client::Error> {
+    let size = if bytes == 0 { "an unknown amount".to_owned() } else { format_bytes(bytes) };
+
+    println!(
+        "{}: {} will be downloaded",
+        color_primary("The connection is metered"),
+        color_secondary(size)
+    );
+
+    let prompt = format!("    {} y/N", color_primary("Download over a metered connection?"));
+
+    if prompt::get_bool(&prompt, false) {
+        client.approve_metered_download()
+    } else {
+        client.cancel()
+    }
+}
+
+/// Asks to kill the process of an apt or dpkg operation which has stalled.
+fn kill_stalled(client: &client::Client, stall: &Stall) -> Result<(), client::Error> {
+    let process = if stall.package.is_empty() {
+        format!("dpkg ({})", stall.pid)
+    } else {
+        format!("the {} script of {} ({})", stall.script, stall.package, stall.pid)
+    };
+
+    println!(
+        "{}: {} has made no progress for {} minutes",
+        color_error("The upgrade has stalled"),
+        color_secondary(process),
+        stall.idle / 60
+    );
+
+    let prompt = format!("    {} y/N", color_primary("Kill it and run `dpkg --configure -a`?"));
+
+    if prompt::get_bool(&prompt, false) {
+        client.kill_stalled()
+    } else {
+        Ok(())
+    }
+}
+
+fn write_fetch_retry(retry: &client::FetchRetry) {
+    println!(
+        "{} ({}) {}: {}",
+        color_primary("Retrying"),
+        color_info(retry.attempt),
+        color_secondary(&retry.package),
+        color_error_desc(&retry.why)
+    );
+}
+
+/// Describes the bytes fetched, the rate of the fetch, and the time remaining.
+fn fetch_throughput(status: &client::FetchStatus) -> String {
+    let mut description = format!(
+        "{} of {}, {}/s",
+        format_bytes(status.fetched_bytes),
+        format_bytes(status.total_bytes),
+        format_bytes(status.rate)
+    );
+
+    if status.rate != 0 {
+        let secs = status.total_bytes.saturating_sub(status.fetched_bytes) / status.rate;
+        description.push_str(&format!(", {}m {}s remaining", secs / 60, secs % 60));
+    }
+
+    description
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = "";
+
+    for &next in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+
+        size /= 1000.0;
+        unit = next;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+/// Verifies that the system can boot, without the need of the daemon.
+pub fn check_boot() -> anyhow::Result<()> {
+    let report = pop_upgrade::health::verify_bootable();
+
+    write_health_report(&report);
+
+    if report.status() == HealthStatus::Failed {
+        return Err(anyhow!("the system may not be able to boot"));
+    }
+
+    Ok(())
+}
+
+fn write_health_report(report: &HealthReport) {
+    for check in &report.checks {
+        let status = <&'static str>::from(check.status);
+        let status = match check.status {
+            HealthStatus::Ok => color_info(status),
+            HealthStatus::Warning => color_secondary(status),
+            HealthStatus::Failed => color_error(status),
+        };
+
+        println!("{} [{}]: {}", color_primary(&check.check), status, check.detail);
+    }
+}
+
+fn write_conffile(conffile: &Conffile, diff: bool) {
+    println!("{} -> {}", color_primary(&conffile.path), conffile.active);
+
+    if diff {
+        for line in conffile.diff.lines() {
+            println!("    {}", line);
+        }
+    }
+}
+
+fn write_history_entry(entry: &HistoryEntry, packages: bool) {
+    let time = Local
+        .timestamp_opt(entry.time, 0)
+        .single()
+        .map_or_else(String::new, |time| time.format("%Y-%m-%d %H:%M").to_string());
+
+    let status = if entry.error.is_empty() { color_info("ok") } else { color_error("failed") };
+
+    println!("{} [{}] {}: {}", color_primary(&time), status, entry.source, entry.description);
+
+    if !entry.error.is_empty() {
+        println!("    {}", entry.error);
+    }
+
+    if !packages {
+        if !entry.changes.is_empty() {
+            println!("    {} packages changed", entry.changes.len());
+        }
+
+        return;
+    }
+
+    for PackageChange { package, action, from, to } in &entry.changes {
+        let versions = match (from, to) {
+            (Some(from), Some(to)) => [from, " -> ", to].concat(),
+            (Some(version), None) | (None, Some(version)) => version.clone(),
+            (None, None) => String::new(),
+        };
+
+        println!("    {} {} {}", color_secondary(action), package, versions);
+    }
+}
+
+fn write_pending_update(update: &PendingUpdate) {
+    let installed = if update.installed.is_empty() { "new" } else { &update.installed };
+
+    println!(
+        "{} {} -> {} ({}, {})",
+        color_primary(&update.package),
+        installed,
+        color_secondary(&update.candidate),
+        update.origin,
+        format_bytes(update.size)
+    );
+
+    for line in update.changelog.lines() {
+        println!("    {}", line);
+    }
+}
+
+fn write_repair_report(report: &RepairReport) {
+    let status = if report.error.is_some() {
+        color_error("failed")
+    } else if report.found.is_empty() {
+        color_info("ok")
+    } else if report.changed.is_empty() {
+        color_secondary("found")
+    } else {
+        color_secondary("repaired")
+    };
+
+    println!("{} [{}]", color_primary(&report.id), status);
+
+    for found in &report.found {
+        println!("  found: {}", found);
+    }
+
+    for changed in &report.changed {
+        println!("  changed: {}", changed);
+    }
+
+    if let Some(why) = report.error.as_ref() {
+        println!("  {}: {}", color_error("error"), color_error_desc(why));
+    }
+}
+
 fn log_result(
     status: u8,
     event: &'static str,
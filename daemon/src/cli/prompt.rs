@@ -28,6 +28,16 @@ pub fn get_bool(message: &str, default: bool) -> bool {
     }
 }
 
+// Write a prompt to the terminal, and return the whitespace-separated words of the answer.
+pub fn get_words(message: &str) -> Vec<String> {
+    let std_in = io::stdin();
+    let std_out = io::stdout();
+
+    get_input(std_in.lock(), std_out.lock(), message)
+        .map(|input| input.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
 fn get_input(
     mut std_in: impl BufRead,
     mut std_out: impl Write,
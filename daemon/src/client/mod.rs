@@ -1,18 +1,27 @@
 use crate::{
+    conffiles::{Conffile, Resolution},
     daemon::*,
+    health::HealthReport,
+    history::{DbusEntry, HistoryEntry},
     recovery::{RecoveryEvent, ReleaseFlags as RecoveryReleaseFlags},
     release::{RefreshOp, UpgradeEvent, UpgradeMethod},
-    sighandler, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
+    repair::RepairReport,
+    schedule::{Schedule, ScheduleError},
+    sighandler,
+    transaction::Transaction,
+    updates::{PendingUpdate, Selection},
+    watchdog::Stall,
+    DBUS_IFACE, DBUS_NAME, DBUS_PATH,
 };
 
 use dbus::{
     arg::messageitem::{MessageItem, MessageItemArray},
-    ffidisp::{Connection, ConnectionItem},
+    ffidisp::{stdintf::org_freedesktop_dbus::Properties, Connection, ConnectionItem},
     Message, Signature,
 };
 
 use num_traits::FromPrimitive;
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use thiserror::Error;
 
 const TIMEOUT: i32 = 0x7fff_ffff;
@@ -20,9 +29,23 @@ const TIMEOUT: i32 = 0x7fff_ffff;
 // Information about the current fetch progress.
 #[derive(Clone, Debug)]
 pub struct FetchStatus {
-    pub package:   Box<str>,
-    pub completed: u32,
-    pub total:     u32,
+    pub package:       Box<str>,
+    pub completed:     u32,
+    pub total:         u32,
+    /// Bytes fetched so far, out of `total_bytes`.
+    pub fetched_bytes: u64,
+    pub total_bytes:   u64,
+    /// The rate of the fetch, in bytes per second.
+    pub rate:          u64,
+}
+
+/// A package which failed to be fetched, and will be fetched again.
+#[derive(Clone, Debug)]
+pub struct FetchRetry {
+    pub package: Box<str>,
+    /// The number of this retry, starting from `1`.
+    pub attempt: u32,
+    pub why:     Box<str>,
 }
 
 /// Data for tracking progress of an action.
@@ -32,6 +55,37 @@ pub struct Progress {
     pub total:    u64,
 }
 
+/// The overall progress of the daemon's current task, weighted by stage.
+#[derive(Clone, Debug)]
+pub struct OverallProgress {
+    pub percent: u8,
+    pub stage:   Box<str>,
+    /// The estimated time remaining, if it is known.
+    pub eta:     Option<Duration>,
+}
+
+impl OverallProgress {
+    fn from_dbus((percent, stage, eta): (u8, String, i64)) -> Self {
+        Self { percent, stage: stage.into(), eta: u64::try_from(eta).ok().map(Duration::from_secs) }
+    }
+}
+
+/// Describes a repair check which the daemon can apply.
+#[derive(Clone, Debug)]
+pub struct RepairCheckInfo {
+    pub id:          Box<str>,
+    pub stage:       Box<str>,
+    pub description: Box<str>,
+}
+
+/// A package removed by an upgrade, which may now be reinstalled.
+#[derive(Clone, Debug)]
+pub struct RemovedPackage {
+    pub package:   Box<str>,
+    pub reason:    Box<str>,
+    pub candidate: Box<str>,
+}
+
 /// Contains information about good and bad repositories.
 #[derive(Clone, Debug)]
 pub struct RepoCompatError {
@@ -42,16 +96,27 @@ pub struct RepoCompatError {
 /// A signal received by the daemon.
 #[derive(Debug)]
 pub enum Signal {
+    HealthReport(HealthReport),
     NoConnection,
     PackageFetchResult(Status),
+    /// A download of the given bytes, or of an unknown size if `0`, awaits approval because the
+    /// connection is metered.
+    MeteredDownload(u64),
     PackageFetched(FetchStatus),
+    PackageFetchRetrying(FetchRetry),
     PackageFetching(Box<str>),
     PackageUpgrade(HashMap<Box<str>, Box<str>>),
+    PackageUpgradeResult(Status),
+    PendingRemovals(Vec<String>),
+    Progress(OverallProgress),
     RecoveryDownloadProgress(Progress),
     RecoveryEvent(RecoveryEvent),
     RecoveryResult(Status),
     ReleaseResult(Status),
     ReleaseEvent(UpgradeEvent),
+    RepairReport(RepairReport),
+    /// An apt or dpkg operation has stopped making progress, and its process may be killed.
+    Stalled(Stall),
 }
 
 /// Designates if the signal event loop should continue listening for signals.
@@ -74,6 +139,10 @@ pub struct Fetched {
     pub updates_available: bool,
     pub completed:         u32,
     pub total:             u32,
+    /// The combined size of the packages to fetch, in bytes.
+    pub bytes:             u64,
+    /// The number of updates that are held back because they are being phased in.
+    pub deferred:          u32,
 }
 
 /// The version of the recovery partition's image.
@@ -120,6 +189,9 @@ pub enum Error {
     #[error("daemon status integer was outside the acceptable range of values")]
     DaemonStatusOutOfRange,
 
+    #[error("daemon returned an invalid schedule")]
+    InvalidSchedule(#[source] ScheduleError),
+
     #[error("failed to create {} method call", _0)]
     NewMethodCall(&'static str, String),
 
@@ -144,29 +216,67 @@ impl Client {
         Connection::new_system().map_err(Error::Connection).and_then(|bus| {
             {
                 let bus = &bus;
+                add_match(bus, signals::HEALTH_REPORT)?;
                 add_match(bus, signals::NO_CONNECTION)?;
                 add_match(bus, signals::PACKAGE_FETCH_RESULT)?;
+                add_match(bus, signals::METERED_DOWNLOAD)?;
                 add_match(bus, signals::PACKAGE_FETCHED)?;
+                add_match(bus, signals::PACKAGE_FETCH_RETRYING)?;
                 add_match(bus, signals::PACKAGE_FETCHING)?;
                 add_match(bus, signals::PACKAGE_UPGRADE)?;
+                add_match(bus, signals::PACKAGE_UPGRADE_RESULT)?;
+                add_match(bus, signals::PENDING_REMOVALS)?;
+                add_match(bus, signals::PROGRESS)?;
                 add_match(bus, signals::RECOVERY_DOWNLOAD_PROGRESS)?;
                 add_match(bus, signals::RECOVERY_RESULT)?;
                 add_match(bus, signals::RECOVERY_EVENT)?;
                 add_match(bus, signals::RELEASE_RESULT)?;
                 add_match(bus, signals::RELEASE_EVENT)?;
+                add_match(bus, signals::REPAIR_REPORT)?;
                 add_match(bus, signals::REPO_COMPAT_ERROR)?;
+                add_match(bus, signals::STALLED)?;
             }
 
             Ok(Client { bus })
         })
     }
 
+    /// Approves a pending download over a metered connection.
+    ///
+    /// To decline the download, and cancel the task, call `cancel` instead.
+    pub fn approve_metered_download(&self) -> Result<(), Error> {
+        self.call_method(methods::APPROVE_METERED_DOWNLOAD, |m| m)?;
+        Ok(())
+    }
+
+    /// Approves the pending package removals of a release upgrade, holding those to keep.
+    ///
+    /// To decline the removals, and cancel the upgrade, call `cancel` instead.
+    pub fn approve_removals(&self, keep: &[String]) -> Result<(), Error> {
+        self.call_method(methods::APPROVE_REMOVALS, |m| m.append1(keep))?;
+        Ok(())
+    }
+
     /// Cancel the active process which is in progress
     pub fn cancel(&self) -> Result<(), Error> {
         self.call_method(methods::CANCEL, |m| m)?;
         Ok(())
     }
 
+    /// Lists the new versions of configuration files which have yet to be reviewed.
+    pub fn conffiles(&self) -> Result<Vec<Conffile>, Error> {
+        self.call_method(methods::CONFFILES, |m| m)?
+            .read1::<Vec<(String, String, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::CONFFILES, why))
+            .map(|conffiles| conffiles.into_iter().map(Conffile::from_dbus).collect())
+    }
+
+    /// Accepts, keeps, or merges the new version of a configuration file.
+    pub fn conffile_resolve(&self, path: &str, resolution: Resolution) -> Result<(), Error> {
+        self.call_method(methods::CONFFILE_RESOLVE, |m| m.append2(path, resolution as u8))?;
+        Ok(())
+    }
+
     /// Dismiss future desktop notifications for the currently-available upgrade.
     pub fn dismiss_notification(&self, event: DismissEvent) -> Result<bool, Error> {
         self.call_method(methods::DISMISS_NOTIFICATION, |m| m.append1(event as u8))?
@@ -178,10 +288,12 @@ impl Client {
     ///
     /// By default, the system is updated once updates have been fetched. This
     /// can be disabled by setting the `download_only` argument to `false`.
+    /// The `selection` may limit which of the available updates are fetched.
     pub fn fetch_updates(
         &self,
         additional_packages: Vec<String>,
         download_only: bool,
+        selection: Selection,
     ) -> Result<Fetched, Error> {
         let packages = MessageItemArray::new(
             additional_packages.into_iter().map(MessageItem::from).collect(),
@@ -191,15 +303,21 @@ impl Client {
 
         let packages = MessageItem::Array(packages);
 
-        let cb = move |message: Message| message.append2(&packages, download_only);
+        let Selection { security_only, include, exclude } = selection;
+
+        let cb = move |message: Message| {
+            message.append3(&packages, download_only, security_only).append2(&include, &exclude)
+        };
 
         self.call_method(methods::FETCH_UPDATES, cb)?
-            .read3::<bool, u32, u32>()
+            .read5::<bool, u32, u32, u64, u32>()
             .map_err(|why| Error::ArgumentMismatch(methods::FETCH_UPDATES, why))
-            .map(|(updates_available, completed, total)| Fetched {
+            .map(|(updates_available, completed, total, bytes, deferred)| Fetched {
                 updates_available,
                 completed,
                 total,
+                bytes,
+                deferred,
             })
     }
 
@@ -211,12 +329,99 @@ impl Client {
             .map(|(status, why)| Status { status, why: why.into() })
     }
 
+    /// Verifies the health of the system, optionally checking that `release` is installed.
+    pub fn health_check(&self, release: &str) -> Result<HealthReport, Error> {
+        self.call_method(methods::HEALTH_CHECK, |m| m.append1(release))?
+            .read1::<Vec<(String, u8, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::HEALTH_CHECK, why))
+            .map(HealthReport::from_dbus)
+    }
+
+    /// Retrieves the last health report generated by the daemon.
+    pub fn health_status(&self) -> Result<HealthReport, Error> {
+        self.call_method(methods::HEALTH_STATUS, |m| m)?
+            .read1::<Vec<(String, u8, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::HEALTH_STATUS, why))
+            .map(HealthReport::from_dbus)
+    }
+
+    /// The transactions of apt and the jobs of the daemon since a time, in seconds since the
+    /// Unix epoch.
+    pub fn history(&self, since: i64) -> Result<Vec<HistoryEntry>, Error> {
+        self.call_method(methods::HISTORY, |m| m.append1(since))?
+            .read1::<Vec<DbusEntry>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::HISTORY, why))
+            .map(|entries| entries.into_iter().map(HistoryEntry::from_dbus).collect())
+    }
+
+    /// Kills the process of an apt or dpkg operation which has stalled, and configures the
+    /// packages that it interrupted.
+    pub fn kill_stalled(&self) -> Result<(), Error> {
+        self.call_method(methods::KILL_STALLED, |m| m)?;
+        Ok(())
+    }
+
+    /// Lists the updates that are pending, with their versions and changelogs.
+    pub fn list_updates(&self) -> Result<Vec<PendingUpdate>, Error> {
+        self.call_method(methods::LIST_UPDATES, |m| m)?
+            .read1::<Vec<(String, String, String, String, u64, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::LIST_UPDATES, why))
+            .map(|updates| updates.into_iter().map(PendingUpdate::from_dbus).collect())
+    }
+
+    /// Stages the fetched package updates to be installed at the next startup, returning the
+    /// number of packages that will be installed.
+    pub fn offline_update(&self) -> Result<u32, Error> {
+        self.call_method(methods::OFFLINE_UPDATE, |m| m)?
+            .read1::<u32>()
+            .map_err(|why| Error::ArgumentMismatch(methods::OFFLINE_UPDATE, why))
+    }
+
+    /// Unstages package updates which were to be installed at the next startup.
+    pub fn offline_update_cancel(&self) -> Result<(), Error> {
+        self.call_method(methods::OFFLINE_UPDATE_CANCEL, |m| m)?;
+        Ok(())
+    }
+
+    /// Whether package updates are staged for the next startup, and the result of the
+    /// package updates which were last installed at startup.
+    pub fn offline_update_status(&self) -> Result<(bool, Status), Error> {
+        self.call_method(methods::OFFLINE_UPDATE_STATUS, |m| m)?
+            .read3::<bool, u8, &str>()
+            .map_err(|why| Error::ArgumentMismatch(methods::OFFLINE_UPDATE_STATUS, why))
+            .map(|(pending, status, why)| (pending, Status { status, why: why.into() }))
+    }
+
     /// Initiates upgrading the system packages.
     pub fn package_upgrade(&self) -> Result<(), Error> {
         self.call_method(methods::PACKAGE_UPGRADE, |m| m)?;
         Ok(())
     }
 
+    /// Retrieves the last known status of a package upgrade.
+    pub fn package_upgrade_status(&self) -> Result<Status, Error> {
+        self.call_method(methods::PACKAGE_UPGRADE_STATUS, |m| m)?
+            .read2::<u8, &str>()
+            .map_err(|why| Error::ArgumentMismatch(methods::PACKAGE_UPGRADE_STATUS, why))
+            .map(|(status, why)| Status { status, why: why.into() })
+    }
+
+    /// Lists the package removals of a release upgrade which are awaiting approval.
+    pub fn pending_removals(&self) -> Result<Vec<String>, Error> {
+        self.call_method(methods::PENDING_REMOVALS, |m| m)?
+            .read1::<Vec<String>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::PENDING_REMOVALS, why))
+    }
+
+    /// Retrieves the overall progress of the daemon's current task.
+    pub fn progress(&self) -> Result<OverallProgress, Error> {
+        self.bus
+            .with_path(DBUS_NAME, DBUS_PATH, TIMEOUT)
+            .get::<(u8, String, i64)>(DBUS_IFACE, properties::PROGRESS)
+            .map(OverallProgress::from_dbus)
+            .map_err(|why| Error::Call(properties::PROGRESS, why))
+    }
+
     /// Initiates upgrading the recovery partition via a recovery image file.
     pub fn recovery_upgrade_file<P: AsRef<str>>(&self, path: P) -> Result<u8, Error> {
         self.call_method(methods::RECOVERY_UPGRADE_FILE, move |m| m.append1(path.as_ref()))?
@@ -302,12 +507,79 @@ impl Client {
         Ok(())
     }
 
+    /// Reinstalls packages which were removed by an upgrade.
+    pub fn reinstall_removed(&self, packages: &[String]) -> Result<(), Error> {
+        self.call_method(methods::REINSTALL_REMOVED, |m| m.append1(packages))?;
+        Ok(())
+    }
+
+    /// Packages which were removed by an upgrade, and can now be reinstalled.
+    pub fn removed_packages(&self) -> Result<Vec<RemovedPackage>, Error> {
+        self.call_method(methods::REMOVED_PACKAGES, |m| m)?
+            .read1::<Vec<(String, String, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::REMOVED_PACKAGES, why))
+            .map(|packages| {
+                packages
+                    .into_iter()
+                    .map(|(package, reason, candidate)| RemovedPackage {
+                        package:   package.into(),
+                        reason:    reason.into(),
+                        candidate: candidate.into(),
+                    })
+                    .collect()
+            })
+    }
+
+    /// Lists the repair checks known to the daemon.
+    pub fn repair_list(&self) -> Result<Vec<RepairCheckInfo>, Error> {
+        self.call_method(methods::REPAIR_LIST, |m| m)?
+            .read1::<Vec<(String, String, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::REPAIR_LIST, why))
+            .map(|checks| {
+                checks
+                    .into_iter()
+                    .map(|(id, stage, description)| RepairCheckInfo {
+                        id:          id.into(),
+                        stage:       stage.into(),
+                        description: description.into(),
+                    })
+                    .collect()
+            })
+    }
+
+    /// Applies the given repair checks, or every repair check if none are given.
+    ///
+    /// With `dry_run`, issues are reported without being fixed.
+    pub fn repair_run(&self, only: &[String], dry_run: bool) -> Result<Vec<RepairReport>, Error> {
+        self.call_method(methods::REPAIR_RUN, |m| m.append2(only, dry_run))?
+            .read1::<Vec<(String, Vec<String>, Vec<String>, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::REPAIR_RUN, why))
+            .map(|reports| reports.into_iter().map(RepairReport::from_dbus).collect())
+    }
+
     /// Reset the daemon to its initial state, and clean up any changes.
     pub fn reset(&self) -> Result<(), Error> {
         self.call_method(methods::RESET, |m| m)?;
         Ok(())
     }
 
+    /// The schedule that the daemon installs package updates on.
+    pub fn schedule(&self) -> Result<Schedule, Error> {
+        let schedule = self
+            .call_method(methods::GET_SCHEDULE, |m| m)?
+            .read4::<u8, u8, u16, u16>()
+            .map_err(|why| Error::ArgumentMismatch(methods::GET_SCHEDULE, why))?;
+
+        Schedule::from_dbus(schedule).map_err(Error::InvalidSchedule)
+    }
+
+    /// Replaces the schedule that the daemon installs package updates on.
+    pub fn set_schedule(&self, schedule: Schedule) -> Result<(), Error> {
+        let (when, days, start, end) = schedule.to_dbus();
+        self.call_method(methods::SET_SCHEDULE, |m| m.append2(when, days).append2(start, end))?;
+        Ok(())
+    }
+
     /// Retrieves the status of the daemon.
     pub fn status(&self) -> Result<DaemonStatus, Error> {
         self.call_method(methods::STATUS, |m| m)?
@@ -316,6 +588,14 @@ impl Client {
             .map(|(status, sub_status)| DaemonStatus { status, sub_status })
     }
 
+    /// Undoes the last package update of the daemon, returning the changes that are undone.
+    pub fn undo_update(&self) -> Result<Transaction, Error> {
+        self.call_method(methods::UNDO_UPDATE, |m| m)?
+            .read1::<Vec<(String, String, String)>>()
+            .map_err(|why| Error::ArgumentMismatch(methods::UNDO_UPDATE, why))
+            .map(Transaction::from_dbus)
+    }
+
     pub fn update_and_restart(&self) -> Result<bool, Error> {
         self.call_method(methods::UPDATE_CHECK, |m| m)?
             .read1::<u8>()
@@ -354,6 +634,10 @@ impl Client {
                 inactivity_count = 0;
             } else if let Some(signal) = filter_signal(item) {
                 let signal = match &*signal.member().unwrap() {
+                    signals::HEALTH_REPORT => signal
+                        .read1::<Vec<(String, u8, String)>>()
+                        .map(|checks| Signal::HealthReport(HealthReport::from_dbus(checks)))
+                        .map_err(|why| Error::ArgumentMismatch(signals::HEALTH_REPORT, why))?,
                     signals::NO_CONNECTION => Signal::NoConnection,
                     signals::PACKAGE_FETCH_RESULT => signal
                         .read2::<u8, String>()
@@ -362,15 +646,33 @@ impl Client {
                         .map_err(|why| {
                             Error::ArgumentMismatch(signals::PACKAGE_FETCH_RESULT, why)
                         })?,
-                    signals::PACKAGE_FETCHED => signal
-                        .read3::<String, u32, u32>()
-                        .map(|(package, completed, total)| FetchStatus {
+                    signals::PACKAGE_FETCHED => {
+                        let mut args = signal.iter_init();
+
+                        (|| {
+                            Ok(FetchStatus {
+                                package:       args.read::<String>()?.into(),
+                                completed:     args.read()?,
+                                total:         args.read()?,
+                                fetched_bytes: args.read()?,
+                                total_bytes:   args.read()?,
+                                rate:          args.read()?,
+                            })
+                        })()
+                        .map(Signal::PackageFetched)
+                        .map_err(|why| Error::ArgumentMismatch(signals::PACKAGE_FETCHED, why))?
+                    }
+                    signals::PACKAGE_FETCH_RETRYING => signal
+                        .read3::<String, u32, String>()
+                        .map(|(package, attempt, why)| FetchRetry {
                             package: package.into(),
-                            completed,
-                            total,
+                            attempt,
+                            why: why.into(),
                         })
-                        .map(Signal::PackageFetched)
-                        .map_err(|why| Error::ArgumentMismatch(signals::PACKAGE_FETCHED, why))?,
+                        .map(Signal::PackageFetchRetrying)
+                        .map_err(|why| {
+                            Error::ArgumentMismatch(signals::PACKAGE_FETCH_RETRYING, why)
+                        })?,
                     signals::PACKAGE_FETCHING => signal
                         .read1::<String>()
                         .map(|package| Signal::PackageFetching(Box::from(package)))
@@ -385,6 +687,25 @@ impl Client {
                                 .collect::<HashMap<Box<str>, Box<str>>>()
                         })
                         .map(Signal::PackageUpgrade)?,
+                    signals::PACKAGE_UPGRADE_RESULT => signal
+                        .read2::<u8, String>()
+                        .map(|(status, why)| Status { status, why: why.into() })
+                        .map(Signal::PackageUpgradeResult)
+                        .map_err(|why| {
+                            Error::ArgumentMismatch(signals::PACKAGE_UPGRADE_RESULT, why)
+                        })?,
+                    signals::METERED_DOWNLOAD => signal
+                        .read1::<u64>()
+                        .map(Signal::MeteredDownload)
+                        .map_err(|why| Error::ArgumentMismatch(signals::METERED_DOWNLOAD, why))?,
+                    signals::PENDING_REMOVALS => signal
+                        .read1::<Vec<String>>()
+                        .map(Signal::PendingRemovals)
+                        .map_err(|why| Error::ArgumentMismatch(signals::PENDING_REMOVALS, why))?,
+                    signals::PROGRESS => signal
+                        .read3::<u8, String, i64>()
+                        .map(|progress| Signal::Progress(OverallProgress::from_dbus(progress)))
+                        .map_err(|why| Error::ArgumentMismatch(signals::PROGRESS, why))?,
                     signals::RECOVERY_DOWNLOAD_PROGRESS => signal
                         .read2::<u64, u64>()
                         .map_err(|why| {
@@ -416,6 +737,17 @@ impl Client {
                         .map_err(|why| Error::ArgumentMismatch(signals::RELEASE_RESULT, why))
                         .map(|(status, why)| Status { status, why: why.into() })
                         .map(Signal::ReleaseResult)?,
+                    signals::REPAIR_REPORT => signal
+                        .read4::<String, Vec<String>, Vec<String>, String>()
+                        .map(|(id, found, changed, error)| {
+                            RepairReport::from_dbus((id, found, changed, error))
+                        })
+                        .map(Signal::RepairReport)
+                        .map_err(|why| Error::ArgumentMismatch(signals::REPAIR_REPORT, why))?,
+                    signals::STALLED => signal
+                        .read4::<String, String, u32, u64>()
+                        .map(|stall| Signal::Stalled(Stall::from_dbus(stall)))
+                        .map_err(|why| Error::ArgumentMismatch(signals::STALLED, why))?,
                     _ => {
                         inactivity_count = 0;
                         continue;
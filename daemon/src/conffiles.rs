@@ -0,0 +1,312 @@
+//! Configuration files that package upgrades left to be reviewed.
+//!
+//! Packages are upgraded with `--force-confdef --force-confold`, so configuration files which
+//! were modified keep their modifications, and the maintainer's new version is written beside
+//! them as a `.dpkg-dist`, `.dpkg-new`, or `.ucf-dist` file. Each new version may be:
+//!
+//! - accepted, replacing the active file with it;
+//! - kept, discarding it in favor of the active file;
+//! - or merged, applying the maintainer's changes to the active file with `diff3`, which
+//!   requires the package of the previous version to still be in the apt cache.
+//!
+//! The active file is saved as a `.dpkg-old` file before it is replaced. New versions written by
+//! ucf are registered with it once resolved, so that its record of the maintainer's version is
+//! current, and the file is not brought up again by the next upgrade.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use thiserror::Error;
+
+const CONFIG_DIR: &str = "/etc";
+
+const SUFFIXES: &[&str] = &[".dpkg-dist", ".dpkg-new", UCF_SUFFIX];
+
+const UCF_SUFFIX: &str = ".ucf-dist";
+
+const BACKUP_SUFFIX: &str = ".dpkg-old";
+
+const ARCHIVES: &str = "/var/cache/apt/archives";
+
+#[derive(Debug, Error)]
+pub enum ConffileError {
+    #[error("{} is not a configuration file awaiting review", _0)]
+    NotPending(String),
+
+    #[error("failed to save {} as {}{}", _0, _0, BACKUP_SUFFIX)]
+    Backup(String, #[source] io::Error),
+
+    #[error("failed to replace {}", _0)]
+    Replace(String, #[source] io::Error),
+
+    #[error("failed to remove {}", _0)]
+    Remove(String, #[source] io::Error),
+
+    #[error("the previous version of {} is not in the apt cache to merge with", _0)]
+    NoPrevious(String),
+
+    #[error("the changes to {} conflict, and must be merged by hand", _0)]
+    Conflict(String),
+
+    #[error("failed to merge {}", _0)]
+    Merge(String, #[source] io::Error),
+
+    #[error("failed to register the new version of {} with ucf", _0)]
+    Ucf(String, #[source] io::Error),
+}
+
+/// What to do with the maintainer's new version of a configuration file.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, FromPrimitive, PartialEq)]
+pub enum Resolution {
+    Accept = 0,
+    Keep = 1,
+    Merge = 2,
+}
+
+/// The maintainer's new version of a configuration file, and its changes to the active file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conffile {
+    pub path:   String,
+    pub active: String,
+    /// A unified diff from the active file to the new version.
+    pub diff:   String,
+}
+
+impl Conffile {
+    fn new(path: String, active: String) -> Self {
+        let output = Command::new("diff")
+            .arg("-u")
+            .arg(if Path::new(&active).exists() { &active } else { "/dev/null" })
+            .arg(&path)
+            .output();
+
+        let diff = output.map_or_else(
+            |why| format!("failed to spawn `diff`: {}", why),
+            |output| String::from_utf8_lossy(&output.stdout).into_owned(),
+        );
+
+        Self { path, active, diff }
+    }
+
+    /// Convert the file into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> (String, String, String) { (self.path, self.active, self.diff) }
+
+    /// Reconstruct a file that was transferred over DBus.
+    pub fn from_dbus((path, active, diff): (String, String, String)) -> Self {
+        Self { path, active, diff }
+    }
+}
+
+/// The path of the active file that a new version of a configuration file is for.
+pub fn active_path(path: &str) -> Option<&str> {
+    SUFFIXES.iter().find_map(|suffix| path.strip_suffix(suffix)).filter(|path| !path.is_empty())
+}
+
+/// Every new version of a configuration file which has yet to be reviewed.
+pub fn pending() -> Vec<Conffile> {
+    let mut paths = Vec::new();
+    find(Path::new(CONFIG_DIR), &mut paths);
+    paths.sort_unstable();
+
+    paths
+        .into_iter()
+        .filter_map(|path| path.into_os_string().into_string().ok())
+        .filter_map(|path| {
+            let active = active_path(&path)?.to_owned();
+            Some(Conffile::new(path, active))
+        })
+        .collect()
+}
+
+fn find(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            find(&path, paths);
+        } else if file_type.is_file()
+            && path.to_str().is_some_and(|path| active_path(path).is_some())
+        {
+            paths.push(path);
+        }
+    }
+}
+
+/// The path of the active file that a new version is for, if the new version is a file within
+/// the configuration directory which is awaiting review.
+fn pending_file(path: &str) -> Option<&str> {
+    let active = active_path(path)?;
+
+    let canonical = fs::canonicalize(path).ok()?;
+    if canonical != Path::new(path) || !canonical.starts_with(CONFIG_DIR) || !canonical.is_file() {
+        return None;
+    }
+
+    Some(active)
+}
+
+/// Resolves a new version of a configuration file, which must be awaiting review.
+pub fn resolve(path: &str, resolution: Resolution) -> Result<(), ConffileError> {
+    let active =
+        pending_file(path).ok_or_else(|| ConffileError::NotPending(path.to_owned()))?.to_owned();
+
+    let path = path.to_owned();
+
+    let register = || {
+        if path.ends_with(UCF_SUFFIX) {
+            ucf_register(&path, &active)
+        } else {
+            Ok(())
+        }
+    };
+
+    match resolution {
+        Resolution::Accept => {
+            register()?;
+            backup(&active)?;
+            fs::rename(&path, &active).map_err(|why| ConffileError::Replace(active, why))
+        }
+
+        Resolution::Keep => {
+            register()?;
+            fs::remove_file(&path).map_err(|why| ConffileError::Remove(path, why))
+        }
+
+        Resolution::Merge => {
+            let merged = merge(&path, &active)?;
+            register()?;
+            backup(&active)?;
+            fs::write(&active, merged).map_err(|why| ConffileError::Replace(active, why))?;
+            fs::remove_file(&path).map_err(|why| ConffileError::Remove(path, why))
+        }
+    }
+}
+
+/// Records the new version as the maintainer's version in the hashfile of ucf, while keeping
+/// the active file as it is.
+fn ucf_register(path: &str, active: &str) -> Result<(), ConffileError> {
+    let ucf_error = |why| ConffileError::Ucf(active.to_owned(), why);
+
+    // ucf writes the new version beside the active file, so it must be given a copy of it.
+    let new = tempfile::NamedTempFile::new().map_err(ucf_error)?;
+    fs::copy(path, new.path()).map_err(ucf_error)?;
+
+    let output = Command::new("ucf")
+        .env("UCF_FORCE_CONFFOLD", "1")
+        .env("DEBIAN_FRONTEND", "noninteractive")
+        .arg(new.path())
+        .arg(active)
+        .output()
+        .map_err(ucf_error)?;
+
+    if !output.status.success() {
+        let why = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(ucf_error(io::Error::other(why)));
+    }
+
+    Ok(())
+}
+
+fn backup(active: &str) -> Result<(), ConffileError> {
+    if !Path::new(active).exists() {
+        return Ok(());
+    }
+
+    fs::copy(active, [active, BACKUP_SUFFIX].concat())
+        .map(|_| ())
+        .map_err(|why| ConffileError::Backup(active.to_owned(), why))
+}
+
+/// Applies the changes between the previous and new versions of the maintainer to the active
+/// file, returning the merged file if none of the changes conflict.
+fn merge(path: &str, active: &str) -> Result<Vec<u8>, ConffileError> {
+    let previous =
+        previous_version(active).ok_or_else(|| ConffileError::NoPrevious(active.into()))?;
+
+    let merge_error = |why| ConffileError::Merge(active.to_owned(), why);
+
+    let base = tempfile::NamedTempFile::new().map_err(merge_error)?;
+    fs::write(base.path(), previous).map_err(merge_error)?;
+
+    let output =
+        Command::new("diff3").arg("-m").arg(active).arg(base.path()).arg(path).output();
+
+    match output.map_err(merge_error)? {
+        output if output.status.success() => Ok(output.stdout),
+        output if output.status.code() == Some(1) => Err(ConffileError::Conflict(active.into())),
+        output => Err(merge_error(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))),
+    }
+}
+
+/// The previous version of a configuration file of the maintainer, read from the package of
+/// the newest version before the installed one that is still in the apt cache.
+fn previous_version(active: &str) -> Option<Vec<u8>> {
+    let owner = Command::new("dpkg-query").args(["-S", active]).output().ok()?;
+    let owner = String::from_utf8_lossy(&owner.stdout);
+    let package = owner.split_once(": ")?.0.split([',', ':']).next()?.trim().to_owned();
+
+    let installed = Command::new("dpkg-query")
+        .args(["-W", "-f=${Version}", &package])
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())?;
+
+    let prefix = [&package, "_"].concat();
+
+    let deb = fs::read_dir(ARCHIVES)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| {
+            let version = name.strip_prefix(&prefix)?.strip_suffix(".deb")?.rsplit_once('_')?.0;
+            Some((version.replace("%3a", ":"), name))
+        })
+        .filter(|(version, _)| version_lt(version, &installed))
+        .reduce(|newest, next| if version_lt(&newest.0, &next.0) { next } else { newest })?
+        .1;
+
+    let deb = Path::new(ARCHIVES).join(deb);
+    let output = Command::new("sh")
+        .args(["-c", "dpkg-deb --fsys-tarfile \"$0\" | tar -xOf - \".$1\""])
+        .arg(&deb)
+        .arg(active)
+        .output()
+        .ok()?;
+
+    (output.status.success() && !output.stdout.is_empty()).then_some(output.stdout)
+}
+
+fn version_lt(a: &str, b: &str) -> bool {
+    Command::new("dpkg")
+        .args(["--compare-versions", a, "lt", b])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_versions() {
+        assert_eq!(active_path("/etc/ssh/sshd_config.ucf-dist"), Some("/etc/ssh/sshd_config"));
+        assert_eq!(active_path("/etc/sudoers.dpkg-dist"), Some("/etc/sudoers"));
+        assert_eq!(active_path("/etc/default/grub.dpkg-new"), Some("/etc/default/grub"));
+        assert_eq!(active_path("/etc/sudoers.dpkg-old"), None);
+        assert_eq!(active_path("/etc/sudoers"), None);
+        assert_eq!(active_path(".dpkg-dist"), None);
+    }
+}
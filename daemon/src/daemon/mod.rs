@@ -9,11 +9,26 @@ pub mod methods {
         Unset = 3,
     }
 
+    pub const APPROVE_METERED_DOWNLOAD: &str = "ApproveMeteredDownload";
+    pub const APPROVE_REMOVALS: &str = "ApproveRemovals";
     pub const CANCEL: &str = "Cancel";
+    pub const CONFFILES: &str = "Conffiles";
+    pub const CONFFILE_RESOLVE: &str = "ConffileResolve";
     pub const DISMISS_NOTIFICATION: &str = "DismissNotification";
     pub const FETCH_UPDATES: &str = "FetchUpdates";
     pub const FETCH_UPDATES_STATUS: &str = "FetchUpdatesStatus";
+    pub const GET_SCHEDULE: &str = "GetSchedule";
+    pub const HEALTH_CHECK: &str = "HealthCheck";
+    pub const HEALTH_STATUS: &str = "HealthStatus";
+    pub const HISTORY: &str = "History";
+    pub const KILL_STALLED: &str = "KillStalled";
+    pub const LIST_UPDATES: &str = "ListUpdates";
+    pub const OFFLINE_UPDATE: &str = "OfflineUpdate";
+    pub const OFFLINE_UPDATE_CANCEL: &str = "OfflineUpdateCancel";
+    pub const OFFLINE_UPDATE_STATUS: &str = "OfflineUpdateStatus";
     pub const PACKAGE_UPGRADE: &str = "UpgradePackages";
+    pub const PACKAGE_UPGRADE_STATUS: &str = "PackageUpgradeStatus";
+    pub const PENDING_REMOVALS: &str = "PendingRemovals";
     pub const RECOVERY_UPGRADE_FILE: &str = "RecoveryUpgradeFile";
     pub const RECOVERY_UPGRADE_RELEASE: &str = "RecoveryUpgradeRelease";
     pub const RECOVERY_UPGRADE_RELEASE_STATUS: &str = "RecoveryUpgradeReleaseStatus";
@@ -24,11 +39,21 @@ pub mod methods {
     pub const RELEASE_UPGRADE_FINALIZE: &str = "ReleaseUpgradeFinalize";
     pub const RELEASE_UPGRADE_STATUS: &str = "ReleaseUpgradeStatus";
     pub const RELEASE_REPAIR: &str = "ReleaseRepair";
+    pub const REINSTALL_REMOVED: &str = "ReinstallRemoved";
+    pub const REMOVED_PACKAGES: &str = "RemovedPackages";
+    pub const REPAIR_LIST: &str = "RepairList";
+    pub const REPAIR_RUN: &str = "RepairRun";
     pub const RESET: &str = "Reset";
+    pub const SET_SCHEDULE: &str = "SetSchedule";
     pub const STATUS: &str = "Status";
+    pub const UNDO_UPDATE: &str = "UndoUpdate";
     pub const UPDATE_CHECK: &str = "UpdateCheck";
 }
 
+pub mod properties {
+    pub const PROGRESS: &str = "Progress";
+}
+
 mod error;
 mod status;
 
@@ -37,21 +62,33 @@ pub use self::{
 };
 
 use crate::{
+    conffiles::{self, Conffile, Resolution},
+    health::{self, HealthReport},
+    history::{self, DbusEntry, HistoryEntry},
     misc::{self, format_error},
+    network::{self, Decision, MeteredError, NetworkConfig, NetworkManager},
+    predownload,
+    progress::{Plan, Stage, Tracker},
     recovery::{
         self, RecoveryError, RecoveryVersion, RecoveryVersionError,
         ReleaseFlags as RecoveryReleaseFlags, UpgradeMethod as RecoveryUpgradeMethod,
     },
     release::{
-        self, FetchEvent, RefreshOp, ReleaseError, ReleaseStatus,
+        self, FetchEvent, FetchProgress, RefreshOp, ReleaseError, ReleaseStatus, UpgradeEvent,
         UpgradeMethod as ReleaseUpgradeMethod,
     },
-    sighandler, DBUS_IFACE, DBUS_NAME, DBUS_PATH, RESTART_SCHEDULED,
+    repair::{self, RepairError, RepairReport},
+    schedule::{self, Attempt, Schedule, ScheduleError, Scheduler, When},
+    sighandler,
+    transaction::Transaction,
+    updates::{self, PendingUpdate, PhasedUpdates, Selection, UpdatesConfig, Upgrade},
+    watchdog::{self, Stall},
+    DBUS_IFACE, DBUS_NAME, DBUS_PATH, RESTART_SCHEDULED,
 };
 use async_shutdown::ShutdownManager as Shutdown;
 
 use anyhow::Context as AnyhowContext;
-use apt_cmd::{request::Request as AptRequest, AptCache, AptGet, AptMark};
+use apt_cmd::{request::Request as AptRequest, AptCache, AptGet, AptMark, AptUpgradeEvent};
 use as_result::MapResult;
 use atomic::Atomic;
 use dbus::{
@@ -68,37 +105,63 @@ use std::{
     fs,
     path::PathBuf,
     sync::{
-        atomic::{AtomicBool, AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicI64, AtomicU8, Ordering},
         Arc,
     },
+    time::Instant,
 };
 use tokio::{
     runtime::Handle,
     sync::{
         mpsc::{self, UnboundedReceiver, UnboundedSender},
-        Mutex,
+        oneshot, Mutex,
     },
 };
 
 pub const DISMISSED: &str = "/usr/lib/pop-upgrade/dismissed";
 pub const INSTALL_DATE: &str = "/usr/lib/pop-upgrade/install_date";
 
+/// How long a request for approval waits for an answer before it is declined.
+const APPROVAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
 #[derive(Debug)]
 pub enum Event {
-    FetchUpdates { apt_uris: HashSet<AptRequest>, download_only: bool },
+    /// Fetches, and unless `download_only`, installs updates, which may be limited to `only`
+    /// the given packages.
+    FetchUpdates {
+        apt_uris:      HashSet<AptRequest>,
+        download_only: bool,
+        only:          Option<Vec<String>>,
+        phased:        PhasedUpdates,
+        /// The updates that are expected to be installed, recorded so that they may be undone.
+        upgrades:      Vec<Upgrade>,
+    },
     PackageUpgrade,
+    /// Fetches updates in the background, so that they are ready to be installed.
+    Predownload,
     RecoveryUpgrade(RecoveryUpgradeMethod),
     ReleaseUpgrade { how: ReleaseUpgradeMethod, from: String, to: String, await_recovery: bool },
+    /// Installs the versions of packages from before their last update.
+    UndoUpdate(Transaction),
 }
 
 #[derive(Debug)]
 pub enum FgEvent {
+    /// Sends the reply of a method call which was answered away from the DBus loop.
+    Reply(Context),
+    /// The report of a health check which was run away from the DBus loop.
+    Health(HealthReport),
+    /// Updates were fetched in the background, if `true`.
+    Predownloaded(bool),
     SetUpgradeState(Result<(), ReleaseError>, ReleaseUpgradeMethod, Box<str>, Box<str>),
 }
 
 pub struct LastKnown {
     development: bool,
     fetch: Result<(), ReleaseError>,
+    health: HealthReport,
+    offline_update: Result<(), ReleaseError>,
+    package_upgrade: Result<(), ReleaseError>,
     recovery_upgrade: Result<(), RecoveryError>,
     release_upgrade: Result<(), ReleaseError>,
 }
@@ -108,6 +171,9 @@ impl Default for LastKnown {
         Self {
             development: false,
             fetch: Ok(()),
+            health: HealthReport::default(),
+            offline_update: Ok(()),
+            package_upgrade: Ok(()),
             recovery_upgrade: Ok(()),
             release_upgrade: Ok(()),
         }
@@ -148,6 +214,23 @@ struct SharedState {
     force_next: AtomicBool,
     // Indicates that it is now uncancellable
     release_upgrade_began: AtomicBool,
+    // Package removals of a release upgrade which are awaiting approval.
+    pending_removals: std::sync::Mutex<Option<PendingRemovals>>,
+    // A download over a metered connection which is awaiting approval.
+    pending_metered: std::sync::Mutex<Option<oneshot::Sender<()>>>,
+    // The overall progress of the current task.
+    progress: std::sync::Mutex<Tracker>,
+    // The bytes fetched by the current fetch of packages.
+    fetch_bytes: std::sync::Mutex<FetchProgress>,
+    // When apt last wrote any output, in seconds since the Unix epoch.
+    apt_activity: AtomicI64,
+    // An apt or dpkg operation which has stopped making progress.
+    stall: std::sync::Mutex<Option<Stall>>,
+}
+
+struct PendingRemovals {
+    packages: Vec<String>,
+    approve:  oneshot::Sender<Vec<String>>,
 }
 
 enum ReleaseCheck {
@@ -158,11 +241,19 @@ enum ReleaseCheck {
 
 pub struct Daemon {
     event_tx: UnboundedSender<Event>,
+    fg_tx: UnboundedSender<FgEvent>,
     last_known: LastKnown,
     perform_upgrade: bool,
     release_check: ReleaseCheck,
     release_upgrade: Option<ReleaseUpgradeState>,
+    scheduler: Scheduler,
     shared_state: Arc<SharedState>,
+    // Prevents shutdowns while the daemon installs updates by itself.
+    install_inhibitor: Option<dbus::arg::OwnedFd>,
+    // Delays shutdowns while updates are scheduled to be staged on shutdown.
+    shutdown_inhibitor: Option<dbus::arg::OwnedFd>,
+    // The inhibitor is released once the daemon is inactive, to let the system shut down.
+    shutting_down: bool,
 }
 
 impl Daemon {
@@ -186,14 +277,22 @@ impl Daemon {
             shutdown: Mutex::new(Shutdown::new()),
             force_next: AtomicBool::new(false),
             release_upgrade_began: AtomicBool::new(false),
+            pending_removals: std::sync::Mutex::new(None),
+            pending_metered: std::sync::Mutex::new(None),
+            progress: std::sync::Mutex::new(Tracker::default()),
+            fetch_bytes: std::sync::Mutex::new(FetchProgress::default()),
+            apt_activity: AtomicI64::new(0),
+            stall: std::sync::Mutex::new(None),
         });
 
         let handle = Handle::current();
 
+        handle.spawn(watch_for_stalls(shared_state.clone(), dbus_tx.clone()));
+
         // Tracks if a successful recovery upgrade was performed.
         let mut recovery_upgraded = false;
 
-        let task = enclose!((handle, shared_state) move || {
+        let task = enclose!((handle, shared_state, fg_tx) move || {
             let main_future = async move {
                 let mut logind = match LoginManager::new() {
                     Ok(logind) => Some(logind),
@@ -210,10 +309,13 @@ impl Daemon {
                             let (current, npackages) = (fetch_state.progress, fetch_state.total);
                             shared_state.fetching_state.store(FetchState::new(current + 1, npackages), Ordering::SeqCst);
 
+                            let bytes = *shared_state.fetch_bytes.lock().unwrap();
+
                             let _ = dbus_tx.send(SignalEvent::Fetched(
                                 uri.name,
                                 current as u32 + 1,
                                 npackages as u32,
+                                bytes,
                             ));
                         }
                         FetchEvent::Fetching(uri) => {
@@ -222,7 +324,13 @@ impl Daemon {
                         FetchEvent::Init(total) => {
                             shared_state.fetching_state.store(FetchState::new(0, total as u64), Ordering::SeqCst);
                         }
-                        FetchEvent::Retrying(_uri) => (),
+                        FetchEvent::Progress(bytes) => {
+                            *shared_state.fetch_bytes.lock().unwrap() = bytes;
+                            track_progress(&shared_state, &dbus_tx, |progress| progress.fetched(bytes.fetched, bytes.total));
+                        }
+                        FetchEvent::Retrying { request, attempt, why } => {
+                            let _ = dbus_tx.send(SignalEvent::FetchRetrying(request.name, u32::from(attempt), why));
+                        }
                     }
                 });
 
@@ -249,14 +357,21 @@ impl Daemon {
 
                     let _shutdown = shutdown.delay_shutdown_token();
 
+                    let started = chrono::Local::now().timestamp();
+
                     match event {
-                        Event::FetchUpdates { apt_uris, download_only } => {
+                        Event::FetchUpdates { apt_uris, download_only, only, phased, upgrades } => {
                             info!("fetching packages for {:?}", apt_uris);
+                            track_progress(&shared_state, &dbus_tx, |progress| progress.start(Plan::Update { download_only }));
 
                             let npackages = apt_uris.len() as u32;
                             shared_state.fetching_state.store(FetchState::new(0, u64::from(npackages)), Ordering::SeqCst);
 
-                            let result = crate::release::apt_fetch(shutdown.clone(), apt_uris, &fetch_closure).await;
+                            let bytes = apt_uris.iter().map(|request| request.size).sum();
+                            let result = match metered_approval(&shared_state, &dbus_tx, Some(bytes)).await {
+                                Ok(()) => crate::release::apt_fetch(shutdown.clone(), apt_uris, &fetch_closure).await,
+                                Err(why) => Err(ReleaseError::Metered(why)),
+                            };
 
                             shared_state.fetching_state.store(FetchState::new(0, 0), Ordering::SeqCst);
 
@@ -267,35 +382,65 @@ impl Daemon {
                                     } else {
                                         let perform_upgrade = || async {
                                             info!("performing upgrade");
+                                            track_progress(&shared_state, &dbus_tx, |progress| progress.enter(Stage::UpgradingCurrent));
+
+                                            let apt_get = phased.apply(misc::apt_get());
+                                            let upgrade = match only {
+                                                Some(ref packages) => misc::stream_only_upgrade(apt_get, packages).await,
+                                                None => apt_get.stream_upgrade().await,
+                                            };
 
-                                            let (mut child, events) = crate::misc::apt_get()
-                                                .stream_upgrade()
-                                                .await
-                                                .map_err(ReleaseError::Upgrade)?;
+                                            let (mut child, events) = upgrade.map_err(ReleaseError::Upgrade)?;
 
                                             futures_util::pin_mut!(events);
 
                                             while let Some(event) = events.next().await {
+                                                track_apt_progress(&shared_state, &dbus_tx, &event);
                                                 let _ = dbus_tx.send(SignalEvent::Upgrade(event));
                                             }
 
                                             child.wait().await.map_result().map_err(ReleaseError::Upgrade)
                                         };
 
-                                        if perform_upgrade().await.is_err() {
+                                        let transaction = Transaction::begin(&upgrades);
+
+                                        let result = if perform_upgrade().await.is_err() {
                                             info!("attempting to repair a packaging error");
-                                            match crate::repair::repair().await {
+                                            let report = |report: &RepairReport| {
+                                                let _ = dbus_tx.send(SignalEvent::RepairReport(report.clone()));
+                                            };
+
+                                            match crate::repair::repair(&report).await {
                                                 Ok(()) => perform_upgrade().await,
                                                 Err(why) => Err(ReleaseError::Repair(why))
                                             }
                                         } else {
                                             Ok(())
+                                        };
+
+                                        if result.is_ok() {
+                                            if let Err(why) = transaction.commit() {
+                                                warn!("the update cannot be undone: {}", format_error(&why));
+                                            }
                                         }
+
+                                        result
                                     }
                                 }
                                 Err(why) => Err(why)
                             };
 
+                            if result.is_ok() {
+                                track_progress(&shared_state, &dbus_tx, Tracker::complete);
+                            }
+
+                            if result.is_ok() && !download_only {
+                                let _ = dbus_tx.send(SignalEvent::HealthReport(health::verify(None)));
+                            }
+
+                            let job = if download_only { "fetched updates" } else { "installed updates" };
+                            history::record_job(started, job.into(), result.as_ref().err().map(|why| format_error(why)));
+
                             info!("submitting package fetch result: {:?}", result);
                             let _ = dbus_tx.send(SignalEvent::FetchResult(result));
 
@@ -304,24 +449,122 @@ impl Daemon {
 
                         Event::PackageUpgrade => {
                             info!("upgrading packages");
-                            let _ = crate::release::package_upgrade(|event| {
-                                let _ = dbus_tx.send(SignalEvent::Upgrade(event));
-                            });
+                            track_progress(&shared_state, &dbus_tx, |progress| progress.start(Plan::PackageUpgrade));
+
+                            let phased = UpdatesConfig::load().phased_updates;
+
+                            // The changes are read from apt's history once the upgrade is complete.
+                            let transaction = Transaction::begin(&[]);
+
+                            let result = match updates::excluding_config(phased).await {
+                                Ok(only) => crate::release::package_upgrade(only.as_deref(), phased, |event| {
+                                    track_apt_progress(&shared_state, &dbus_tx, &event);
+                                    let _ = dbus_tx.send(SignalEvent::Upgrade(event));
+                                }).await,
+                                Err(why) => Err(ReleaseError::AptList(why)),
+                            };
+
+                            if result.is_ok() {
+                                if let Err(why) = transaction.commit() {
+                                    warn!("the upgrade cannot be undone: {}", format_error(&why));
+                                }
+
+                                track_progress(&shared_state, &dbus_tx, Tracker::complete);
+                                let _ = dbus_tx.send(SignalEvent::HealthReport(health::verify(None)));
+                            }
+
+                            let job = "upgraded packages of the release".into();
+                            history::record_job(started, job, result.as_ref().err().map(|why| format_error(why)));
+
+                            info!("submitting package upgrade result: {:?}", result);
+                            let _ = dbus_tx.send(SignalEvent::PackageUpgradeResult(result));
 
                             info!("packages upgraded");
                         }
 
+                        Event::Predownload => {
+                            // Nothing is installed, so packaging is not repaired before fetching.
+                            let fetch = async {
+                                let phased = UpdatesConfig::load().phased_updates;
+                                let only = updates::excluding_config(phased).await.map_err(ReleaseError::AptList)?;
+
+                                let apt_uris = match only {
+                                    Some(packages) => crate::fetch::apt::fetch_only_upgrade_uris(shutdown.clone(), packages, Vec::new(), phased).await,
+                                    None => crate::fetch::apt::fetch_uris(shutdown.clone(), None, true, phased).await,
+                                };
+
+                                let apt_uris = apt_uris.map_err(ReleaseError::AptList)?;
+
+                                if apt_uris.is_empty() {
+                                    return Ok(());
+                                }
+
+                                crate::release::apt_fetch(shutdown.clone(), apt_uris, &fetch_closure).await
+                            };
+
+                            let result = fetch.await;
+
+                            if let Err(ref why) = result {
+                                error!("failed to fetch updates in the background: {}", format_error(why));
+                            }
+
+                            let job = "fetched updates in the background".into();
+                            history::record_job(started, job, result.as_ref().err().map(|why| format_error(why)));
+
+                            let _ = fg_tx.send(FgEvent::Predownloaded(result.is_ok()));
+                        }
+
+                        Event::UndoUpdate(transaction) => {
+                            info!("undoing the last package update");
+                            track_progress(&shared_state, &dbus_tx, |progress| progress.start(Plan::PackageUpgrade));
+
+                            let undo = async {
+                                let (mut child, events) = misc::stream_install(misc::apt_get(), &transaction.undo_args())
+                                    .await
+                                    .map_err(ReleaseError::Undo)?;
+
+                                futures_util::pin_mut!(events);
+
+                                while let Some(event) = events.next().await {
+                                    track_apt_progress(&shared_state, &dbus_tx, &event);
+                                    let _ = dbus_tx.send(SignalEvent::Upgrade(event));
+                                }
+
+                                child.wait().await.map_result().map_err(ReleaseError::Undo)
+                            };
+
+                            let result = undo.await;
+
+                            if result.is_ok() {
+                                Transaction::remove();
+                                track_progress(&shared_state, &dbus_tx, Tracker::complete);
+                                let _ = dbus_tx.send(SignalEvent::HealthReport(health::verify(None)));
+                            }
+
+                            let job = "undid the last update".into();
+                            history::record_job(started, job, result.as_ref().err().map(|why| format_error(why)));
+
+                            info!("submitting undo result: {:?}", result);
+                            let _ = dbus_tx.send(SignalEvent::PackageUpgradeResult(result));
+                        }
+
                         Event::RecoveryUpgrade(action) => {
                             info!("attempting recovery upgrade with {:?}", action);
 
-                            let result = recovery::recovery(
-                                shutdown.clone(),
-                                &action,
-                                dbus_tx.clone(),
-                            ).await;
+                            let result = match metered_approval(&shared_state, &dbus_tx, None).await {
+                                Ok(()) => recovery::recovery(
+                                    shutdown.clone(),
+                                    &action,
+                                    dbus_tx.clone(),
+                                ).await,
+                                Err(why) => Err(RecoveryError::Metered(why)),
+                            };
 
                             recovery_upgraded = result.is_ok();
 
+                            let job = "upgraded the recovery partition".into();
+                            history::record_job(started, job, result.as_ref().err().map(|why| format_error(why)));
+
                             let _ = dbus_tx.send(SignalEvent::RecoveryUpgradeResult(result));
 
                             info!("recovery partition upgraded");
@@ -336,12 +579,30 @@ impl Daemon {
                                 continue
                             }
 
+                            if let Err(why) = metered_approval(&shared_state, &dbus_tx, None).await {
+                                info!("release upgrade not started: {}", why);
+                                let _ = fg_tx.send(FgEvent::SetUpgradeState(Err(ReleaseError::Metered(why)), how, from.into(), to.into()));
+                                shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+                                continue
+                            }
+
                             info!(
                                 "attempting release upgrade, using a {}",
                                 <&'static str>::from(how)
                             );
 
+                            track_progress(&shared_state, &dbus_tx, |progress| progress.start(Plan::ReleaseUpgrade));
+
                             let progress = enclose!((dbus_tx, shared_state) move |event| {
+                                track_progress(&shared_state, &dbus_tx, |progress| match event {
+                                    UpgradeEvent::Success | UpgradeEvent::SuccessLive => progress.complete(),
+                                    event => {
+                                        if let Some(stage) = Stage::of(event) {
+                                            progress.enter(stage);
+                                        }
+                                    }
+                                });
+
                                 let _ = dbus_tx.send(SignalEvent::ReleaseUpgradeEvent(event));
                                 shared_state.sub_status.store(event as u8, Ordering::SeqCst);
                             });
@@ -355,12 +616,38 @@ impl Daemon {
                                 &progress,
                                 &fetch_closure,
                                 &|event| {
+                                    track_apt_progress(&shared_state, &dbus_tx, &event);
                                     let _ = dbus_tx.send(SignalEvent::Upgrade(event));
                                 },
+                                &|report| {
+                                    let _ = dbus_tx.send(SignalEvent::RepairReport(report.clone()));
+                                },
+                                &|packages| {
+                                    let (approve, approval) = oneshot::channel();
+                                    *shared_state.pending_removals.lock().unwrap() =
+                                        Some(PendingRemovals { packages: packages.clone(), approve });
+                                    let _ = dbus_tx.send(SignalEvent::PendingRemovals(packages));
+                                    let approval = tokio::time::timeout(APPROVAL_TIMEOUT, approval);
+                                    async move {
+                                        match approval.await {
+                                            Ok(answer) => answer.ok(),
+                                            Err(_) => {
+                                                info!("package removals were not approved in time");
+                                                None
+                                            }
+                                        }
+                                    }
+                                    .boxed()
+                                },
                             ).await;
 
+                            shared_state.pending_removals.lock().unwrap().take();
+
                             info!("upgrade result: {:?}", result);
 
+                            let job = format!("upgraded the release from {} to {}", from, to);
+                            history::record_job(started, job, result.as_ref().err().map(|why| format_error(why)));
+
                             let _ = AptMark::new().unhold(&["pop-upgrade"]).await;
 
                             info!("setting upgrade state");
@@ -386,11 +673,19 @@ impl Daemon {
         Ok((
             Daemon {
                 event_tx,
+                fg_tx,
+                install_inhibitor: None,
                 last_known: Default::default(),
                 release_upgrade: None,
                 perform_upgrade: false,
                 release_check: ReleaseCheck::NotChecked,
+                scheduler: Scheduler::new(Schedule::load().unwrap_or_else(|why| {
+                    error!("{}", format_error(&why));
+                    Schedule::default()
+                })),
                 shared_state,
+                shutdown_inhibitor: None,
+                shutting_down: false,
             },
             fg_rx,
             dbus_rx,
@@ -408,7 +703,37 @@ impl Daemon {
             warn!("failure restoring previous boot entry: {}", why);
         }
 
-        let (daemon, mut fg_receiver, mut receiver) = Self::new()?;
+        let (mut daemon, mut fg_receiver, mut receiver) = Self::new()?;
+        daemon.inhibit_shutdown();
+
+        // Verify the release which was installed by an offline upgrade on the last boot.
+        if !release::upgrade_in_progress() {
+            if let Some(target) = health::take_scheduled_verification() {
+                info!("verifying the system after the upgrade to {}", target);
+                daemon.last_known.health = health::verify(Some(&target));
+            }
+        }
+
+        // Keep the result of package updates which were installed at startup.
+        if let Some(result) = release::systemd::take_offline_update_report() {
+            info!("package updates installed at startup: {:?}", result);
+
+            let job = "installed updates at startup".into();
+            let error = result.as_ref().err().map(|why| format_error(why));
+            history::record_job(chrono::Local::now().timestamp(), job, error);
+
+            match Transaction::take_staged() {
+                Ok(transaction) if result.is_ok() => {
+                    if let Err(why) = transaction.commit() {
+                        warn!("the update cannot be undone: {}", format_error(&why));
+                    }
+                }
+                Ok(_) => (),
+                Err(why) => warn!("the update cannot be undone: {}", format_error(&why)),
+            }
+
+            daemon.last_known.offline_update = result;
+        }
 
         let connection = Connection::new_system().map_err(DaemonError::PrivateConnection)?;
 
@@ -422,12 +747,33 @@ impl Daemon {
             let _fetch_result =
                 b.signal::<(u8, String), _>(signals::PACKAGE_FETCH_RESULT, ("status", "why"));
 
+            let _progress = b.signal::<(u8, String, i64), _>(
+                signals::PROGRESS,
+                ("percent", "stage", "eta"),
+            );
+
+            b.property::<(u8, String, i64), _>(properties::PROGRESS).get(
+                |_ctx, daemon: &mut Daemon| {
+                    Ok(daemon.shared_state.progress.lock().unwrap().snapshot().into_dbus())
+                },
+            );
+
+            let _package_upgrade_result = b.signal::<(u8, String), _>(
+                signals::PACKAGE_UPGRADE_RESULT,
+                ("status", "why"),
+            );
+
             let _fetching_package =
                 b.signal::<(String,), _>(signals::PACKAGE_FETCHING, ("package",));
 
-            let _fetched_package = b.signal::<(String, u32, u32), _>(
+            let _fetch_retrying = b.signal::<(String, u32, String), _>(
+                signals::PACKAGE_FETCH_RETRYING,
+                ("package", "attempt", "why"),
+            );
+
+            let _fetched_package = b.signal::<(String, u32, u32, u64, u64, u64), _>(
                 signals::PACKAGE_FETCHED,
-                ("package", "completed", "total"),
+                ("package", "completed", "total", "fetched_bytes", "total_bytes", "rate"),
             );
 
             let _no_connection = b.signal::<(), _>(signals::NO_CONNECTION, ());
@@ -453,6 +799,43 @@ impl Daemon {
             let _upgrade_event =
                 b.signal::<(HashMap<String, String>,), _>(signals::PACKAGE_UPGRADE, ("event",));
 
+            let _health_report = b
+                .signal::<(Vec<(String, u8, String)>,), _>(signals::HEALTH_REPORT, ("checks",));
+
+            let _repair_report = b.signal::<(String, Vec<String>, Vec<String>, String), _>(
+                signals::REPAIR_REPORT,
+                ("id", "found", "changed", "error"),
+            );
+
+            let _pending_removals =
+                b.signal::<(Vec<String>,), _>(signals::PENDING_REMOVALS, ("packages",));
+
+            let _metered_download =
+                b.signal::<(u64,), _>(signals::METERED_DOWNLOAD, ("bytes",));
+
+            let _stalled = b.signal::<(String, String, u32, u64), _>(
+                signals::STALLED,
+                ("package", "script", "pid", "idle"),
+            );
+
+            b.method(
+                methods::APPROVE_METERED_DOWNLOAD,
+                (),
+                (),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    daemon.approve_metered_download().map_err(|why| MethodErr::failed(&why))
+                },
+            );
+
+            b.method(
+                methods::APPROVE_REMOVALS,
+                ("keep",),
+                (),
+                |_ctx: &mut Context, daemon: &mut Daemon, (keep,): (Vec<String>,)| {
+                    daemon.approve_removals(keep).map_err(|why| MethodErr::failed(&why))
+                },
+            );
+
             b.method(
                 methods::CANCEL,
                 (),
@@ -463,6 +846,61 @@ impl Daemon {
                 },
             );
 
+            b.method_with_cr_custom::<(), (Vec<(String, String, String)>,), _, _>(
+                methods::CONFFILES,
+                (),
+                ("conffiles",),
+                |mut ctx: Context, cr: &mut Crossroads, _inputs: ()| {
+                    if let Err(why) = require_root(&ctx) {
+                        ctx.reply::<()>(Err(why));
+                        return Some(ctx);
+                    }
+
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+
+                    daemon.reply_later(ctx, || {
+                        let conffiles = conffiles::pending();
+                        Ok((conffiles.into_iter().map(Conffile::into_dbus).collect::<Vec<_>>(),))
+                    })
+                },
+            );
+
+            b.method_with_cr_custom::<(String, u8), (), _, _>(
+                methods::CONFFILE_RESOLVE,
+                ("path", "resolution"),
+                (),
+                |mut ctx: Context, cr: &mut Crossroads, (path, resolution): (String, u8)| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+
+                    let checked = require_root(&ctx).and_then(|()| {
+                        if daemon.shared_state.status.load(Ordering::SeqCst)
+                            != DaemonStatus::Inactive
+                        {
+                            return Err(MethodErr::failed("daemon is busy"));
+                        }
+
+                        Resolution::from_u8(resolution)
+                            .ok_or("resolution value is out of range")
+                            .map_err(|why| MethodErr::failed(&why))
+                    });
+
+                    let resolution = match checked {
+                        Ok(resolution) => resolution,
+                        Err(why) => {
+                            ctx.reply::<()>(Err(why));
+                            return Some(ctx);
+                        }
+                    };
+
+                    info!("resolving {} with {:?}", path, resolution);
+
+                    daemon.reply_later(ctx, move || {
+                        conffiles::resolve(&path, resolution)
+                            .map_err(|ref why| MethodErr::failed(&format_error(why)))
+                    })
+                },
+            );
+
             b.method(
                 methods::DISMISS_NOTIFICATION,
                 ("dismiss",),
@@ -484,11 +922,18 @@ impl Daemon {
 
             b.method(
                 methods::FETCH_UPDATES,
-                ("additional_packages", "download_only"),
-                ("updates_available", "completed", "total"),
+                ("additional_packages", "download_only", "security_only", "include", "exclude"),
+                ("updates_available", "completed", "total", "bytes", "deferred"),
                 |_ctx: &mut Context,
                  daemon: &mut Daemon,
-                 (additional_packages, download_only): (Vec<String>, bool)| {
+                 (additional_packages, download_only, security_only, include, exclude): (
+                    Vec<String>,
+                    bool,
+                    bool,
+                    Vec<String>,
+                    Vec<String>,
+                )| {
+                    let selection = Selection { security_only, include, exclude };
                     daemon
                         .set_status(
                             DaemonStatus::FetchingPackages,
@@ -498,14 +943,20 @@ impl Daemon {
                                         daemon.shared_state.fetching_state.load(Ordering::SeqCst);
                                     let progress = progress as u32;
                                     let total = total as u32;
-                                    Ok((true, progress, total))
+                                    let bytes =
+                                        daemon.shared_state.fetch_bytes.lock().unwrap().total;
+                                    Ok((true, progress, total, bytes, 0))
                                 } else {
                                     futures::executor::block_on(async move {
                                         daemon
-                                            .fetch_updates(additional_packages, download_only)
+                                            .fetch_updates(
+                                                additional_packages,
+                                                download_only,
+                                                selection,
+                                            )
                                             .await
                                     })
-                                    .map(|(x, t)| (x, 0u32, t))
+                                    .map(|(x, t, bytes, deferred)| (x, 0u32, t, bytes, deferred))
                                     .map_err(|ref why| format_error(why.as_ref()))
                                 }
                             },
@@ -514,6 +965,15 @@ impl Daemon {
                 },
             );
 
+            b.method(
+                methods::GET_SCHEDULE,
+                (),
+                ("when", "days", "start", "end"),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    Ok(daemon.scheduler.schedule.to_dbus())
+                },
+            );
+
             b.method(
                 methods::FETCH_UPDATES_STATUS,
                 (),
@@ -523,6 +983,142 @@ impl Daemon {
                 },
             );
 
+            // The checks inspect the whole system, so they are run off the loop.
+            b.method_with_cr_custom::<(String,), (Vec<(String, u8, String)>,), _, _>(
+                methods::HEALTH_CHECK,
+                ("release",),
+                ("checks",),
+                |ctx: Context, cr: &mut Crossroads, (release,): (String,)| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+                    let fg_tx = daemon.fg_tx.clone();
+
+                    daemon.reply_later(ctx, move || {
+                        let report = health::verify(Some(&release));
+                        let _ = fg_tx.send(FgEvent::Health(report.clone()));
+                        Ok((report.into_dbus(),))
+                    })
+                },
+            );
+
+            b.method(
+                methods::HEALTH_STATUS,
+                (),
+                ("checks",),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    Ok((daemon.last_known.health.clone().into_dbus(),))
+                },
+            );
+
+            // The rotated apt history logs are decompressed and parsed off the loop.
+            b.method_with_cr_custom::<(i64,), (Vec<DbusEntry>,), _, _>(
+                methods::HISTORY,
+                ("since",),
+                ("entries",),
+                |ctx: Context, cr: &mut Crossroads, (since,): (i64,)| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+
+                    daemon.reply_later(ctx, move || {
+                        let entries = history::timeline(since);
+                        Ok((entries.into_iter().map(HistoryEntry::into_dbus).collect::<Vec<_>>(),))
+                    })
+                },
+            );
+
+            b.method(
+                methods::KILL_STALLED,
+                (),
+                (),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    daemon.kill_stalled().map_err(|ref why| MethodErr::failed(&format_error(why)))
+                },
+            );
+
+            // Changelogs are fetched from the archive, so the updates are listed off the loop.
+            b.method_with_cr_custom::<
+                (),
+                (Vec<(String, String, String, String, u64, String)>,),
+                _,
+                _,
+            >(
+                methods::LIST_UPDATES,
+                (),
+                ("updates",),
+                |mut ctx: Context, cr: &mut Crossroads, _inputs: ()| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+
+                    if daemon.shared_state.status.load(Ordering::SeqCst) != DaemonStatus::Inactive
+                    {
+                        ctx.reply::<()>(Err(MethodErr::failed("daemon is busy")));
+                        return Some(ctx);
+                    }
+
+                    let phased = UpdatesConfig::load().phased_updates;
+
+                    daemon.reply_later(ctx, move || {
+                        tokio::runtime::Handle::current()
+                            .block_on(updates::pending(phased))
+                            .map(|updates| {
+                                let updates = updates.into_iter().map(PendingUpdate::into_dbus);
+                                (updates.collect::<Vec<_>>(),)
+                            })
+                            .map_err(|ref why| MethodErr::failed(&format_error(why.as_ref())))
+                    })
+                },
+            );
+
+            // Staging simulates and fetches the updates, so it is done off the loop.
+            b.method_with_cr_custom::<(), (u32,), _, _>(
+                methods::OFFLINE_UPDATE,
+                (),
+                ("packages",),
+                |mut ctx: Context, cr: &mut Crossroads, _inputs: ()| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+                    let shared_state = daemon.shared_state.clone();
+
+                    let claimed = shared_state.status.compare_exchange(
+                        DaemonStatus::Inactive,
+                        DaemonStatus::FetchingPackages,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+
+                    if claimed.is_err() {
+                        ctx.reply::<()>(Err(MethodErr::failed("daemon is busy")));
+                        return Some(ctx);
+                    }
+
+                    daemon.reply_later(ctx, move || {
+                        let result =
+                            Handle::current().block_on(Daemon::offline_update(&shared_state));
+                        shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+
+                        result
+                            .map(|packages| (packages,))
+                            .map_err(|ref why| MethodErr::failed(&format_error(why)))
+                    })
+                },
+            );
+
+            b.method(
+                methods::OFFLINE_UPDATE_CANCEL,
+                (),
+                (),
+                |_ctx: &mut Context, _daemon: &mut Daemon, _inputs: ()| {
+                    release::systemd::offline_update_cancel();
+                    Ok(())
+                },
+            );
+
+            b.method(
+                methods::OFFLINE_UPDATE_STATUS,
+                (),
+                ("pending", "status", "why"),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    let (status, why) = result_signal(daemon.last_known.offline_update.as_ref());
+                    Ok((release::systemd::offline_update_pending(), status, why))
+                },
+            );
+
             b.method(
                 methods::PACKAGE_UPGRADE,
                 (),
@@ -541,6 +1137,26 @@ impl Daemon {
                 },
             );
 
+            b.method(
+                methods::PACKAGE_UPGRADE_STATUS,
+                (),
+                ("status", "why"),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    Ok(result_signal(daemon.last_known.package_upgrade.as_ref()))
+                },
+            );
+
+            b.method(
+                methods::PENDING_REMOVALS,
+                (),
+                ("packages",),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    let pending = daemon.shared_state.pending_removals.lock().unwrap();
+                    let packages = pending.as_ref().map(|removals| removals.packages.clone());
+                    Ok((packages.unwrap_or_default(),))
+                },
+            );
+
             b.method(
                 methods::RECOVERY_UPGRADE_FILE,
                 ("path",),
@@ -645,11 +1261,14 @@ impl Daemon {
                                 "daemon is busy upgrading the recovery partition",
                             ))
                         }
-                        DaemonStatus::FetchingPackages => {
+                        DaemonStatus::FetchingPackages | DaemonStatus::BackgroundFetch => {
                             return Err(MethodErr::failed(
                                 "daemon is busy fetching package updates",
                             ))
                         }
+                        DaemonStatus::Repairing => {
+                            return Err(MethodErr::failed("daemon is busy repairing the system"))
+                        }
                     }
 
                     daemon.last_known.development = development;
@@ -711,12 +1330,14 @@ impl Daemon {
                 },
             );
 
-            b.method(
+            // Initrds may have to be regenerated, so the upgrade is finalized off the loop.
+            b.method_with_cr_custom::<(), (), _, _>(
                 methods::RELEASE_UPGRADE_FINALIZE,
                 (),
                 (),
-                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
-                    daemon.release_upgrade_finalize().map_err(|why| MethodErr::failed(&why))
+                |ctx: Context, cr: &mut Crossroads, _inputs: ()| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+                    daemon.release_upgrade_finalize(ctx)
                 },
             );
 
@@ -733,10 +1354,112 @@ impl Daemon {
                 methods::RELEASE_REPAIR,
                 (),
                 (),
-                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
-                    futures::executor::block_on(daemon.release_repair())
-                        .map_err(|ref why| format_error(why.as_ref()))
-                        .map_err(|why| MethodErr::failed(&why))
+                |ctx: &mut Context, _daemon: &mut Daemon, _inputs: ()| {
+                    let reports = futures::executor::block_on(Daemon::release_repair(&[], false))
+                        .map_err(|ref why| format_error(why))
+                        .map_err(|why| MethodErr::failed(&why))?;
+
+                    let mut result = Ok(());
+
+                    for report in reports {
+                        if let (Some(why), Ok(())) = (report.error.as_ref(), result.as_ref()) {
+                            result = Err(MethodErr::failed(why));
+                        }
+
+                        let signal = ctx.make_signal(signals::REPAIR_REPORT, report.into_dbus());
+                        ctx.push_msg(signal);
+                    }
+
+                    result
+                },
+            );
+
+            b.method_with_cr_custom::<(Vec<String>,), (), _, _>(
+                methods::REINSTALL_REMOVED,
+                ("packages",),
+                (),
+                |mut ctx: Context, cr: &mut Crossroads, (packages,): (Vec<String>,)| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+                    let shared_state = daemon.shared_state.clone();
+
+                    let claimed = shared_state.status.compare_exchange(
+                        DaemonStatus::Inactive,
+                        DaemonStatus::PackageUpgrade,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+
+                    if claimed.is_err() {
+                        ctx.reply::<()>(Err(MethodErr::failed("daemon is busy")));
+                        return Some(ctx);
+                    }
+
+                    info!("reinstalling removed packages: {:?}", packages);
+
+                    daemon.reply_later(ctx, move || {
+                        let handle = tokio::runtime::Handle::current();
+                        let result = handle.block_on(release::removed::reinstall(&packages));
+                        shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+                        result.map_err(|ref why| MethodErr::failed(&format_error(why)))
+                    })
+                },
+            );
+
+            // The candidates of the packages are queried from apt, so they are listed off the loop.
+            b.method_with_cr_custom::<(), (Vec<(String, String, String)>,), _, _>(
+                methods::REMOVED_PACKAGES,
+                (),
+                ("packages",),
+                |ctx: Context, cr: &mut Crossroads, _inputs: ()| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+
+                    daemon.reply_later(ctx, || {
+                        let packages = Handle::current()
+                            .block_on(release::removed::reinstallable())
+                            .map_err(|ref why| MethodErr::failed(&format_error(why)))?
+                            .into_iter()
+                            .map(|package| {
+                                (package.removal.package, package.removal.reason, package.candidate)
+                            })
+                            .collect::<Vec<_>>();
+
+                        Ok((packages,))
+                    })
+                },
+            );
+
+            b.method(
+                methods::REPAIR_LIST,
+                (),
+                ("checks",),
+                |_ctx: &mut Context, _daemon: &mut Daemon, _inputs: ()| {
+                    let release = &os_release::OS_RELEASE.as_ref().unwrap().version_codename;
+                    let checks = repair::checks(release)
+                        .iter()
+                        .map(|check| {
+                            let stage = <&'static str>::from(check.stage()).to_owned();
+                            let description = check.description().to_owned();
+                            (check.id().to_owned(), stage, description)
+                        })
+                        .collect::<Vec<_>>();
+
+                    Ok((checks,))
+                },
+            );
+
+            // Repairs may modify packages and partitions, so they are applied off the loop.
+            b.method_with_cr_custom::<
+                (Vec<String>, bool),
+                (Vec<(String, Vec<String>, Vec<String>, String)>,),
+                _,
+                _,
+            >(
+                methods::REPAIR_RUN,
+                ("only", "dry_run"),
+                ("reports",),
+                |ctx: Context, cr: &mut Crossroads, (only, dry_run): (Vec<String>, bool)| {
+                    let daemon: &mut Daemon = cr.data_mut(ctx.path())?;
+                    daemon.repair_run(ctx, only, dry_run)
                 },
             );
 
@@ -750,6 +1473,17 @@ impl Daemon {
                 },
             );
 
+            b.method(
+                methods::SET_SCHEDULE,
+                ("when", "days", "start", "end"),
+                (),
+                |_ctx: &mut Context, daemon: &mut Daemon, schedule: (u8, u8, u16, u16)| {
+                    Schedule::from_dbus(schedule)
+                        .and_then(|schedule| daemon.set_schedule(schedule))
+                        .map_err(|ref why| MethodErr::failed(&format_error(why)))
+                },
+            );
+
             b.method(
                 methods::STATUS,
                 (),
@@ -761,6 +1495,32 @@ impl Daemon {
                 },
             );
 
+            b.method(
+                methods::UNDO_UPDATE,
+                (),
+                ("changes",),
+                |_ctx: &mut Context, daemon: &mut Daemon, _inputs: ()| {
+                    if daemon.shared_state.status.load(Ordering::SeqCst) != DaemonStatus::Inactive
+                    {
+                        return Err(MethodErr::failed("daemon is busy"));
+                    }
+
+                    let transaction = Transaction::load()
+                        .map_err(|ref why| MethodErr::failed(&format_error(why)))?;
+
+                    let changes = transaction.clone().into_dbus();
+                    info!("undoing the last package update: {:?}", changes);
+
+                    daemon.set_status(DaemonStatus::PackageUpgrade, move |daemon, _active| {
+                        daemon
+                            .submit_event(Event::UndoUpdate(transaction))
+                            .map_err(|ref why| MethodErr::failed(&format_error(why.as_ref())))?;
+
+                        Ok((changes,))
+                    })
+                },
+            );
+
             b.method(
                 methods::UPDATE_CHECK,
                 (),
@@ -795,6 +1555,22 @@ impl Daemon {
 
         let path = dbus::strings::Path::from_slice("/com/system76/PopUpgrade\0").unwrap();
         let mut shutdown_triggered = false;
+        let mut predownload =
+            predownload::Schedule::new(&NetworkConfig::load().predownload, Instant::now());
+
+        // Updates scheduled for shutdown are staged when logind prepares for a shutdown.
+        let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+        let prepare_for_shutdown =
+            MatchRule::new_signal(predownload::LOGIND_IFACE, "PrepareForShutdown");
+
+        let matched = connection.add_match(prepare_for_shutdown, move |(start,): (bool,), _, _| {
+            let _ = shutdown_tx.send(start);
+            true
+        });
+
+        if let Err(why) = matched {
+            error!("failed to listen for shutdowns: {}", why);
+        }
 
         loop {
             let _ = connection.process(std::time::Duration::from_millis(500));
@@ -807,7 +1583,8 @@ impl Daemon {
 
             if !daemon.last_known.development {
                 if let ReleaseCheck::NotFound = daemon.release_check {
-                    shutdown_triggered = true;
+                    // Scheduled updates require the daemon to keep running.
+                    shutdown_triggered = daemon.scheduler.schedule.when == When::Never;
                 }
             }
 
@@ -827,6 +1604,32 @@ impl Daemon {
                 self_upgrade(&packages).await;
             }
 
+            if predownload.due(Instant::now()) {
+                let started = daemon.predownload_updates();
+                predownload.reschedule(Instant::now(), started);
+            }
+
+            let now = chrono::Local::now().naive_local();
+            if let Some(window) = daemon.scheduler.due(now, Instant::now()) {
+                let attempt = daemon.scheduled_updates().await;
+                daemon.scheduler.checked(window, attempt, Instant::now());
+            }
+
+            while let Ok(start) = shutdown_rx.try_recv() {
+                if start {
+                    daemon.shutdown_updates().await;
+                } else {
+                    daemon.shutting_down = false;
+                    daemon.inhibit_shutdown();
+                }
+            }
+
+            if daemon.shutting_down && daemon.is_inactive() {
+                info!("allowing the system to shut down");
+                daemon.shutdown_inhibitor = None;
+                daemon.shutting_down = false;
+            }
+
             if let Some(status) = sighandler::status() {
                 info!("received a '{}' signal", status);
 
@@ -845,6 +1648,21 @@ impl Daemon {
 
             while let Ok(fg_event) = fg_receiver.try_recv() {
                 match fg_event {
+                    FgEvent::Reply(mut ctx) => {
+                        if let Err(()) = ctx.flush_messages(&connection) {
+                            error!("failed to send reply to {}", ctx.method());
+                        }
+                    }
+
+                    FgEvent::Health(report) => daemon.last_known.health = report,
+
+                    // A failed fetch is attempted again shortly.
+                    FgEvent::Predownloaded(fetched) => {
+                        if !fetched {
+                            predownload.reschedule(Instant::now(), false);
+                        }
+                    }
+
                     FgEvent::SetUpgradeState(result, action, from, to) => {
                         if result.is_ok() {
                             info!("setting release upgrade state");
@@ -872,6 +1690,7 @@ impl Daemon {
                         | SignalEvent::RecoveryUpgradeResult(_)
                         | SignalEvent::ReleaseUpgradeEvent(_)
                         | SignalEvent::Upgrade(_) => info!("{}", dbus_event),
+                        SignalEvent::Stalled(_) => warn!("{}", dbus_event),
                         _ => (),
                     }
 
@@ -881,17 +1700,52 @@ impl Daemon {
                             let message = Self::signal_message(signals::PACKAGE_FETCH_RESULT)
                                 .append2(status, why);
 
+                            daemon.scheduler.finished(result.is_ok());
+                            daemon.install_inhibitor = None;
                             daemon.last_known.fetch = result;
                             message
                         }
-                        SignalEvent::Fetched(name, completed, total) => Self::signal_message(
-                            signals::PACKAGE_FETCHED,
-                        )
-                        .append3(name.as_str(), completed, total),
+                        SignalEvent::Fetched(name, completed, total, bytes) => {
+                            Self::signal_message(signals::PACKAGE_FETCHED)
+                                .append3(name.as_str(), completed, total)
+                                .append3(bytes.fetched, bytes.total, bytes.rate)
+                        }
+                        SignalEvent::FetchRetrying(name, attempt, why) => {
+                            Self::signal_message(signals::PACKAGE_FETCH_RETRYING)
+                                .append3(name.as_str(), attempt, why.as_str())
+                        }
                         SignalEvent::Fetching(name) => {
                             Self::signal_message(signals::PACKAGE_FETCHING).append1(name.as_str())
                         }
+                        SignalEvent::HealthReport(report) => {
+                            daemon.last_known.health = report.clone();
+                            Self::signal_message(signals::HEALTH_REPORT).append1(report.into_dbus())
+                        }
                         SignalEvent::NoConnection => Self::signal_message(signals::NO_CONNECTION),
+                        SignalEvent::PackageUpgradeResult(result) => {
+                            let (status, why) = result_signal(result.as_ref());
+                            let message = Self::signal_message(signals::PACKAGE_UPGRADE_RESULT)
+                                .append2(status, why);
+
+                            daemon.last_known.package_upgrade = result;
+                            message
+                        }
+                        SignalEvent::Progress(snapshot) => {
+                            let (percent, stage, eta) = snapshot.into_dbus();
+                            Self::signal_message(signals::PROGRESS).append3(percent, stage, eta)
+                        }
+                        SignalEvent::MeteredDownload(bytes) => {
+                            Self::signal_message(signals::METERED_DOWNLOAD).append1(bytes)
+                        }
+                        SignalEvent::PendingRemovals(packages) => {
+                            Self::signal_message(signals::PENDING_REMOVALS).append1(packages)
+                        }
+                        SignalEvent::RepairReport(report) => {
+                            let (id, found, changed, error) = report.into_dbus();
+                            Self::signal_message(signals::REPAIR_REPORT)
+                                .append3(id, found, changed)
+                                .append1(error)
+                        }
                         SignalEvent::RecoveryDownloadProgress(progress, total) => {
                             daemon
                                 .shared_state
@@ -915,6 +1769,12 @@ impl Daemon {
                         SignalEvent::ReleaseUpgradeEvent(event) => {
                             Self::signal_message(signals::RELEASE_EVENT).append1(event as u8)
                         }
+                        SignalEvent::Stalled(stall) => {
+                            let (package, script, pid, idle) = stall.clone().into_dbus();
+                            Self::signal_message(signals::STALLED)
+                                .append3(package, script, pid)
+                                .append1(idle)
+                        }
                         SignalEvent::Upgrade(ref event) => {
                             Self::signal_message(signals::PACKAGE_UPGRADE)
                                 .append1(event.clone().into_dbus_map())
@@ -952,25 +1812,263 @@ impl Daemon {
         &self,
         extra_packages: Vec<String>,
         download_only: bool,
-    ) -> anyhow::Result<(bool, u32)> {
-        info!("fetching updates for the system, including {:?}", extra_packages);
-
+        selection: Selection,
+    ) -> anyhow::Result<(bool, u32, u64, u32)> {
         let shutdown = self.shared_state.shutdown.lock().await.clone();
+        let config = UpdatesConfig::load();
+        let selection = selection.with_config(&config);
+        let phased = config.phased_updates;
+
+        let upgrades = updates::upgrades(phased).await?;
+        let deferred = upgrades.deferred.len() as u32;
+
+        if deferred != 0 {
+            info!("updates deferred by phasing ({}): {:?}", phased, upgrades.deferred);
+        }
 
-        use crate::fetch::apt::ExtraPackages;
-        let packages = Some(ExtraPackages::Dynamic(extra_packages));
-        let apt_uris = crate::fetch::apt::fetch_uris(shutdown, packages, true).await?;
+        // A selection of updates is limited to upgrading the packages which it selects.
+        let only = if !selection.is_everything() {
+            info!(
+                "fetching updates for the system, selected by {:?}, including {:?}",
+                selection, extra_packages
+            );
+
+            let packages = upgrades.selected(&selection, &config);
+
+            if packages.is_empty() && extra_packages.is_empty() {
+                info!("no selected updates available to fetch");
+                return Ok((false, 0, 0, deferred));
+            }
+
+            Some(packages)
+        } else {
+            info!("fetching updates for the system, including {:?}", extra_packages);
+            None
+        };
+
+        let upgrades: Vec<Upgrade> = match only {
+            Some(ref packages) => upgrades
+                .packages
+                .into_iter()
+                .filter(|upgrade| packages.contains(&upgrade.package))
+                .collect(),
+            None => upgrades.packages,
+        };
+
+        let apt_uris = match only.clone() {
+            Some(packages) => {
+                let extra = extra_packages;
+                crate::fetch::apt::fetch_only_upgrade_uris(shutdown, packages, extra, phased)
+                    .await?
+            }
+            None => {
+                use crate::fetch::apt::ExtraPackages;
+                let packages = Some(ExtraPackages::Dynamic(extra_packages));
+                crate::fetch::apt::fetch_uris(shutdown, packages, true, phased).await?
+            }
+        };
 
         if apt_uris.is_empty() {
-            info!("no updates available to fetch");
-            return Ok((false, 0));
+            // Updates that were fetched in the background only remain to be installed.
+            let pending = if download_only { 0 } else { upgrades.len() as u32 };
+
+            if pending == 0 {
+                info!("no updates available to fetch");
+                return Ok((false, 0, 0, deferred));
+            }
+
+            info!("{} updates were already fetched", pending);
+            self.submit_event(Event::FetchUpdates {
+                apt_uris,
+                download_only,
+                only,
+                phased,
+                upgrades,
+            })?;
+            return Ok((true, pending, 0, deferred));
         }
 
         let npackages = apt_uris.len() as u32;
+        let bytes = apt_uris.iter().map(|request| request.size).sum();
+
+        self.submit_event(Event::FetchUpdates {
+            apt_uris,
+            download_only,
+            only,
+            phased,
+            upgrades,
+        })?;
+
+        Ok((true, npackages, bytes, deferred))
+    }
+
+    /// Begins to fetch updates in the background if the daemon is inactive and the system is
+    /// ready for it, returning `true` if the fetch was started.
+    fn predownload_updates(&mut self) -> bool {
+        if !self.is_inactive() {
+            return false;
+        }
+
+        if let Err(why) = predownload::ready(&predownload::System, &NetworkManager) {
+            debug!("not fetching updates in the background: {}", why);
+            return false;
+        }
+
+        let claimed = self.shared_state.status.compare_exchange(
+            DaemonStatus::Inactive,
+            DaemonStatus::BackgroundFetch,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if claimed.is_err() {
+            return false;
+        }
+
+        info!("fetching updates in the background");
+        self.submit_event(Event::Predownload).is_ok()
+    }
+
+    /// Installs updates within a maintenance window if the daemon is inactive and the system is
+    /// ready for it.
+    async fn scheduled_updates(&mut self) -> Attempt {
+        if !self.is_inactive() {
+            return Attempt::Postponed;
+        }
 
-        self.submit_event(Event::FetchUpdates { apt_uris, download_only })?;
+        if let Err(why) = schedule::ready(&predownload::System) {
+            info!("postponing scheduled updates: {}", why);
+            return Attempt::Postponed;
+        }
+
+        info!("installing scheduled updates");
+        self.background_updates().await
+    }
+
+    /// Stages fetched updates to be installed at the next startup as the system shuts down,
+    /// which is delayed until the daemon is inactive.
+    async fn shutdown_updates(&mut self) {
+        if self.shutdown_inhibitor.is_none() {
+            return;
+        }
+
+        self.shutting_down = true;
+
+        if !self.is_inactive() {
+            return;
+        }
+
+        info!("staging updates to be installed at the next startup");
+        self.shared_state.status.store(DaemonStatus::FetchingPackages, Ordering::SeqCst);
+
+        if let Err(why) = Self::offline_update(&self.shared_state).await {
+            info!("not staging updates on shutdown: {}", format_error(&why));
+        }
 
-        Ok((true, npackages))
+        self.shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+    }
+
+    /// Fetches and installs updates on behalf of the daemon.
+    ///
+    /// Shutting down is prevented until the updates have been installed.
+    async fn background_updates(&mut self) -> Attempt {
+        self.shared_state.status.store(DaemonStatus::FetchingPackages, Ordering::SeqCst);
+
+        let fetched = self.fetch_updates(Vec::new(), false, Selection::default()).await;
+
+        let attempt = match fetched {
+            Ok((true, ..)) => {
+                self.install_inhibitor = schedule::block_shutdown()
+                    .map_err(|why| error!("failed to inhibit shutdown: {}", why))
+                    .ok();
+
+                return Attempt::Installing;
+            }
+            Ok(_) => Attempt::UpToDate,
+            Err(why) => {
+                error!("failed to fetch updates in the background: {}", format_error(why.as_ref()));
+                Attempt::Postponed
+            }
+        };
+
+        self.shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+        attempt
+    }
+
+    /// Whether the daemon is free to begin a task of its own.
+    fn is_inactive(&self) -> bool {
+        self.shared_state.status.load(Ordering::SeqCst) == DaemonStatus::Inactive
+            && self.release_upgrade.is_none()
+    }
+
+    /// Replaces the schedule of package updates, and persists it.
+    fn set_schedule(&mut self, schedule: Schedule) -> Result<(), ScheduleError> {
+        schedule.save()?;
+        info!("package updates scheduled: {}", schedule);
+        self.scheduler = Scheduler::new(schedule);
+        self.inhibit_shutdown();
+        Ok(())
+    }
+
+    /// Delays shutdowns while updates are scheduled to be staged on shutdown.
+    fn inhibit_shutdown(&mut self) {
+        if self.scheduler.schedule.when != When::Shutdown {
+            self.shutdown_inhibitor = None;
+        } else if self.shutdown_inhibitor.is_none() {
+            self.shutdown_inhibitor = schedule::inhibit_shutdown()
+                .map_err(|why| error!("failed to inhibit shutdown: {}", why))
+                .ok();
+        }
+    }
+
+    /// Stages fetched package updates to be installed at the next startup, returning the number
+    /// of packages that will be installed.
+    async fn offline_update(shared_state: &SharedState) -> Result<u32, ReleaseError> {
+        release::systemd::upgrade_prereq()?;
+
+        if release::upgrade_in_progress() {
+            return Err(ReleaseError::ReleaseUpgradeStaged);
+        }
+
+        let shutdown = shared_state.shutdown.lock().await.clone();
+        let phased = UpdatesConfig::load().phased_updates;
+        let only = updates::excluding_config(phased).await.map_err(ReleaseError::AptList)?;
+
+        let unfetched = match only {
+            Some(ref packages) => {
+                let packages = packages.clone();
+                crate::fetch::apt::fetch_only_upgrade_uris(shutdown, packages, Vec::new(), phased)
+                    .await
+            }
+            None => crate::fetch::apt::fetch_uris(shutdown, None, true, phased).await,
+        };
+
+        let unfetched = unfetched.map_err(ReleaseError::AptList)?;
+
+        if !unfetched.is_empty() {
+            return Err(ReleaseError::UpdatesNotFetched(unfetched.len()));
+        }
+
+        let mut upgrades = updates::upgrades(phased).await.map_err(ReleaseError::AptList)?.packages;
+
+        if let Some(ref packages) = only {
+            upgrades.retain(|upgrade| packages.contains(&upgrade.package));
+        }
+
+        let packages = upgrades.len() as u32;
+
+        if packages == 0 {
+            return Err(ReleaseError::NoUpdates);
+        }
+
+        if let Err(why) = Transaction::begin(&upgrades).stage() {
+            warn!("the update cannot be undone: {}", format_error(&why));
+        }
+
+        release::systemd::offline_update_set(phased.apt_options(), only.as_deref())?;
+        info!("{} package updates will be installed at the next startup", packages);
+
+        Ok(packages)
     }
 
     fn package_upgrade(&mut self) -> anyhow::Result<()> {
@@ -980,7 +2078,99 @@ impl Daemon {
         Ok(())
     }
 
+    /// Approves the pending package removals of a release upgrade, except those to keep.
+    fn approve_removals(&mut self, keep: Vec<String>) -> Result<(), String> {
+        let mut pending = self.shared_state.pending_removals.lock().unwrap();
+
+        let removals = pending.as_ref().ok_or("no package removals are awaiting approval")?;
+
+        if let Some(package) = keep.iter().find(|package| !removals.packages.contains(package)) {
+            return Err(format!("{} is not one of the pending package removals", package));
+        }
+
+        info!("package removals approved, keeping {:?}", keep);
+
+        if let Some(removals) = pending.take() {
+            let _ = removals.approve.send(keep);
+        }
+
+        Ok(())
+    }
+
+    /// Approves a pending download over a metered connection.
+    fn approve_metered_download(&mut self) -> Result<(), &'static str> {
+        let approve = self
+            .shared_state
+            .pending_metered
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or("no download is awaiting approval")?;
+
+        info!("download over a metered connection approved");
+        let _ = approve.send(());
+
+        Ok(())
+    }
+
+    /// Kills the process of an apt or dpkg operation which has stalled, and configures the
+    /// packages that it interrupted.
+    fn kill_stalled(&mut self) -> Result<(), watchdog::WatchdogError> {
+        let stall = self
+            .shared_state
+            .stall
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(watchdog::WatchdogError::NotStalled)?;
+
+        info!("killing the stalled process {}", stall.pid);
+
+        let shared_state = self.shared_state.clone();
+
+        tokio::spawn(async move {
+            if let Err(why) = watchdog::recover(&stall).await {
+                error!("failed to recover from the stall: {}", format_error(&why));
+                return;
+            }
+
+            // The task which stalled may repair the packages itself, so the packages which remain
+            // interrupted are only configured once it has finished.
+            while shared_state
+                .status
+                .compare_exchange(
+                    DaemonStatus::Inactive,
+                    DaemonStatus::PackageUpgrade,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_err()
+            {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+
+            if let Err(why) = watchdog::configure_interrupted().await {
+                error!("failed to recover from the stall: {}", format_error(&why));
+            }
+
+            shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+        });
+
+        Ok(())
+    }
+
     async fn cancel(&mut self) {
+        // Declining the package removals of a release upgrade cancels the upgrade.
+        if self.shared_state.pending_removals.lock().unwrap().take().is_some() {
+            info!("declined the pending package removals");
+            return;
+        }
+
+        if self.shared_state.pending_metered.lock().unwrap().take().is_some() {
+            info!("declined the download over a metered connection");
+            return;
+        }
+
         if self.shared_state.release_upgrade_began.load(Ordering::SeqCst) {
             info!("cannot cancel a release upgrade that's now ongoing");
             return;
@@ -1086,22 +2276,99 @@ impl Daemon {
         self.submit_event(event)
     }
 
-    fn release_upgrade_finalize(&mut self) -> Result<(), String> {
-        match self.release_upgrade.as_ref() {
-            Some(ReleaseUpgradeState { action, from, to }) => {
-                release::upgrade_finalize(*action, from, to)
-                    .map_err(|why| format!("release upgrade finalization failed: {}", why))
+    fn release_upgrade_finalize(&self, mut ctx: Context) -> Option<Context> {
+        let (action, from, to) = match self.release_upgrade.as_ref() {
+            Some(ReleaseUpgradeState { action, from, to }) => (*action, from.clone(), to.clone()),
+            None => {
+                ctx.reply::<()>(Err(MethodErr::failed(
+                    "release upgrade cannot be finalized, because a release upgrade was not \
+                     performed",
+                )));
+                return Some(ctx);
             }
-            None => Err("release upgrade cannot be finalized, because a release upgrade was not \
-                         performed"
-                .into()),
-        }
+        };
+
+        self.reply_later(ctx, move || {
+            Handle::current()
+                .block_on(release::upgrade_finalize(action, &from, &to))
+                .map_err(|why| format!("release upgrade finalization failed: {}", why))
+                .map_err(|why| MethodErr::failed(&why))?;
+
+            if let Err(why) = health::schedule_verification(&to) {
+                warn!("failed to schedule verification of the upgrade: {}", why);
+            }
+
+            Ok(())
+        })
     }
 
-    async fn release_repair(&mut self) -> anyhow::Result<()> {
-        crate::repair::repair().await?;
+    /// Applies the selected repair checks, or every check of the repair stage if none are given.
+    ///
+    /// Checks which must be fixed by request are only fixed when they are selected. Checks which
+    /// fail record their error in their report.
+    async fn release_repair(
+        only: &[String],
+        dry_run: bool,
+    ) -> Result<Vec<RepairReport>, RepairError> {
+        let release = &os_release::OS_RELEASE.as_ref().unwrap().version_codename;
+        let checks = repair::select(release, only)?;
+
+        let fix = if dry_run {
+            repair::Fix::None
+        } else if only.is_empty() {
+            repair::Fix::Automatic
+        } else {
+            repair::Fix::All
+        };
 
-        Ok(())
+        Ok(repair::run(&checks, fix, &|_| ()).await.0)
+    }
+
+    /// Applies the selected repair checks away from the DBus loop, while the daemon is busy.
+    ///
+    /// A signal is sent with the report of each check before the reports are returned.
+    fn repair_run(&self, mut ctx: Context, only: Vec<String>, dry_run: bool) -> Option<Context> {
+        let shared_state = self.shared_state.clone();
+
+        let claimed = shared_state.status.compare_exchange(
+            DaemonStatus::Inactive,
+            DaemonStatus::Repairing,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if claimed.is_err() {
+            ctx.reply::<()>(Err(MethodErr::failed("daemon is busy")));
+            return Some(ctx);
+        }
+
+        let fg_tx = self.fg_tx.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let result = Handle::current().block_on(Self::release_repair(&only, dry_run));
+            shared_state.status.store(DaemonStatus::Inactive, Ordering::SeqCst);
+
+            let reply = match result {
+                Ok(reports) => {
+                    let reports = reports
+                        .into_iter()
+                        .map(|report| {
+                            let report = report.into_dbus();
+                            ctx.push_msg(ctx.make_signal(signals::REPAIR_REPORT, report.clone()));
+                            report
+                        })
+                        .collect::<Vec<_>>();
+
+                    Ok((reports,))
+                }
+                Err(ref why) => Err(MethodErr::failed(&format_error(why))),
+            };
+
+            ctx.reply(reply);
+            let _ = fg_tx.send(FgEvent::Reply(ctx));
+        });
+
+        None
     }
 
     async fn reset(&mut self) -> Result<(), String> {
@@ -1117,6 +2384,23 @@ impl Daemon {
         Ok(())
     }
 
+    /// Replies to a method call once `query` has run on a blocking thread, so that slow queries
+    /// do not hold up the DBus loop.
+    fn reply_later<OA, F>(&self, mut ctx: Context, query: F) -> Option<Context>
+    where
+        OA: dbus::arg::AppendAll,
+        F: FnOnce() -> Result<OA, MethodErr> + Send + 'static,
+    {
+        let fg_tx = self.fg_tx.clone();
+
+        tokio::task::spawn_blocking(move || {
+            ctx.reply(query());
+            let _ = fg_tx.send(FgEvent::Reply(ctx));
+        });
+
+        None
+    }
+
     fn send_signal_message(connection: &Connection, message: Message) {
         if let Err(()) = connection.send(message) {
             error!("failed to send dbus signal message");
@@ -1156,6 +2440,129 @@ impl Daemon {
     }
 }
 
+/// Decides if a download of `bytes` may begin, asking clients first if the connection is metered.
+async fn metered_approval(
+    shared_state: &SharedState,
+    dbus_tx: &UnboundedSender<SignalEvent>,
+    bytes: Option<u64>,
+) -> Result<(), MeteredError> {
+    match network::decide(&NetworkManager, &NetworkConfig::load(), bytes) {
+        Decision::Proceed => Ok(()),
+        Decision::Defer => Err(MeteredError::Deferred),
+        Decision::Ask => {
+            info!("awaiting approval to download over a metered connection");
+            let (approve, approval) = oneshot::channel();
+            *shared_state.pending_metered.lock().unwrap() = Some(approve);
+            let _ = dbus_tx.send(SignalEvent::MeteredDownload(bytes.unwrap_or(0)));
+
+            let result = match tokio::time::timeout(APPROVAL_TIMEOUT, approval).await {
+                Ok(answer) => answer.map_err(|_| MeteredError::Declined),
+                Err(_) => {
+                    info!("download over a metered connection was not answered in time");
+                    Err(MeteredError::Declined)
+                }
+            };
+
+            shared_state.pending_metered.lock().unwrap().take();
+            result
+        }
+    }
+}
+
+/// Fails unless the caller of a method is root, for methods which expose or modify files that
+/// only root may.
+fn require_root(ctx: &Context) -> Result<(), MethodErr> {
+    let sender = ctx.message().sender().ok_or_else(|| MethodErr::failed("caller is unknown"))?;
+
+    let uid = Connection::new_system()
+        .and_then(|bus| {
+            let timeout = std::time::Duration::from_millis(1000);
+            bus.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", timeout).method_call(
+                "org.freedesktop.DBus",
+                "GetConnectionUnixUser",
+                (&*sender,),
+            )
+        })
+        .map(|(uid,): (u32,)| uid)
+        .map_err(|why| MethodErr::failed(&format!("failed to identify the caller: {}", why)))?;
+
+    if uid != 0 {
+        return Err(MethodErr::failed(&format!("{} may only be called by root", ctx.method())));
+    }
+
+    Ok(())
+}
+
+/// Applies a change to the overall progress, and signals the new progress if it has changed.
+fn track_progress(
+    shared_state: &SharedState,
+    dbus_tx: &UnboundedSender<SignalEvent>,
+    update: impl FnOnce(&mut Tracker),
+) {
+    let mut progress = shared_state.progress.lock().unwrap();
+    let previous = progress.snapshot();
+    update(&mut progress);
+    let current = progress.snapshot();
+
+    if (previous.percent, previous.stage) != (current.percent, current.stage) {
+        let _ = dbus_tx.send(SignalEvent::Progress(current));
+    }
+}
+
+fn track_apt_progress(
+    shared_state: &SharedState,
+    dbus_tx: &UnboundedSender<SignalEvent>,
+    event: &AptUpgradeEvent,
+) {
+    shared_state.apt_activity.store(chrono::Local::now().timestamp(), Ordering::SeqCst);
+
+    if let AptUpgradeEvent::Progress { percent } = *event {
+        track_progress(shared_state, dbus_tx, |progress| progress.installed(percent));
+    }
+}
+
+/// Reports apt and dpkg operations of the daemon which have stopped making progress.
+async fn watch_for_stalls(shared_state: Arc<SharedState>, dbus_tx: UnboundedSender<SignalEvent>) {
+    // The timeout is read once, so a change to it applies when the daemon is restarted.
+    let timeout = UpdatesConfig::load().stall_timeout;
+
+    if timeout == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
+
+    loop {
+        interval.tick().await;
+
+        let mut stall = shared_state.stall.lock().unwrap();
+
+        if shared_state.status.load(Ordering::SeqCst) == DaemonStatus::Inactive {
+            *stall = None;
+            continue;
+        }
+
+        let now = chrono::Local::now().timestamp();
+
+        let dpkg_activity = watchdog::dpkg_activity()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs() as i64);
+
+        let last_activity = shared_state.apt_activity.load(Ordering::SeqCst).max(dpkg_activity);
+        let idle = now.saturating_sub(last_activity).max(0) as u64;
+
+        if idle < timeout {
+            *stall = None;
+        } else if stall.is_none() {
+            *stall = watchdog::find_stall(idle);
+
+            if let Some(ref stall) = *stall {
+                let _ = dbus_tx.send(SignalEvent::Stalled(stall.clone()));
+            }
+        }
+    }
+}
+
 pub async fn upgrade_required() -> anyhow::Result<bool> {
     let (_, mut policies) = apt_cmd::AptCache::new().policy(&["pop-upgrade"]).await?;
 
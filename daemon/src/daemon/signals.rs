@@ -1,16 +1,24 @@
 use crate::{
+    health::HealthReport,
+    progress::Snapshot,
     recovery::{RecoveryError, RecoveryEvent},
-    release::{ReleaseError, UpgradeEvent},
+    release::{FetchProgress, ReleaseError, UpgradeEvent},
+    repair::RepairReport,
+    watchdog::Stall,
 };
 use apt_cmd::AptUpgradeEvent;
 use std::fmt::{self, Display, Formatter};
 
 // Signals supported by the daemon.
+pub const HEALTH_REPORT: &str = "HealthReport";
+
 pub const PACKAGE_FETCH_RESULT: &str = "PackageFetchResult";
+pub const PACKAGE_FETCH_RETRYING: &str = "PackageFetchRetrying";
 pub const PACKAGE_FETCHING: &str = "PackageFetching";
 pub const PACKAGE_FETCHED: &str = "PackageFetched";
 
 pub const PACKAGE_UPGRADE: &str = "PackageUpgrade";
+pub const PACKAGE_UPGRADE_RESULT: &str = "PackageUpgradeResult";
 
 pub const RECOVERY_DOWNLOAD_PROGRESS: &str = "RecoveryDownloadProgress";
 pub const RECOVERY_EVENT: &str = "RecoveryUpgradeEvent";
@@ -19,20 +27,38 @@ pub const RECOVERY_RESULT: &str = "RecoveryUpgradeResult";
 pub const RELEASE_EVENT: &str = "ReleaseUpgradeEvent";
 pub const RELEASE_RESULT: &str = "ReleaseUpgradeResult";
 
+pub const REPAIR_REPORT: &str = "RepairReport";
+
 pub const REPO_COMPAT_ERROR: &str = "RepoCompatError";
 
+pub const METERED_DOWNLOAD: &str = "MeteredDownload";
+
 pub const NO_CONNECTION: &str = "NoConnection";
 
+pub const PENDING_REMOVALS: &str = "PendingRemovals";
+
+pub const PROGRESS: &str = "Progress";
+
+pub const STALLED: &str = "Stalled";
+
 #[derive(Debug)]
 pub enum SignalEvent {
     FetchResult(Result<(), ReleaseError>),
-    Fetched(String, u32, u32),
+    Fetched(String, u32, u32, FetchProgress),
+    FetchRetrying(String, u32, String),
     Fetching(String),
+    HealthReport(HealthReport),
+    MeteredDownload(u64),
     NoConnection,
+    PackageUpgradeResult(Result<(), ReleaseError>),
+    PendingRemovals(Vec<String>),
+    Progress(Snapshot),
     RecoveryDownloadProgress(u64, u64),
     RecoveryUpgradeEvent(RecoveryEvent),
     RecoveryUpgradeResult(Result<(), RecoveryError>),
     ReleaseUpgradeEvent(UpgradeEvent),
+    RepairReport(RepairReport),
+    Stalled(Stall),
     Upgrade(AptUpgradeEvent),
 }
 
@@ -41,11 +67,27 @@ impl Display for SignalEvent {
         use self::SignalEvent::*;
         match self {
             FetchResult(result) => write!(fmt, "fetch result: {:?}", result),
-            Fetched(package, progress, total) => {
-                write!(fmt, "fetched {}/{}: {}", progress, total, package)
+            Fetched(package, progress, total, bytes) => write!(
+                fmt,
+                "fetched {}/{}: {} ({}/{} bytes)",
+                progress, total, package, bytes.fetched, bytes.total
+            ),
+            FetchRetrying(package, attempt, why) => {
+                write!(fmt, "retrying fetch of {} (attempt {}): {}", package, attempt, why)
             }
             Fetching(package) => write!(fmt, "fetching {}", package),
+            HealthReport(report) => {
+                write!(fmt, "health report: {}", <&'static str>::from(report.status()))
+            }
+            MeteredDownload(bytes) => write!(
+                fmt,
+                "awaiting approval to download {} bytes over a metered connection",
+                bytes
+            ),
             NoConnection => write!(fmt, "internet connection required, but not available"),
+            PackageUpgradeResult(result) => write!(fmt, "package upgrade result: {:?}", result),
+            PendingRemovals(packages) => write!(fmt, "awaiting approval to remove {:?}", packages),
+            Progress(snapshot) => write!(fmt, "progress: {}% ({})", snapshot.percent, snapshot.stage),
             RecoveryDownloadProgress(progress, total) => {
                 write!(fmt, "recovery download: {}/{} MiB", progress / 1024, total / 1024)
             }
@@ -56,6 +98,15 @@ impl Display for SignalEvent {
             ReleaseUpgradeEvent(event) => {
                 write!(fmt, "release upgrade: {}", <&'static str>::from(*event))
             }
+            RepairReport(report) => write!(fmt, "repair check: {}", report.id),
+            Stalled(stall) if stall.package.is_empty() => {
+                write!(fmt, "dpkg ({}) stalled for {} seconds", stall.pid, stall.idle)
+            }
+            Stalled(stall) => write!(
+                fmt,
+                "{} {} ({}) stalled for {} seconds",
+                stall.package, stall.script, stall.pid, stall.idle
+            ),
             Upgrade(event) => write!(fmt, "package upgrade: {}", event),
         }
     }
@@ -8,6 +8,8 @@ pub enum DaemonStatus {
     RecoveryUpgrade = 2,
     ReleaseUpgrade = 3,
     PackageUpgrade = 4,
+    BackgroundFetch = 5,
+    Repairing = 6,
 }
 
 unsafe impl bytemuck::NoUninit for DaemonStatus {}
@@ -20,6 +22,8 @@ impl From<DaemonStatus> for &'static str {
             DaemonStatus::RecoveryUpgrade => "upgrading recovery partition",
             DaemonStatus::ReleaseUpgrade => "upgrading distribution release",
             DaemonStatus::PackageUpgrade => "upgrading packages",
+            DaemonStatus::BackgroundFetch => "fetching package updates in the background",
+            DaemonStatus::Repairing => "repairing the system",
         }
     }
 }
@@ -1,23 +1,28 @@
+use crate::updates::PhasedUpdates;
 use anyhow::Context;
 use apt_cmd::{lock::apt_lock_wait, request::Request as AptRequest, AptGet};
 use async_shutdown::ShutdownManager as Shutdown;
 use std::collections::HashSet;
+use tokio::task::JoinHandle;
 
 pub enum ExtraPackages {
     Static(&'static [&'static str]),
     Dynamic(Vec<String>),
 }
 
+/// Fetches the URIs of the packages required for a full upgrade, as well as those of the extra
+/// `packages`, with the `phased` updates policy applied to apt.
 pub async fn fetch_uris(
     shutdown: Shutdown<()>,
     packages: Option<ExtraPackages>,
     dependencies: bool,
+    phased: PhasedUpdates,
 ) -> anyhow::Result<HashSet<AptRequest>> {
     let task = tokio::spawn(async move {
         apt_lock_wait().await;
 
-        let mut uris = AptGet::new()
-            .noninteractive()
+        let mut uris = phased
+            .apply(AptGet::new().noninteractive())
             .fetch_uris(&["full-upgrade"])
             .await
             .context("failed to exec `apt-get full-upgrade --print-uris`")?
@@ -40,8 +45,8 @@ pub async fn fetch_uris(
 
             apt_lock_wait().await;
 
-            let install_uris = AptGet::new()
-                .noninteractive()
+            let install_uris = phased
+                .apply(AptGet::new().noninteractive())
                 .fetch_uris(&args)
                 .await
                 .context("failed to exec `apt-get install --print-uris` or `apt-get download --print-uris`")?
@@ -55,6 +60,58 @@ pub async fn fetch_uris(
         Ok(uris)
     });
 
+    cancellable(shutdown, task).await
+}
+
+/// Fetches the URIs of the packages required to upgrade only the given packages, as well as
+/// those required to install the `extra` packages.
+pub async fn fetch_only_upgrade_uris(
+    shutdown: Shutdown<()>,
+    packages: Vec<String>,
+    extra: Vec<String>,
+    phased: PhasedUpdates,
+) -> anyhow::Result<HashSet<AptRequest>> {
+    let task = tokio::spawn(async move {
+        apt_lock_wait().await;
+
+        let mut args = vec!["install", "--only-upgrade"];
+        args.extend(packages.iter().map(String::as_str));
+
+        let mut uris = phased
+            .apply(AptGet::new().noninteractive())
+            .fetch_uris(&args)
+            .await
+            .context("failed to exec `apt-get install --only-upgrade --print-uris`")?
+            .context("failed to fetch package URIs from `apt-get install --only-upgrade`")?;
+
+        if extra.is_empty() {
+            return Ok(uris);
+        }
+
+        apt_lock_wait().await;
+
+        let mut args = vec!["install"];
+        args.extend(extra.iter().map(String::as_str));
+
+        let install_uris = phased
+            .apply(AptGet::new().noninteractive())
+            .fetch_uris(&args)
+            .await
+            .context("failed to exec `apt-get install --print-uris`")?
+            .context("failed to fetch package URIs from `apt-get install`")?;
+
+        uris.extend(install_uris);
+
+        Ok(uris)
+    });
+
+    cancellable(shutdown, task).await
+}
+
+async fn cancellable<T>(
+    shutdown: Shutdown<()>,
+    task: JoinHandle<anyhow::Result<T>>,
+) -> anyhow::Result<T> {
     let task = async move { task.await.unwrap() };
 
     let cancel = async move {
@@ -0,0 +1,84 @@
+//! Limits the bandwidth of the fetchers of the daemon.
+//!
+//! The client of async-fetcher cannot be throttled, so when a limit is configured, files are
+//! fetched by the throttle instead, one stream at a time. Each chunk of a response body is only
+//! taken once the limit allows it to be received, which holds back the connection once the
+//! buffers of its socket are full.
+
+use crate::network::NetworkConfig;
+use async_fetcher::FetchEvent;
+use std::{io, path::Path, sync::Mutex, time::Duration};
+use tokio::{fs::File, io::AsyncWriteExt, time::Instant};
+
+/// Limits the combined rate at which the bodies of every throttled fetch are received.
+pub struct Throttle {
+    client: reqwest::Client,
+    rate:   u64,
+    next:   Mutex<Instant>,
+}
+
+impl Throttle {
+    /// A throttle for the bandwidth limit configured by the administrator, if there is one.
+    pub fn configured() -> Option<Self> {
+        let rate = NetworkConfig::load().bandwidth_limit.filter(|&rate| rate != 0)?;
+
+        match crate::misc::http_client() {
+            Ok(client) => {
+                info!("limiting downloads to {} bytes per second", rate);
+                Some(Self::new(client, rate))
+            }
+            Err(why) => {
+                error!("failed to create the client of the bandwidth throttle: {}", why);
+                None
+            }
+        }
+    }
+
+    /// A throttle which allows `rate` bytes per second.
+    pub fn new(client: reqwest::Client, rate: u64) -> Self {
+        Self { client, rate, next: Mutex::new(Instant::now()) }
+    }
+
+    /// Fetches `uri` to `dest`, reporting the length of the file and the bytes received since
+    /// the last report as the events of async-fetcher.
+    pub async fn fetch(
+        &self,
+        uri: &str,
+        dest: &Path,
+        event: &(dyn Fn(FetchEvent) + Send + Sync),
+    ) -> io::Result<()> {
+        let mut response = self
+            .client
+            .get(uri)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?;
+
+        if let Some(length) = response.content_length() {
+            event(FetchEvent::ContentLength(length));
+        }
+
+        let mut file = File::create(dest).await?;
+
+        while let Some(chunk) = response.chunk().await.map_err(io::Error::other)? {
+            self.consume(chunk.len()).await;
+            file.write_all(&chunk).await?;
+            event(FetchEvent::Progress(chunk.len() as u64));
+        }
+
+        file.sync_all().await
+    }
+
+    /// Waits until `bytes` more may be received without exceeding the rate.
+    async fn consume(&self, bytes: usize) {
+        let deadline = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.rate as f64);
+            *next
+        };
+
+        tokio::time::sleep_until(deadline).await;
+    }
+}
@@ -0,0 +1,716 @@
+//! Verifies that the system is in a bootable, consistent state after an upgrade.
+
+use crate::system_environment::SystemEnvironment;
+use os_release::OsRelease;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+    process::Command,
+};
+use systemd_boot_conf::SystemdBootConf;
+
+/// Records the release that a release upgrade targeted, so that it may be verified on the
+/// first boot into the new release.
+pub const VERIFY_RELEASE_FILE: &str = "/var/lib/pop-upgrade/verify_release";
+
+/// Units which, if failed, will most likely leave the system unusable.
+const CRITICAL_UNITS: &[&str] = &[
+    "accounts-daemon.service",
+    "dbus.service",
+    "display-manager.service",
+    "gdm.service",
+    "gdm3.service",
+    "NetworkManager.service",
+    "polkit.service",
+    "systemd-journald.service",
+    "systemd-logind.service",
+    "systemd-udevd.service",
+];
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, FromPrimitive, PartialEq)]
+pub enum HealthStatus {
+    Ok = 0,
+    Warning = 1,
+    Failed = 2,
+}
+
+impl From<HealthStatus> for &'static str {
+    fn from(status: HealthStatus) -> Self {
+        match status {
+            HealthStatus::Ok => "ok",
+            HealthStatus::Warning => "warning",
+            HealthStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The outcome of an individual verification.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    pub check:  Box<str>,
+    pub status: HealthStatus,
+    pub detail: Box<str>,
+}
+
+impl HealthCheck {
+    fn new(check: &str, status: HealthStatus, detail: impl Into<Box<str>>) -> Self {
+        Self { check: check.into(), status, detail: detail.into() }
+    }
+}
+
+impl Display for HealthCheck {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}: {}", self.check, <&'static str>::from(self.status), self.detail)
+    }
+}
+
+/// A collection of verifications performed on the system.
+#[derive(Clone, Debug, Default)]
+pub struct HealthReport {
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    /// The most severe status among all of the checks.
+    pub fn status(&self) -> HealthStatus {
+        self.checks.iter().fold(HealthStatus::Ok, |acc, check| {
+            if check.status as u8 > acc as u8 {
+                check.status
+            } else {
+                acc
+            }
+        })
+    }
+
+    /// Convert the report into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> Vec<(String, u8, String)> {
+        self.checks
+            .into_iter()
+            .map(|check| (check.check.into(), check.status as u8, check.detail.into()))
+            .collect()
+    }
+
+    /// Reconstruct a report that was transferred over DBus.
+    pub fn from_dbus(checks: Vec<(String, u8, String)>) -> Self {
+        use num_traits::FromPrimitive;
+
+        let checks = checks
+            .into_iter()
+            .map(|(check, status, detail)| HealthCheck {
+                check:  check.into(),
+                status: HealthStatus::from_u8(status).unwrap_or(HealthStatus::Failed),
+                detail: detail.into(),
+            })
+            .collect();
+
+        Self { checks }
+    }
+}
+
+/// Verify the health of the system.
+///
+/// If a `target` release is given, `/etc/os-release` must report that release.
+pub fn verify(target: Option<&str>) -> HealthReport {
+    info!("verifying the health of the system");
+
+    let report = HealthReport {
+        checks: vec![
+            os_release(target),
+            dpkg_audit(),
+            apt_check(),
+            initramfs(),
+            boot_entries(),
+            failed_units(),
+            conffiles(),
+        ],
+    };
+
+    for check in &report.checks {
+        match check.status {
+            HealthStatus::Ok => info!("health check {}", check),
+            HealthStatus::Warning => warn!("health check {}", check),
+            HealthStatus::Failed => error!("health check {}", check),
+        }
+    }
+
+    report
+}
+
+/// Verify that the running and newest kernels will be able to boot the system.
+pub fn verify_bootable() -> HealthReport {
+    info!("verifying that the system is bootable");
+
+    let report = HealthReport { checks: vec![kernel_initrds(), luks_initrd(), boot_entries()] };
+
+    for check in &report.checks {
+        match check.status {
+            HealthStatus::Ok => info!("boot check {}", check),
+            HealthStatus::Warning => warn!("boot check {}", check),
+            HealthStatus::Failed => error!("boot check {}", check),
+        }
+    }
+
+    report
+}
+
+/// Record the release that should be verified on the next start of the daemon.
+pub fn schedule_verification(target: &str) -> std::io::Result<()> {
+    fs::write(VERIFY_RELEASE_FILE, target)
+}
+
+/// Take the release awaiting verification, if a release upgrade has completed.
+pub fn take_scheduled_verification() -> Option<String> {
+    let target = fs::read_to_string(VERIFY_RELEASE_FILE).ok()?;
+    let _ = fs::remove_file(VERIFY_RELEASE_FILE);
+    Some(target.trim().to_owned())
+}
+
+fn os_release(target: Option<&str>) -> HealthCheck {
+    const CHECK: &str = "os-release";
+
+    let release = match OsRelease::new() {
+        Ok(release) => release,
+        Err(why) => {
+            return HealthCheck::new(
+                CHECK,
+                HealthStatus::Failed,
+                format!("failed to read /etc/os-release: {}", why),
+            )
+        }
+    };
+
+    match target {
+        Some(target)
+            if !target.is_empty()
+                && target != release.version_id
+                && target != release.version_codename =>
+        {
+            HealthCheck::new(
+                CHECK,
+                HealthStatus::Failed,
+                format!("expected release {}, but found {}", target, release.version_id),
+            )
+        }
+        _ => HealthCheck::new(CHECK, HealthStatus::Ok, release.pretty_name),
+    }
+}
+
+fn dpkg_audit() -> HealthCheck {
+    const CHECK: &str = "dpkg-audit";
+
+    match Command::new("dpkg").arg("--audit").output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stdout = stdout.trim();
+            if output.status.success() && stdout.is_empty() {
+                HealthCheck::new(CHECK, HealthStatus::Ok, "no packages require attention")
+            } else {
+                HealthCheck::new(CHECK, HealthStatus::Failed, stdout)
+            }
+        }
+        Err(why) => HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("failed to spawn `dpkg --audit`: {}", why),
+        ),
+    }
+}
+
+fn apt_check() -> HealthCheck {
+    const CHECK: &str = "apt-check";
+
+    match Command::new("apt-get").arg("check").output() {
+        Ok(output) if output.status.success() => {
+            HealthCheck::new(CHECK, HealthStatus::Ok, "no broken dependencies")
+        }
+        Ok(output) => HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ),
+        Err(why) => HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("failed to spawn `apt-get check`: {}", why),
+        ),
+    }
+}
+
+fn initramfs() -> HealthCheck {
+    const CHECK: &str = "initramfs";
+
+    let kernels = installed_kernels();
+
+    if kernels.is_empty() {
+        return HealthCheck::new(CHECK, HealthStatus::Failed, "no kernels found in /boot");
+    }
+
+    let missing = kernels
+        .iter()
+        .filter(|version| !Path::new(&["/boot/initrd.img-", version].concat()).exists())
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        HealthCheck::new(CHECK, HealthStatus::Ok, kernels.join(", "))
+    } else {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("initramfs missing for kernels: {}", missing.join(", ")),
+        )
+    }
+}
+
+fn boot_entries() -> HealthCheck {
+    const CHECK: &str = "boot-entries";
+
+    if SystemEnvironment::detect() != SystemEnvironment::Efi
+        || !Path::new("/boot/efi/loader").exists()
+    {
+        return HealthCheck::new(CHECK, HealthStatus::Ok, "systemd-boot is not in use");
+    }
+
+    let conf = match SystemdBootConf::new("/boot/efi") {
+        Ok(conf) => conf,
+        Err(why) => {
+            return HealthCheck::new(
+                CHECK,
+                HealthStatus::Failed,
+                format!("failed to load systemd-boot configuration: {}", why),
+            )
+        }
+    };
+
+    // Entries must point to files which exist on the ESP.
+    let mut broken = Vec::new();
+    for entry in &conf.entries {
+        let files = std::iter::once(&*entry.linux).chain(entry.initrd.as_deref());
+        for file in files {
+            if !conf.efi_mount.join(file.trim_start_matches('/')).exists() {
+                broken.push(format!("{} ({})", entry.id, file));
+            }
+        }
+    }
+
+    let entries = conf
+        .entries
+        .iter()
+        .map(|entry| (&*entry.id, &*entry.linux, entry.options.join(" ")))
+        .collect::<Vec<_>>();
+
+    let current = link_version("/boot/vmlinuz");
+    let old = link_version("/boot/vmlinuz.old");
+
+    let mut unbootable = Vec::new();
+    for kernel in installed_kernels() {
+        let linked = if current.as_deref() == Some(&*kernel) {
+            Some("-current")
+        } else if old.as_deref() == Some(&*kernel) {
+            Some("-oldkern")
+        } else {
+            None
+        };
+
+        if !kernel_has_entry(&kernel, linked, &entries) {
+            unbootable.push(kernel);
+        }
+    }
+
+    let newest_unbootable =
+        installed_kernels().last().is_some_and(|newest| unbootable.contains(newest));
+
+    if !broken.is_empty() {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("entries reference missing files: {}", broken.join(", ")),
+        )
+    } else if newest_unbootable {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("kernels without a boot entry: {}", unbootable.join(", ")),
+        )
+    } else if !unbootable.is_empty() {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Warning,
+            format!("kernels without a boot entry: {}", unbootable.join(", ")),
+        )
+    } else {
+        HealthCheck::new(CHECK, HealthStatus::Ok, format!("{} entries", conf.entries.len()))
+    }
+}
+
+fn kernel_initrds() -> HealthCheck {
+    const CHECK: &str = "kernel-initrds";
+
+    let running = fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.trim().to_owned())
+        .ok();
+
+    let mut kernels = installed_kernels();
+    let newest = kernels.pop();
+
+    let missing = running
+        .iter()
+        .chain(newest.iter())
+        .filter(|version| Path::new(&["/boot/vmlinuz-", version].concat()).exists())
+        .filter(|version| !initrd_exists(version))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    if newest.is_none() {
+        HealthCheck::new(CHECK, HealthStatus::Failed, "no kernels found in /boot")
+    } else if missing.is_empty() {
+        let detail = fomat!(
+            "running: " (running.as_deref().unwrap_or("unknown"))
+            ", newest: " (newest.as_deref().unwrap_or_default())
+        );
+
+        HealthCheck::new(CHECK, HealthStatus::Ok, detail)
+    } else {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("initrd missing or empty for kernels: {}", missing.join(", ")),
+        )
+    }
+}
+
+/// On encrypted systems, the initrd must be able to unlock the devices backing the root
+/// file system.
+fn luks_initrd() -> HealthCheck {
+    const CHECK: &str = "luks-initrd";
+
+    let crypttab = fs::read_to_string("/etc/crypttab").unwrap_or_default();
+    let required = root_crypt_devices()
+        .into_iter()
+        .filter(|name| crypttab_names(&crypttab).contains(&name.as_str()))
+        .collect::<Vec<_>>();
+
+    if required.is_empty() {
+        return HealthCheck::new(CHECK, HealthStatus::Ok, "root is not encrypted");
+    }
+
+    let newest = match installed_kernels().pop() {
+        Some(newest) => newest,
+        None => return HealthCheck::new(CHECK, HealthStatus::Failed, "no kernels found in /boot"),
+    };
+
+    let initrd = ["/boot/initrd.img-", &newest].concat();
+
+    let listing = match Command::new("lsinitramfs").arg(&initrd).output() {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => {
+            return HealthCheck::new(
+                CHECK,
+                HealthStatus::Failed,
+                format!("unable to list the contents of {}", initrd),
+            )
+        }
+    };
+
+    if !lists_cryptsetup(&String::from_utf8_lossy(&listing)) {
+        return HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("cryptsetup is missing from {}", initrd),
+        );
+    }
+
+    let initrd_crypttab = match extract_initrd_crypttab(&initrd) {
+        Some(crypttab) => crypttab,
+        None => {
+            return HealthCheck::new(
+                CHECK,
+                HealthStatus::Warning,
+                format!("unable to read the crypttab from {}", initrd),
+            )
+        }
+    };
+
+    let included = crypttab_names(&initrd_crypttab);
+    let missing = required
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !included.contains(name))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Ok,
+            format!("{} unlocks {}", initrd, required.join(", ")),
+        )
+    } else {
+        HealthCheck::new(
+            CHECK,
+            HealthStatus::Failed,
+            format!("{} does not unlock: {}", initrd, missing.join(", ")),
+        )
+    }
+}
+
+fn failed_units() -> HealthCheck {
+    const CHECK: &str = "failed-units";
+
+    let output = match Command::new("systemctl")
+        .args(["--failed", "--plain", "--no-legend", "--no-pager"])
+        .output()
+    {
+        Ok(output) => output,
+        Err(why) => {
+            return HealthCheck::new(
+                CHECK,
+                HealthStatus::Warning,
+                format!("failed to spawn `systemctl --failed`: {}", why),
+            )
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let failed = parse_failed_units(&stdout);
+
+    if failed.is_empty() {
+        return HealthCheck::new(CHECK, HealthStatus::Ok, "no failed units");
+    }
+
+    let status = if failed.iter().any(|unit| CRITICAL_UNITS.contains(unit)) {
+        HealthStatus::Failed
+    } else {
+        HealthStatus::Warning
+    };
+
+    HealthCheck::new(CHECK, status, failed.join(", "))
+}
+
+fn conffiles() -> HealthCheck {
+    const CHECK: &str = "conffiles";
+
+    let pending = crate::conffiles::pending();
+
+    if pending.is_empty() {
+        return HealthCheck::new(CHECK, HealthStatus::Ok, "no configuration files to review");
+    }
+
+    let paths: Vec<&str> = pending.iter().map(|conffile| conffile.path.as_str()).collect();
+
+    HealthCheck::new(
+        CHECK,
+        HealthStatus::Warning,
+        format!(
+            "new versions of configuration files to review with `pop-upgrade conffiles`: {}",
+            paths.join(", ")
+        ),
+    )
+}
+
+/// Kernel versions which have been installed to `/boot`, sorted from oldest to newest.
+fn installed_kernels() -> Vec<String> {
+    let mut kernels = fs::read_dir("/boot")
+        .map(|dir| {
+            dir.flatten()
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter_map(|name| kernel_version(&name).map(String::from))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    kernels.sort_by(|a, b| human_sort::compare(a, b));
+    kernels
+}
+
+/// The kernel version that a `/boot/vmlinuz` symlink points to.
+fn link_version(link: &str) -> Option<String> {
+    let target = fs::read_link(link).ok()?;
+    let name = target.file_name()?.to_str()?;
+    kernel_version(name).map(String::from)
+}
+
+fn initrd_exists(version: &str) -> bool {
+    fs::metadata(["/boot/initrd.img-", version].concat()).is_ok_and(|meta| meta.len() != 0)
+}
+
+/// Names of the device mapper crypt devices which the root file system resides on.
+fn root_crypt_devices() -> Vec<String> {
+    let root = match proc_mounts::MountList::new()
+        .ok()
+        .and_then(|mounts| mounts.get_mount_by_dest("/").map(|mount| mount.source.clone()))
+    {
+        Some(root) => root,
+        None => return Vec::new(),
+    };
+
+    Command::new("lsblk")
+        .args(["--inverse", "--noheadings", "--raw", "--output", "NAME,TYPE"])
+        .arg(&root)
+        .output()
+        .map(|output| {
+            parse_crypt_devices(&String::from_utf8_lossy(&output.stdout))
+                .into_iter()
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Extracts the initrd to a temporary directory to read the crypttab that it was built with.
+fn extract_initrd_crypttab(initrd: &str) -> Option<String> {
+    let tempdir = tempfile::tempdir().ok()?;
+
+    let status = Command::new("unmkinitramfs").arg(initrd).arg(tempdir.path()).status().ok()?;
+
+    if !status.success() {
+        return None;
+    }
+
+    ["cryptroot/crypttab", "main/cryptroot/crypttab"]
+        .iter()
+        .find_map(|path| fs::read_to_string(tempdir.path().join(path)).ok())
+}
+
+fn parse_crypt_devices(lsblk: &str) -> Vec<&str> {
+    lsblk
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_ascii_whitespace();
+            let name = fields.next()?;
+            if fields.next()? == "crypt" {
+                Some(name)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn crypttab_names(crypttab: &str) -> Vec<&str> {
+    crypttab
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .collect()
+}
+
+fn lists_cryptsetup(listing: &str) -> bool {
+    listing.lines().any(|path| path.ends_with("sbin/cryptsetup"))
+}
+
+fn kernel_version(file_name: &str) -> Option<&str> {
+    file_name.strip_prefix("vmlinuz-").filter(|version| !version.is_empty())
+}
+
+/// Checks if any of the given entries (id, linux, options) will boot the kernel.
+///
+/// Entries created by kernelstub are named after the `/boot/vmlinuz` and `/boot/vmlinuz.old`
+/// links rather than the version, so the `linked` suffix is used to match those.
+fn kernel_has_entry(kernel: &str, linked: Option<&str>, entries: &[(&str, &str, String)]) -> bool {
+    entries.iter().any(|(id, linux, options)| {
+        id.contains(kernel)
+            || linux.contains(kernel)
+            || options.contains(kernel)
+            || linked.is_some_and(|suffix| id.ends_with(suffix))
+    })
+}
+
+fn parse_failed_units(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .filter(|unit| unit.contains('.'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_versions() {
+        assert_eq!(
+            Some("6.9.3-76060903-generic"),
+            kernel_version("vmlinuz-6.9.3-76060903-generic")
+        );
+        assert_eq!(None, kernel_version("vmlinuz"));
+        assert_eq!(None, kernel_version("vmlinuz.old"));
+        assert_eq!(None, kernel_version("initrd.img-6.9.3-76060903-generic"));
+    }
+
+    #[test]
+    fn failed_units() {
+        const OUTPUT: &str = "gdm.service         loaded failed failed GNOME Display Manager\n\
+                              snapd.seeded.service loaded failed failed Wait until snapd is \
+                              fully seeded\n";
+
+        assert_eq!(vec!["gdm.service", "snapd.seeded.service"], parse_failed_units(OUTPUT));
+        assert!(parse_failed_units("").is_empty());
+    }
+
+    #[test]
+    fn kernelstub_entries() {
+        let entries = vec![
+            ("Pop_OS-current", "/EFI/Pop_OS-abc/vmlinuz.efi", String::from("root=UUID=abc")),
+            ("Pop_OS-oldkern", "/EFI/Pop_OS-abc/vmlinuz-previous.efi", String::new()),
+        ];
+
+        assert!(kernel_has_entry("6.9.3-generic", Some("-current"), &entries));
+        assert!(kernel_has_entry("6.8.0-generic", Some("-oldkern"), &entries));
+        assert!(!kernel_has_entry("6.2.0-generic", None, &entries));
+    }
+
+    #[test]
+    fn kernel_install_entries() {
+        let entries = vec![(
+            "0123456789abcdef-6.9.3-generic",
+            "/0123456789abcdef/6.9.3-generic/linux",
+            String::new(),
+        )];
+
+        assert!(kernel_has_entry("6.9.3-generic", None, &entries));
+        assert!(!kernel_has_entry("6.8.0-generic", None, &entries));
+    }
+
+    #[test]
+    fn crypt_devices() {
+        const LSBLK: &str = "data-root lvm\ncryptdata crypt\nnvme0n1p3 part\nnvme0n1 disk\n";
+        assert_eq!(vec!["cryptdata"], parse_crypt_devices(LSBLK));
+        assert!(parse_crypt_devices("nvme0n1p2 part\nnvme0n1 disk\n").is_empty());
+    }
+
+    #[test]
+    fn crypttab() {
+        const CRYPTTAB: &str = "# comment\n\
+                                cryptdata UUID=ed9e7007 none luks\n\
+                                cryptswap UUID=ed9e7007 /dev/urandom swap,plain\n";
+
+        assert_eq!(vec!["cryptdata", "cryptswap"], crypttab_names(CRYPTTAB));
+    }
+
+    #[test]
+    fn initramfs_listing() {
+        assert!(lists_cryptsetup("usr\nusr/sbin\nusr/sbin/cryptsetup\n"));
+        assert!(!lists_cryptsetup("usr\nusr/sbin\nusr/sbin/cryptsetup-reencrypt\n"));
+    }
+
+    #[test]
+    fn report_status() {
+        let mut report = HealthReport::default();
+        assert_eq!(HealthStatus::Ok, report.status());
+
+        report.checks.push(HealthCheck::new("a", HealthStatus::Warning, ""));
+        report.checks.push(HealthCheck::new("b", HealthStatus::Ok, ""));
+        assert_eq!(HealthStatus::Warning, report.status());
+
+        report.checks.push(HealthCheck::new("c", HealthStatus::Failed, ""));
+        assert_eq!(HealthStatus::Failed, report.status());
+
+        let report = HealthReport::from_dbus(report.into_dbus());
+        assert_eq!(HealthStatus::Failed, report.status());
+        assert_eq!(3, report.checks.len());
+    }
+}
@@ -0,0 +1,411 @@
+//! A timeline of the changes made to the system's packages.
+//!
+//! The transactions of apt are parsed from `/var/log/apt/history.log`, and the logs that
+//! logrotate keeps of it, which are gzipped after the first rotation. They are merged with the
+//! jobs of the daemon, which it records as it completes them. Only the latest jobs are kept.
+
+use crate::VAR_LIB_DIR;
+use chrono::{Local, NaiveDateTime, TimeZone};
+use const_format::concatcp;
+use flate2::read::GzDecoder;
+use num_traits::FromPrimitive;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+pub const JOBS: &str = concatcp!(VAR_LIB_DIR, "/jobs");
+
+/// The number of jobs which are kept, after which the oldest jobs are dropped.
+const MAX_JOBS: usize = 500;
+
+const APT_LOGS: &str = "/var/log/apt";
+
+/// Where an entry of the history comes from.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Deserialize, FromPrimitive, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Apt = 0,
+    Daemon = 1,
+}
+
+impl Display for Source {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Source::Apt => "apt",
+            Source::Daemon => "pop-upgrade",
+        })
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Deserialize, FromPrimitive, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Install = 0,
+    Upgrade = 1,
+    Downgrade = 2,
+    Reinstall = 3,
+    Remove = 4,
+    Purge = 5,
+}
+
+impl Action {
+    fn from_key(key: &str) -> Option<Self> {
+        let action = match key {
+            "Install" => Action::Install,
+            "Upgrade" => Action::Upgrade,
+            "Downgrade" => Action::Downgrade,
+            "Reinstall" => Action::Reinstall,
+            "Remove" => Action::Remove,
+            "Purge" => Action::Purge,
+            _ => return None,
+        };
+
+        Some(action)
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Action::Install => "install",
+            Action::Upgrade => "upgrade",
+            Action::Downgrade => "downgrade",
+            Action::Reinstall => "reinstall",
+            Action::Remove => "remove",
+            Action::Purge => "purge",
+        })
+    }
+}
+
+/// A change to a package, and its versions before and after it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PackageChange {
+    /// The name of the package, qualified by its architecture.
+    pub package: String,
+    pub action:  Action,
+    pub from:    Option<String>,
+    pub to:      Option<String>,
+}
+
+impl PackageChange {
+    /// Parses an entry of a list of packages from apt's history, such as
+    /// `libssl3:amd64 (3.0.2-0ubuntu1.10, 3.0.2-0ubuntu1.12)`.
+    fn parse(action: Action, entry: &str) -> Option<Self> {
+        let (package, versions) = entry.split_once(" (")?;
+        let mut versions = versions.trim_end_matches(')').split(", ").map(String::from);
+        let first = versions.next();
+
+        let (from, to) = match action {
+            Action::Install | Action::Reinstall => (None, first),
+            Action::Remove | Action::Purge => (first, None),
+            Action::Upgrade | Action::Downgrade => (first, versions.next()),
+        };
+
+        Some(Self { package: package.to_owned(), action, from, to })
+    }
+}
+
+/// A transaction of apt, or a job of the daemon.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    /// When it began, in seconds since the Unix epoch.
+    pub time:        i64,
+    pub source:      Source,
+    /// The command line of apt, or a description of the daemon's job.
+    pub description: String,
+    /// Why it failed, which is empty if it succeeded.
+    pub error:       String,
+    #[serde(default)]
+    pub changes:     Vec<PackageChange>,
+}
+
+pub type DbusEntry = (i64, u8, String, String, Vec<(String, u8, String, String)>);
+
+impl HistoryEntry {
+    /// Convert the entry into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> DbusEntry {
+        let changes = self
+            .changes
+            .into_iter()
+            .map(|change| {
+                let from = change.from.unwrap_or_default();
+                (change.package, change.action as u8, from, change.to.unwrap_or_default())
+            })
+            .collect();
+
+        (self.time, self.source as u8, self.description, self.error, changes)
+    }
+
+    /// Reconstruct an entry that was transferred over DBus.
+    pub fn from_dbus((time, source, description, error, changes): DbusEntry) -> Self {
+        let version = |version: String| if version.is_empty() { None } else { Some(version) };
+
+        let changes = changes
+            .into_iter()
+            .filter_map(|(package, action, from, to)| {
+                Some(PackageChange {
+                    package,
+                    action: Action::from_u8(action)?,
+                    from: version(from),
+                    to: version(to),
+                })
+            })
+            .collect();
+
+        Self {
+            time,
+            source: Source::from_u8(source).unwrap_or(Source::Apt),
+            description,
+            error,
+            changes,
+        }
+    }
+}
+
+/// Parses the transactions of a history log of apt.
+pub fn parse_apt_history(history: &str) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+
+    for line in history.lines() {
+        let Some((key, value)) = line.split_once(": ") else {
+            continue;
+        };
+
+        if key == "Start-Date" {
+            let time = NaiveDateTime::parse_from_str(value, "%Y-%m-%d  %H:%M:%S")
+                .ok()
+                .and_then(|date| Local.from_local_datetime(&date).earliest())
+                .map_or(0, |date| date.timestamp());
+
+            entries.push(HistoryEntry {
+                time,
+                source: Source::Apt,
+                description: String::new(),
+                error: String::new(),
+                changes: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let Some(entry) = entries.last_mut() else {
+            continue;
+        };
+
+        match key {
+            "Commandline" => entry.description = value.to_owned(),
+            "Error" => entry.error = value.to_owned(),
+            _ => {
+                if let Some(action) = Action::from_key(key) {
+                    let changes = value.split("), ");
+                    entry.changes.extend(changes.filter_map(|c| PackageChange::parse(action, c)));
+                }
+            }
+        }
+    }
+
+    entries
+}
+
+/// Every transaction of apt which is still logged, from oldest to newest.
+pub fn apt_history() -> Vec<HistoryEntry> {
+    let mut logs: Vec<(u32, String)> = match fs::read_dir(APT_LOGS) {
+        Ok(dir) => dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter_map(|name| Some((rotation(&name)?, name)))
+            .collect(),
+        Err(why) => {
+            warn!("failed to read {}: {}", APT_LOGS, why);
+            return Vec::new();
+        }
+    };
+
+    // The oldest logs are those which have been rotated the most times.
+    logs.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+    logs.into_iter()
+        .filter_map(|(_, name)| {
+            let path = Path::new(APT_LOGS).join(&name);
+            read_log(&path)
+                .map_err(|why| warn!("failed to read {}: {}", path.display(), why))
+                .ok()
+        })
+        .flat_map(|history| parse_apt_history(&history))
+        .collect()
+}
+
+/// How many times a history log has been rotated, if it is one.
+fn rotation(name: &str) -> Option<u32> {
+    let rotation = name.strip_prefix("history.log")?;
+
+    if rotation.is_empty() {
+        return Some(0);
+    }
+
+    rotation.strip_prefix('.')?.trim_end_matches(".gz").parse().ok()
+}
+
+fn read_log(path: &Path) -> io::Result<String> {
+    let mut log = String::new();
+    let file = File::open(path)?;
+
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        GzDecoder::new(file).read_to_string(&mut log)?;
+    } else {
+        io::BufReader::new(file).read_to_string(&mut log)?;
+    }
+
+    Ok(log)
+}
+
+/// Records a job of the daemon once it is complete, and why it failed, if it did.
+pub fn record_job(time: i64, description: String, error: Option<String>) {
+    let entry = HistoryEntry {
+        time,
+        source: Source::Daemon,
+        description,
+        error: error.unwrap_or_default(),
+        changes: Vec::new(),
+    };
+
+    let record = || -> io::Result<()> {
+        let mut line = serde_json::to_string(&entry).map_err(io::Error::from)?;
+        line.push('\n');
+
+        fs::create_dir_all(VAR_LIB_DIR)?;
+
+        let jobs = match fs::read_to_string(JOBS) {
+            Ok(jobs) => jobs,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(why) => return Err(why),
+        };
+
+        if jobs.lines().count() < MAX_JOBS {
+            return OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(JOBS)?
+                .write_all(line.as_bytes());
+        }
+
+        let temporary = [JOBS, ".tmp"].concat();
+        fs::write(&temporary, [latest_jobs(&jobs, MAX_JOBS - 1), &line].concat())?;
+        fs::rename(&temporary, JOBS)
+    };
+
+    if let Err(why) = record() {
+        warn!("failed to record job in {}: {}", JOBS, why);
+    }
+}
+
+/// The last `count` lines of the recorded jobs.
+fn latest_jobs(jobs: &str, count: usize) -> &str {
+    let skip = jobs.lines().count().saturating_sub(count);
+
+    if skip == 0 {
+        return jobs;
+    }
+
+    jobs.match_indices('\n').nth(skip - 1).map_or("", |(position, _)| &jobs[position + 1..])
+}
+
+/// The jobs which the daemon has recorded.
+pub fn jobs() -> Vec<HistoryEntry> {
+    match fs::read_to_string(JOBS) {
+        Ok(jobs) => jobs.lines().filter_map(|line| serde_json::from_str(line).ok()).collect(),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(why) => {
+            warn!("failed to read {}: {}", JOBS, why);
+            Vec::new()
+        }
+    }
+}
+
+/// The transactions of apt and the jobs of the daemon since a time, from oldest to newest.
+pub fn timeline(since: i64) -> Vec<HistoryEntry> {
+    let mut timeline = apt_history();
+    timeline.extend(jobs());
+    timeline.retain(|entry| entry.time >= since);
+    timeline.sort_by_key(|entry| entry.time);
+    timeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apt_history_log() {
+        let history = "
+Start-Date: 2023-10-20  10:00:00
+Commandline: apt-get install htop
+Requested-By: system76 (1000)
+Install: htop:amd64 (3.0.5-7build2)
+End-Date: 2023-10-20  10:00:02
+
+Start-Date: 2023-10-24  09:12:01
+Commandline: apt-get --show-progress full-upgrade
+Install: linux-image-6.5.0-10-generic:amd64 (6.5.0-10.10, automatic)
+Upgrade: libssl3:amd64 (3.0.2-0ubuntu1.10, 3.0.2-0ubuntu1.12), mesa-va-drivers:amd64 \
+(23.0.4-0ubuntu1~22.04.1, 23.2.1-1ubuntu3~22.04.1)
+Remove: firefox:amd64 (1:1snap1-0ubuntu2)
+Error: Sub-process /usr/bin/dpkg returned an error code (1)
+End-Date: 2023-10-24  09:13:40
+";
+
+        let entries = parse_apt_history(history);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].description, "apt-get install htop");
+        assert_eq!(entries[0].error, "");
+        assert_eq!(entries[0].changes, [PackageChange {
+            package: "htop:amd64".into(),
+            action:  Action::Install,
+            from:    None,
+            to:      Some("3.0.5-7build2".into()),
+        }]);
+
+        let upgrade = &entries[1];
+        assert!(upgrade.time > entries[0].time);
+        assert_eq!(upgrade.error, "Sub-process /usr/bin/dpkg returned an error code (1)");
+        assert_eq!(upgrade.changes.len(), 4);
+        assert_eq!(upgrade.changes[0].to.as_deref(), Some("6.5.0-10.10"));
+        assert_eq!(upgrade.changes[2], PackageChange {
+            package: "mesa-va-drivers:amd64".into(),
+            action:  Action::Upgrade,
+            from:    Some("23.0.4-0ubuntu1~22.04.1".into()),
+            to:      Some("23.2.1-1ubuntu3~22.04.1".into()),
+        });
+        assert_eq!(upgrade.changes[3].action, Action::Remove);
+        assert_eq!(upgrade.changes[3].from.as_deref(), Some("1:1snap1-0ubuntu2"));
+
+        let entry = HistoryEntry::from_dbus(upgrade.clone().into_dbus());
+        assert_eq!(&entry, upgrade);
+    }
+
+    #[test]
+    fn rotated_logs() {
+        assert_eq!(rotation("history.log"), Some(0));
+        assert_eq!(rotation("history.log.1"), Some(1));
+        assert_eq!(rotation("history.log.12.gz"), Some(12));
+        assert_eq!(rotation("term.log.1.gz"), None);
+        assert_eq!(rotation("history.logs"), None);
+    }
+
+    #[test]
+    fn latest_jobs_kept() {
+        let jobs = "{\"time\":1}\n{\"time\":2}\n{\"time\":3}\n";
+
+        assert_eq!(latest_jobs(jobs, 2), "{\"time\":2}\n{\"time\":3}\n");
+        assert_eq!(latest_jobs(jobs, 3), jobs);
+        assert_eq!(latest_jobs(jobs, 5), jobs);
+        assert_eq!(latest_jobs(jobs, 0), "");
+    }
+}
@@ -30,15 +30,33 @@ pub mod checksum;
 /// Features specific to the client for the upgrade daemon
 pub mod client;
 
+/// Configuration files that upgrades left to be reviewed
+pub mod conffiles;
+
 /// Features specific to the upgrade daemon
 pub mod daemon;
 
+/// Verification of the system's health after an upgrade
+pub mod health;
+
+/// A timeline of package changes, from apt's logs and the daemon's jobs
+pub mod history;
+
 /// Functions for determining when the OS was installed
 pub mod install;
 
 /// Miscellaneous functions used throughout the library.
 pub mod misc;
 
+/// The state of the network connection, and the policy for downloads over it
+pub mod network;
+
+/// Fetching of updates in the background while the system is idle
+pub mod predownload;
+
+/// Overall progress of the daemon's tasks, weighted by stage
+pub mod progress;
+
 /// Functions for upgrading the recovery partition
 pub mod recovery;
 
@@ -54,15 +72,27 @@ pub mod release_architecture;
 /// Functions for repairing the OS
 pub mod repair;
 
+/// When the daemon installs package updates by itself
+pub mod schedule;
+
 /// Signal-handling capabilities for the daemon.
 pub mod sighandler;
 
 /// Determine if the system is in legacy BIOS or EFI mode.
 pub mod system_environment;
 
+/// The last package update transaction, which may be undone
+pub mod transaction;
+
 /// Ubuntu versions
 pub mod ubuntu_version;
 
+/// Which package updates are fetched and installed
+pub mod updates;
+
+/// Detects apt and dpkg operations which have stalled
+pub mod watchdog;
+
 mod external;
 mod fetch;
 mod gnome_extensions;
@@ -77,6 +107,7 @@ pub const DEVELOPMENT_RELEASE_FILE: &str = "/etc/pop-upgrade/devel";
 
 pub const VAR_LIB_DIR: &str = "/var/lib/pop-upgrade";
 pub const TRANSITIONAL_SNAPS: &str = "/var/lib/pop-upgrade/transitional_snaps";
+pub const REMOVED_PACKAGES: &str = "/var/lib/pop-upgrade/removed_packages";
 pub const RESTART_SCHEDULED: &str = "/var/lib/pop-upgrade/restarting";
 
 pub fn development_releases_enabled() -> bool { Path::new(DEVELOPMENT_RELEASE_FILE).exists() }
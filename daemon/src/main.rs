@@ -66,9 +66,6 @@ async fn main() {
     // Ensure file system caches are synced to prevent recovery ISO download corruption.
     rustix::fs::sync();
 
-    // Fixes a panic in `reqwest::Client::new`
-    wait_for_systemd_resolvd().await;
-
     // Service shall not run in a live environment.
     if Path::new("/cdrom/casper/filesystem.squashfs").exists() {
         exit(0);
@@ -76,6 +73,10 @@ async fn main() {
 
     let _ = setup_logging(::log::LevelFilter::Debug);
 
+    let conffile = clap::Arg::new("PATH")
+        .help("the new configuration file: IE: `/etc/ssh/sshd_config.ucf-dist`")
+        .required(true);
+
     let clap = clap::Command::new("pop-upgrade")
         .about("Pop!_OS Upgrade Utility")
         .subcommand_required(true)
@@ -84,6 +85,10 @@ async fn main() {
             clap::Command::new("cancel")
                 .about("cancels any process which is currently in progress"),
         )
+        .subcommand(
+            clap::Command::new("check-boot")
+                .about("verify that the installed kernels are able to boot the system"),
+        )
         .subcommand(
             clap::Command::new("daemon")
                 .about("launch a daemon for integration with control centers like GNOME's"),
@@ -148,6 +153,20 @@ async fn main() {
                 .subcommand(
                     clap::Command::new("update")
                         .about("fetch the latest updates for the current release")
+                        .arg(
+                            clap::Arg::new("list")
+                                .help("list pending updates and their changes, without fetching them")
+                                .short('l')
+                                .long("list")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("undo")
+                                .help("undo the last update, by installing the previous versions")
+                                .long("undo")
+                                .conflicts_with_all(["list", "download-only", "offline"])
+                                .action(clap::ArgAction::SetTrue),
+                        )
                         .arg(
                             clap::Arg::new("download-only")
                                 .help(
@@ -156,17 +175,98 @@ async fn main() {
                                 .short('d')
                                 .long("download-only")
                                 .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("offline")
+                                .help("fetch updates, and install them when the system restarts")
+                                .long("offline")
+                                .conflicts_with("download-only")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("security-only")
+                                .help("only fetch and install updates that fix security issues")
+                                .long("security-only")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("include")
+                                .help("only update packages matching this glob; may be repeated")
+                                .long("include")
+                                .value_name("GLOB")
+                                .action(clap::ArgAction::Append),
+                        )
+                        .arg(
+                            clap::Arg::new("exclude")
+                                .help("do not update packages matching this glob; may be repeated")
+                                .long("exclude")
+                                .value_name("GLOB")
+                                .action(clap::ArgAction::Append),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("offline-update")
+                        .about("show if updates will be installed when the system restarts")
+                        .subcommand(
+                            clap::Command::new("cancel")
+                                .about("do not install the updates when the system restarts"),
                         ),
                 )
+                .subcommand(
+                    clap::Command::new("upgrade-packages")
+                        .about("upgrade the system using the packages which have been fetched"),
+                )
                 .subcommand(
                     clap::Command::new("refresh")
                         .about("refresh the existing OS (requires recovery partition)")
                         .subcommand(clap::Command::new("disable"))
                         .subcommand(clap::Command::new("enable")),
                 )
+                .subcommand(
+                    clap::Command::new("reinstall-removed")
+                        .about("reinstall packages which were removed by a release upgrade")
+                        .arg(
+                            clap::Arg::new("PACKAGES")
+                                .help("the packages to reinstall, instead of being prompted")
+                                .num_args(1..),
+                        ),
+                )
                 .subcommand(
                     clap::Command::new("repair")
-                        .about("search for issues in the system, and repair them"),
+                        .about("search for issues in the system, and repair them")
+                        .arg(
+                            clap::Arg::new("list")
+                                .help("list the checks which can be applied")
+                                .long("list")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("dry-run")
+                                .help("report issues without fixing them")
+                                .long("dry-run")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            clap::Arg::new("only")
+                                .help("apply only the check with this ID; may be repeated")
+                                .long("only")
+                                .value_name("ID")
+                                .action(clap::ArgAction::Append),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("verify")
+                        .about("verify that the system is healthy after an upgrade")
+                        .arg(
+                            clap::Arg::new("RELEASE")
+                                .help("the release version which should be installed: IE: `24.04`"),
+                        )
+                        .arg(
+                            clap::Arg::new("last")
+                                .help("show the last report generated by the daemon")
+                                .long("last")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
                 )
                 .subcommand(
                     clap::Command::new("upgrade")
@@ -184,9 +284,90 @@ async fn main() {
                         ),
                 ),
         )
+        .subcommand(
+            clap::Command::new("conffiles")
+                .about("review the new configuration files left behind by updates (requires root)")
+                .arg(
+                    clap::Arg::new("diff")
+                        .help("show the changes that each new configuration file makes")
+                        .short('d')
+                        .long("diff")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .subcommand(
+                    clap::Command::new("accept")
+                        .about("replace the active file with the new configuration file")
+                        .arg(conffile.clone()),
+                )
+                .subcommand(
+                    clap::Command::new("keep")
+                        .about("keep the active file, and discard the new configuration file")
+                        .arg(conffile.clone()),
+                )
+                .subcommand(
+                    clap::Command::new("merge")
+                        .about("apply the changes of the new configuration file to the active file")
+                        .arg(conffile.clone()),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("history")
+                .about("show the changes made to packages, by apt and by the daemon")
+                .arg(
+                    clap::Arg::new("packages")
+                        .help("show every package that each transaction changed")
+                        .short('p')
+                        .long("packages")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    clap::Arg::new("since")
+                        .help("only show changes made since this date: IE: `2023-10-24`")
+                        .long("since")
+                        .value_name("DATE"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("schedule")
+                .about("show or set when the daemon installs package updates by itself")
+                .subcommand(
+                    clap::Command::new("window")
+                        .about("install updates within a weekly maintenance window")
+                        .arg(
+                            clap::Arg::new("DAYS")
+                                .help("days of the week, separated by commas: IE: `sat,sun`")
+                                .required(true),
+                        )
+                        .arg(
+                            clap::Arg::new("START")
+                                .help("time of day that the window starts at: IE: `02:00`")
+                                .required(true),
+                        )
+                        .arg(
+                            clap::Arg::new("END")
+                                .help("time of day that the window ends at: IE: `04:00`")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("on-shutdown")
+                        .about("stage fetched updates at shutdown, to install at the next startup"),
+                )
+                .subcommand(
+                    clap::Command::new("disable").about("only install updates when requested"),
+                ),
+        )
         .subcommand(clap::Command::new("status").about("get the status of the pop upgrade daemon"));
 
-    if main_(&clap.get_matches()).await.is_err() {
+    let matches = clap.get_matches();
+
+    // The offline upgrade checks the boot configuration before the network is available.
+    if matches.subcommand_name() != Some("check-boot") {
+        // Fixes a panic in `reqwest::Client::new`
+        wait_for_systemd_resolvd().await;
+    }
+
+    if main_(&matches).await.is_err() {
         exit(1);
     }
 }
@@ -196,6 +377,7 @@ async fn main_(matches: &clap::ArgMatches) -> anyhow::Result<()> {
 
     match matches.subcommand() {
         Some(("cancel", _)) => Client::new()?.cancel()?,
+        Some(("check-boot", _)) => cli::check_boot()?,
         Some(("daemon", _)) => Daemon::init().await?,
         Some((other, matches)) => {
             let mut client = Client::new()?;
@@ -222,8 +404,11 @@ async fn main_(matches: &clap::ArgMatches) -> anyhow::Result<()> {
             }
 
             let func = match other {
+                "conffiles" => Client::conffiles,
+                "history" => Client::history,
                 "recovery" => Client::recovery,
                 "release" => Client::release,
+                "schedule" => Client::schedule,
                 "status" => Client::status,
                 _ => unreachable!(),
             };
@@ -1,6 +1,13 @@
 use anyhow::Context;
-use std::{fs, io, path::Path, time::Duration};
-use tokio::fs::{copy, File};
+use apt_cmd::AptUpgradeEvent;
+use futures::{Stream, StreamExt};
+use std::{fs, io, path::Path, pin::Pin, time::Duration};
+use tokio::{
+    fs::{copy, File},
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+};
+use tokio_stream::wrappers::LinesStream;
 
 pub fn http_client() -> Result<reqwest::Client, reqwest::Error> {
     reqwest::ClientBuilder::new()
@@ -20,6 +27,39 @@ pub fn apt_get() -> apt_cmd::AptGet {
         .allow_downgrades()
 }
 
+/// The events of an upgrade, as `AptGet::stream_upgrade` returns them.
+pub type UpgradeEvents = Pin<Box<dyn Stream<Item = AptUpgradeEvent> + Send>>;
+
+/// Upgrades only the given packages, instead of every upgradable package.
+pub async fn stream_only_upgrade(
+    mut apt_get: apt_cmd::AptGet,
+    packages: &[String],
+) -> io::Result<(Child, UpgradeEvents)> {
+    apt_get.args(["install", "--only-upgrade"]).args(packages);
+    stream_events(apt_get).await
+}
+
+/// Installs the given packages, which may be paths to packages or specific versions.
+pub async fn stream_install(
+    mut apt_get: apt_cmd::AptGet,
+    packages: &[String],
+) -> io::Result<(Child, UpgradeEvents)> {
+    apt_get.arg("install").args(packages);
+    stream_events(apt_get).await
+}
+
+async fn stream_events(mut apt_get: apt_cmd::AptGet) -> io::Result<(Child, UpgradeEvents)> {
+    apt_get.arg("--show-progress");
+
+    let (child, stdout) = apt_get.spawn_with_stdout().await?;
+
+    let events = LinesStream::new(BufReader::new(stdout).lines()).filter_map(|line| async move {
+        line.ok().and_then(|line| line.parse::<AptUpgradeEvent>().ok())
+    });
+
+    Ok((child, Box::pin(events)))
+}
+
 pub async fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
     File::create(&path).await.map_err(|why| {
         io::Error::new(
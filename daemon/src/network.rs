@@ -0,0 +1,289 @@
+//! The state of the network connection, and the policy for downloading over it.
+//!
+//! The policy, and the settings of the fetchers, may be configured by an administrator in
+//! `/etc/pop-upgrade/network.json`:
+//!
+//! ```json
+//! { "metered": "defer", "large_download": 50000000, "bandwidth_limit": 1000000 }
+//! ```
+//!
+//! - `metered` decides what happens to large downloads on metered connections: `allow`, `ask`,
+//!   or `defer`. Defaults to `ask`.
+//! - `large_download` is the size in bytes from which a download is considered large.
+//! - `bandwidth_limit` limits every download of the daemon to that many bytes per second.
+//! - `fetch` overrides the defaults of the fetchers, with any of the following:
+//!   - `retries`: times that a failed download is retried
+//!   - `backoff`: seconds to wait before retrying failed packages, doubling with each retry
+//!   - `timeout`: seconds to wait for a read before the connection is considered lost
+//!   - `connections_per_file`: connections to fetch the parts of a file over
+//!   - `part_size`: size in bytes of each part of a file
+//! - `predownload` schedules the fetching of updates in the background, as described in
+//!   [`crate::predownload`].
+
+use crate::predownload::PredownloadConfig;
+use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+use serde_derive::Deserialize;
+use std::{fs, io, time::Duration};
+use thiserror::Error;
+
+const NETWORK_CONFIG: &str = "/etc/pop-upgrade/network.json";
+
+/// Backoff stops doubling after this many retries.
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+
+const NM_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+
+#[derive(Debug, Error)]
+pub enum MeteredError {
+    #[error("download deferred until the connection is no longer metered")]
+    Deferred,
+
+    #[error("download over a metered connection was declined")]
+    Declined,
+}
+
+/// Whether the connection is metered, as reported by NetworkManager.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metered {
+    Unknown,
+    Yes,
+    No,
+    GuessYes,
+    GuessNo,
+}
+
+impl Metered {
+    pub fn is_metered(self) -> bool { matches!(self, Metered::Yes | Metered::GuessYes) }
+}
+
+impl From<u32> for Metered {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Metered::Yes,
+            2 => Metered::No,
+            3 => Metered::GuessYes,
+            4 => Metered::GuessNo,
+            _ => Metered::Unknown,
+        }
+    }
+}
+
+/// A source of the state of the network connection.
+pub trait NetworkStatus {
+    fn metered(&self) -> Metered;
+}
+
+/// Queries NetworkManager over the system bus.
+pub struct NetworkManager;
+
+impl NetworkStatus for NetworkManager {
+    fn metered(&self) -> Metered {
+        let metered = Connection::new_system().and_then(|connection| {
+            connection
+                .with_proxy(NM_NAME, NM_PATH, Duration::from_millis(1000))
+                .get::<u32>(NM_NAME, "Metered")
+        });
+
+        match metered {
+            Ok(metered) => Metered::from(metered),
+            Err(why) => {
+                warn!("failed to get metered state from NetworkManager: {}", why);
+                Metered::Unknown
+            }
+        }
+    }
+}
+
+/// What to do with large downloads on a metered connection.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MeteredPolicy {
+    Allow,
+    #[default]
+    Ask,
+    Defer,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub metered:         MeteredPolicy,
+    pub large_download:  u64,
+    pub bandwidth_limit: Option<u64>,
+    pub fetch:           FetchConfig,
+    pub predownload:     PredownloadConfig,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            metered:         MeteredPolicy::default(),
+            large_download:  50_000_000,
+            bandwidth_limit: None,
+            fetch:           FetchConfig::default(),
+            predownload:     PredownloadConfig::default(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Loads the configuration of the administrator, or the defaults if there is none.
+    pub fn load() -> Self {
+        let config = match fs::read_to_string(NETWORK_CONFIG) {
+            Ok(config) => config,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(why) => {
+                warn!("failed to read {}: {}", NETWORK_CONFIG, why);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&config).unwrap_or_else(|why| {
+            warn!("failed to parse {}: {}", NETWORK_CONFIG, why);
+            Self::default()
+        })
+    }
+}
+
+/// Settings of the fetchers which override their defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub retries:              Option<u16>,
+    pub backoff:              Option<u64>,
+    pub timeout:              Option<u64>,
+    pub connections_per_file: Option<u16>,
+    pub part_size:            Option<u32>,
+}
+
+impl FetchConfig {
+    /// Applies the configured settings over the defaults of a fetcher.
+    pub fn apply(&self, defaults: FetchSettings) -> FetchSettings {
+        FetchSettings {
+            retries:              self.retries.unwrap_or(defaults.retries),
+            backoff:              self.backoff.map_or(defaults.backoff, Duration::from_secs),
+            timeout:              self.timeout.map_or(defaults.timeout, Duration::from_secs),
+            connections_per_file: self
+                .connections_per_file
+                .unwrap_or(defaults.connections_per_file),
+            part_size:            self.part_size.unwrap_or(defaults.part_size),
+        }
+    }
+}
+
+/// The settings that a fetcher is built with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FetchSettings {
+    pub retries:              u16,
+    pub backoff:              Duration,
+    pub timeout:              Duration,
+    pub connections_per_file: u16,
+    pub part_size:            u32,
+}
+
+impl FetchSettings {
+    /// Fetches packages one connection at a time, retrying those which failed.
+    pub const APT: Self = Self {
+        retries:              2,
+        backoff:              Duration::from_secs(5),
+        timeout:              Duration::from_secs(15),
+        connections_per_file: 1,
+        part_size:            2 * 1024 * 1024,
+    };
+
+    /// Fetches the recovery ISO in parts, over several connections at a time.
+    pub const RECOVERY: Self = Self {
+        retries:              3,
+        backoff:              Duration::from_secs(5),
+        timeout:              Duration::from_secs(5),
+        connections_per_file: 4,
+        part_size:            4 * 1024 * 1024,
+    };
+
+    /// The time to wait before the given retry, which doubles with each retry.
+    pub fn backoff(&self, retry: u16) -> Duration {
+        let factor = 1u32 << u32::from(retry.saturating_sub(1)).min(MAX_BACKOFF_DOUBLINGS);
+        self.backoff * factor
+    }
+}
+
+/// How a download should be handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Proceed,
+    Ask,
+    Defer,
+}
+
+/// Decides how to handle a download of `bytes`, which is considered large when unknown.
+pub fn decide(network: &dyn NetworkStatus, config: &NetworkConfig, bytes: Option<u64>) -> Decision {
+    if bytes.is_some_and(|bytes| bytes < config.large_download) || !network.metered().is_metered()
+    {
+        return Decision::Proceed;
+    }
+
+    match config.metered {
+        MeteredPolicy::Allow => Decision::Proceed,
+        MeteredPolicy::Ask => Decision::Ask,
+        MeteredPolicy::Defer => Decision::Defer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockNetwork(Metered);
+
+    impl NetworkStatus for MockNetwork {
+        fn metered(&self) -> Metered { self.0 }
+    }
+
+    #[test]
+    fn metered_decisions() {
+        let config = NetworkConfig::default();
+        let tethered = MockNetwork(Metered::GuessYes);
+
+        assert_eq!(decide(&MockNetwork(Metered::No), &config, None), Decision::Proceed);
+        assert_eq!(decide(&MockNetwork(Metered::Unknown), &config, None), Decision::Proceed);
+        assert_eq!(decide(&tethered, &config, Some(1_000_000)), Decision::Proceed);
+        assert_eq!(decide(&tethered, &config, Some(2_000_000_000)), Decision::Ask);
+        assert_eq!(decide(&tethered, &config, None), Decision::Ask);
+
+        let config = NetworkConfig { metered: MeteredPolicy::Defer, ..NetworkConfig::default() };
+        assert_eq!(decide(&MockNetwork(Metered::Yes), &config, None), Decision::Defer);
+    }
+
+    #[test]
+    fn parse_config() {
+        let config: NetworkConfig =
+            serde_json::from_str(r#"{ "metered": "defer", "bandwidth_limit": 1000000 }"#).unwrap();
+
+        assert_eq!(
+            config,
+            NetworkConfig {
+                metered:         MeteredPolicy::Defer,
+                large_download:  50_000_000,
+                bandwidth_limit: Some(1_000_000),
+                fetch:           FetchConfig::default(),
+                predownload:     PredownloadConfig::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn fetch_settings() {
+        let config: NetworkConfig =
+            serde_json::from_str(r#"{ "fetch": { "retries": 5, "timeout": 30 } }"#).unwrap();
+
+        let settings = config.fetch.apply(FetchSettings::APT);
+        assert_eq!(settings.retries, 5);
+        assert_eq!(settings.timeout, Duration::from_secs(30));
+        assert_eq!(settings.connections_per_file, 1);
+
+        assert_eq!(settings.backoff(1), Duration::from_secs(5));
+        assert_eq!(settings.backoff(3), Duration::from_secs(20));
+        assert_eq!(settings.backoff(20), Duration::from_secs(320));
+    }
+}
@@ -0,0 +1,199 @@
+//! Fetches updates in the background, so that they only have to be installed when the user
+//! chooses to update the system.
+//!
+//! Updates are only fetched while every session of the system is idle, the system is on AC
+//! power, and the connection is not metered. The schedule may be configured by an administrator
+//! in the `predownload` section of `/etc/pop-upgrade/network.json`:
+//!
+//! ```json
+//! { "predownload": { "enabled": true, "interval": 21600 } }
+//! ```
+//!
+//! - `enabled` decides if updates are fetched in the background. Defaults to `false`.
+//! - `interval` is the time in seconds to wait between each fetch of updates.
+
+use crate::network::NetworkStatus;
+use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+use serde_derive::Deserialize;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The time to wait before checking again if the system is ready to fetch updates.
+const RECHECK: Duration = Duration::from_secs(5 * 60);
+
+pub(crate) const LOGIND_NAME: &str = "org.freedesktop.login1";
+pub(crate) const LOGIND_PATH: &str = "/org/freedesktop/login1";
+pub(crate) const LOGIND_IFACE: &str = "org.freedesktop.login1.Manager";
+
+const UPOWER_NAME: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NotReady {
+    #[error("the system is in use")]
+    InUse,
+
+    #[error("the system is on battery power")]
+    OnBattery,
+
+    #[error("the connection is metered")]
+    Metered,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PredownloadConfig {
+    pub enabled:  bool,
+    pub interval: u64,
+}
+
+impl Default for PredownloadConfig {
+    fn default() -> Self { Self { enabled: false, interval: 6 * 60 * 60 } }
+}
+
+/// A source of the state of the system.
+pub trait SystemStatus {
+    fn idle(&self) -> bool;
+
+    /// Whether an application, such as a video player, is keeping the system from going idle.
+    fn idle_inhibited(&self) -> bool;
+
+    fn on_battery(&self) -> bool;
+}
+
+/// Queries logind and UPower over the system bus.
+pub struct System;
+
+impl System {
+    fn property<T>(name: &str, path: &str, iface: &str, property: &str) -> Result<T, dbus::Error>
+    where
+        T: for<'a> dbus::arg::Get<'a> + 'static,
+    {
+        Connection::new_system().and_then(|connection| {
+            connection.with_proxy(name, path, Duration::from_millis(1000)).get(iface, property)
+        })
+    }
+}
+
+impl SystemStatus for System {
+    fn idle(&self) -> bool {
+        Self::property(LOGIND_NAME, LOGIND_PATH, LOGIND_IFACE, "IdleHint").unwrap_or_else(|why| {
+            warn!("failed to get idle hint from logind: {}", why);
+            false
+        })
+    }
+
+    fn idle_inhibited(&self) -> bool {
+        Self::property::<String>(LOGIND_NAME, LOGIND_PATH, LOGIND_IFACE, "BlockInhibited")
+            .map(|inhibited| inhibited.split(':').any(|what| what == "idle"))
+            .unwrap_or_else(|why| {
+                warn!("failed to get inhibitors from logind: {}", why);
+                false
+            })
+    }
+
+    fn on_battery(&self) -> bool {
+        Self::property(UPOWER_NAME, UPOWER_PATH, UPOWER_NAME, "OnBattery").unwrap_or_else(|why| {
+            warn!("failed to get power source from UPower: {}", why);
+            true
+        })
+    }
+}
+
+/// Whether the system is ready for updates to be fetched in the background.
+pub fn ready(system: &dyn SystemStatus, network: &dyn NetworkStatus) -> Result<(), NotReady> {
+    if !system.idle() {
+        Err(NotReady::InUse)
+    } else if system.on_battery() {
+        Err(NotReady::OnBattery)
+    } else if network.metered().is_metered() {
+        Err(NotReady::Metered)
+    } else {
+        Ok(())
+    }
+}
+
+/// When to next fetch updates in the background.
+pub struct Schedule {
+    interval: Duration,
+    next:     Option<Instant>,
+}
+
+impl Schedule {
+    pub fn new(config: &PredownloadConfig, now: Instant) -> Self {
+        Self {
+            interval: Duration::from_secs(config.interval),
+            next:     if config.enabled { Some(now + RECHECK) } else { None },
+        }
+    }
+
+    pub fn due(&self, now: Instant) -> bool { self.next.is_some_and(|next| now >= next) }
+
+    /// Waits for the interval after updates were fetched, or checks again shortly if not.
+    pub fn reschedule(&mut self, now: Instant, fetched: bool) {
+        if let Some(next) = self.next.as_mut() {
+            *next = now + if fetched { self.interval } else { RECHECK };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Metered;
+
+    struct MockSystem {
+        idle:       bool,
+        on_battery: bool,
+    }
+
+    impl SystemStatus for MockSystem {
+        fn idle(&self) -> bool { self.idle }
+
+        fn idle_inhibited(&self) -> bool { false }
+
+        fn on_battery(&self) -> bool { self.on_battery }
+    }
+
+    struct MockNetwork(Metered);
+
+    impl NetworkStatus for MockNetwork {
+        fn metered(&self) -> Metered { self.0 }
+    }
+
+    #[test]
+    fn ready_when_idle() {
+        let idle = MockSystem { idle: true, on_battery: false };
+        let unmetered = MockNetwork(Metered::No);
+
+        assert_eq!(ready(&idle, &unmetered), Ok(()));
+        assert_eq!(ready(&idle, &MockNetwork(Metered::Unknown)), Ok(()));
+        assert_eq!(ready(&idle, &MockNetwork(Metered::GuessYes)), Err(NotReady::Metered));
+
+        let in_use = MockSystem { idle: false, on_battery: false };
+        assert_eq!(ready(&in_use, &unmetered), Err(NotReady::InUse));
+
+        let on_battery = MockSystem { idle: true, on_battery: true };
+        assert_eq!(ready(&on_battery, &unmetered), Err(NotReady::OnBattery));
+    }
+
+    #[test]
+    fn schedule() {
+        let now = Instant::now();
+        let config = PredownloadConfig { enabled: true, interval: 3600 };
+
+        let mut schedule = Schedule::new(&config, now);
+        assert!(!schedule.due(now));
+        assert!(schedule.due(now + RECHECK));
+
+        schedule.reschedule(now, false);
+        assert!(schedule.due(now + RECHECK));
+
+        schedule.reschedule(now, true);
+        assert!(!schedule.due(now + RECHECK));
+        assert!(schedule.due(now + Duration::from_secs(3600)));
+
+        let schedule = Schedule::new(&PredownloadConfig { enabled: false, ..config }, now);
+        assert!(!schedule.due(now + Duration::from_secs(86400)));
+    }
+}
@@ -0,0 +1,252 @@
+//! A single measure of progress for the tasks of the daemon.
+//!
+//! Each task is divided into weighted stages. Stages which fetch packages advance by the bytes
+//! that have been fetched, and stages which install packages advance by the progress that apt
+//! reports. The overall progress is the weighted sum of each stage's progress.
+
+use crate::release::UpgradeEvent;
+use std::time::{Duration, Instant};
+
+/// The minimum progress required before an ETA is estimated.
+const ETA_THRESHOLD: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Preparing,
+    FetchingCurrent,
+    UpgradingCurrent,
+    PreparingRelease,
+    FetchingRelease,
+    Finalizing,
+}
+
+impl Stage {
+    /// The stage of a release upgrade which begins with an event.
+    pub fn of(event: UpgradeEvent) -> Option<Self> {
+        let stage = match event {
+            UpgradeEvent::UpdatingPackageLists => Stage::Preparing,
+            // Sent before the current release's packages are fetched.
+            UpgradeEvent::UpgradingPackages => Stage::FetchingCurrent,
+            // Sent before the current release's packages are installed.
+            UpgradeEvent::FetchingPackages => Stage::UpgradingCurrent,
+            UpgradeEvent::RemovingConflicts
+            | UpgradeEvent::RemovingWacomConflicts
+            | UpgradeEvent::InstallingPackages
+            | UpgradeEvent::UpdatingSourceLists => Stage::PreparingRelease,
+            UpgradeEvent::FetchingPackagesForNewRelease
+            | UpgradeEvent::FetchingAdditionalPackagesForNewRelease => Stage::FetchingRelease,
+            UpgradeEvent::Simulating
+            | UpgradeEvent::AttemptingLiveUpgrade
+            | UpgradeEvent::AttemptingSystemdUnit
+            | UpgradeEvent::AttemptingRecovery => Stage::Finalizing,
+            _ => return None,
+        };
+
+        Some(stage)
+    }
+}
+
+impl From<Stage> for &'static str {
+    fn from(stage: Stage) -> Self {
+        match stage {
+            Stage::Preparing => "preparing",
+            Stage::FetchingCurrent => "fetching-current",
+            Stage::UpgradingCurrent => "upgrading-current",
+            Stage::PreparingRelease => "preparing-release",
+            Stage::FetchingRelease => "fetching-release",
+            Stage::Finalizing => "finalizing",
+        }
+    }
+}
+
+/// The tasks which progress is tracked for, and the weights of their stages.
+#[derive(Clone, Copy, Debug)]
+pub enum Plan {
+    PackageUpgrade,
+    ReleaseUpgrade,
+    Update { download_only: bool },
+}
+
+impl Plan {
+    fn stages(self) -> &'static [(Stage, u32)] {
+        match self {
+            Plan::PackageUpgrade => &[(Stage::UpgradingCurrent, 100)],
+            Plan::ReleaseUpgrade => &[
+                (Stage::Preparing, 5),
+                (Stage::FetchingCurrent, 15),
+                (Stage::UpgradingCurrent, 25),
+                (Stage::PreparingRelease, 10),
+                (Stage::FetchingRelease, 40),
+                (Stage::Finalizing, 5),
+            ],
+            Plan::Update { download_only: true } => &[(Stage::FetchingCurrent, 100)],
+            Plan::Update { download_only: false } => {
+                &[(Stage::FetchingCurrent, 50), (Stage::UpgradingCurrent, 50)]
+            }
+        }
+    }
+}
+
+/// The overall progress of a task at a point in time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub percent: u8,
+    pub stage:   &'static str,
+    pub eta:     Option<Duration>,
+}
+
+impl Snapshot {
+    /// The ETA is given in seconds, or `-1` if it is unknown.
+    pub fn into_dbus(self) -> (u8, String, i64) {
+        let eta = self.eta.map_or(-1, |eta| eta.as_secs() as i64);
+        (self.percent, self.stage.to_owned(), eta)
+    }
+}
+
+/// Tracks the progress of the active task.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    stages:   &'static [(Stage, u32)],
+    stage:    usize,
+    fraction: f64,
+    started:  Option<Instant>,
+    complete: bool,
+}
+
+impl Tracker {
+    /// Begins tracking a new task.
+    pub fn start(&mut self, plan: Plan) {
+        *self = Tracker { stages: plan.stages(), started: Some(Instant::now()), ..Self::default() };
+    }
+
+    /// Advances to a later stage of the task. Stages which are not a part of the task, or which
+    /// have already passed, are ignored.
+    pub fn enter(&mut self, stage: Stage) {
+        if let Some(position) = self.stages.iter().position(|&(s, _)| s == stage) {
+            if position > self.stage {
+                self.stage = position;
+                self.fraction = 0.0;
+            }
+        }
+    }
+
+    /// `fetched` of the `total` bytes of the current stage have been fetched.
+    pub fn fetched(&mut self, fetched: u64, total: u64) {
+        if total != 0 {
+            self.advance(fetched as f64 / total as f64);
+        }
+    }
+
+    /// Apt has installed `percent` of the packages of the current stage.
+    pub fn installed(&mut self, percent: u8) { self.advance(f64::from(percent) / 100.0); }
+
+    /// Marks the task as complete.
+    pub fn complete(&mut self) { self.complete = true; }
+
+    pub fn snapshot(&self) -> Snapshot { self.snapshot_at(Instant::now()) }
+
+    fn snapshot_at(&self, now: Instant) -> Snapshot {
+        let Some(started) = self.started else {
+            return Snapshot::default();
+        };
+
+        let overall = self.overall();
+        let eta = if self.complete {
+            Some(Duration::ZERO)
+        } else if overall >= ETA_THRESHOLD {
+            Some(now.duration_since(started).mul_f64((1.0 - overall) / overall))
+        } else {
+            None
+        };
+
+        Snapshot {
+            percent: (overall * 100.0) as u8,
+            stage: self.stages.get(self.stage).map_or("", |&(stage, _)| stage.into()),
+            eta,
+        }
+    }
+
+    fn advance(&mut self, fraction: f64) { self.fraction = fraction.clamp(self.fraction, 1.0); }
+
+    fn overall(&self) -> f64 {
+        let total = self.stages.iter().map(|&(_, weight)| weight).sum::<u32>();
+
+        if self.complete {
+            return 1.0;
+        } else if total == 0 {
+            return 0.0;
+        }
+
+        let passed = self.stages[..self.stage].iter().map(|&(_, weight)| weight).sum::<u32>();
+        let current = f64::from(self.stages[self.stage].1) * self.fraction;
+
+        (f64::from(passed) + current) / f64::from(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_stages() {
+        let mut tracker = Tracker::default();
+        tracker.start(Plan::ReleaseUpgrade);
+        assert_eq!(tracker.snapshot().percent, 0);
+        assert_eq!(tracker.snapshot().stage, "preparing");
+
+        tracker.enter(Stage::FetchingCurrent);
+        tracker.fetched(900, 1000);
+        tracker.fetched(1000, 1000);
+        assert_eq!(tracker.snapshot().percent, 20);
+
+        tracker.enter(Stage::UpgradingCurrent);
+        tracker.installed(40);
+        assert_eq!(tracker.snapshot().percent, 30);
+
+        // Earlier stages and stages outside of the plan are ignored.
+        tracker.enter(Stage::Preparing);
+        assert_eq!(tracker.snapshot().stage, "upgrading-current");
+
+        tracker.complete();
+        assert_eq!(tracker.snapshot().percent, 100);
+        assert_eq!(tracker.snapshot().eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn fetch_discards() {
+        let mut tracker = Tracker::default();
+        tracker.start(Plan::Update { download_only: true });
+
+        tracker.fetched(500, 1000);
+        assert_eq!(tracker.snapshot().percent, 50);
+
+        // A partial download was discarded, but progress never goes backwards.
+        tracker.fetched(250, 1000);
+        assert_eq!(tracker.snapshot().percent, 50);
+        tracker.fetched(750, 1000);
+        assert_eq!(tracker.snapshot().percent, 75);
+    }
+
+    #[test]
+    fn eta() {
+        let mut tracker = Tracker::default();
+        tracker.start(Plan::PackageUpgrade);
+
+        let started = tracker.started.unwrap();
+        assert_eq!(tracker.snapshot_at(started).eta, None);
+
+        tracker.installed(25);
+        let eta = tracker.snapshot_at(started + Duration::from_secs(60)).eta;
+        assert_eq!(eta, Some(Duration::from_secs(180)));
+    }
+
+    #[test]
+    fn snapshot_dbus() {
+        let snapshot =
+            Snapshot { percent: 42, stage: "fetching-release", eta: Some(Duration::from_secs(5)) };
+
+        assert_eq!(snapshot.into_dbus(), (42, "fetching-release".to_owned(), 5));
+        assert_eq!(Snapshot::default().into_dbus(), (0, String::new(), -1));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
-    release_api::ApiError, release_architecture::ReleaseArchError, repair::RepairError,
-    ubuntu_version::VersionError,
+    network::MeteredError, release_api::ApiError, release_architecture::ReleaseArchError,
+    repair::RepairError, ubuntu_version::VersionError,
 };
 use std::{io, path::PathBuf};
 use thiserror::Error;
@@ -30,6 +30,9 @@ pub enum RecoveryError {
     #[error("ISO does not exist at path")]
     IsoNotFound,
 
+    #[error("the connection is metered")]
+    Metered(#[source] MeteredError),
+
     #[error("failed to fetch mount points")]
     Mounts(#[source] io::Error),
 
@@ -39,6 +42,9 @@ pub enum RecoveryError {
     #[error("failed to create temporary directory for ISO")]
     TempDir(#[source] io::Error),
 
+    #[error("fetching from {} failed: {}", url, source)]
+    ThrottledFetch { url: String, source: io::Error },
+
     #[error("recovery partition was not found")]
     RecoveryNotFound,
 
@@ -3,7 +3,7 @@ mod version;
 
 use crate::daemon::SignalEvent;
 use anyhow::Context;
-use async_fetcher::{Checksum, FetchEvent, Fetcher, SumStr};
+use async_fetcher::{Checksum, FetchEvent, SumStr};
 use async_shutdown::ShutdownManager as Shutdown;
 use std::{
     convert::TryFrom,
@@ -14,7 +14,12 @@ use sys_mount::{Mount, MountFlags, Unmount, UnmountFlags};
 use tokio::{process::Command, sync::mpsc::UnboundedSender};
 
 use crate::{
-    external::findmnt_uuid, release_api::Release, release_architecture::detect_arch,
+    external::findmnt_uuid,
+    fetch::throttle::Throttle,
+    network::{FetchSettings, NetworkConfig},
+    release_api::Release,
+    release_architecture::detect_arch,
+    repair::RepairReport,
     system_environment::SystemEnvironment,
 };
 
@@ -68,7 +73,11 @@ pub async fn recovery(
     }
 
     // Check the system and perform any repairs necessary for success.
-    crate::repair::repair().await.map_err(RecoveryError::Repair)?;
+    let report = |report: &RepairReport| {
+        let _ = sender.send(SignalEvent::RepairReport(report.clone()));
+    };
+
+    crate::repair::repair(&report).await.map_err(RecoveryError::Repair)?;
 
     shutdown_check(&cancel)?;
 
@@ -281,28 +290,54 @@ async fn from_remote(
     let result = tokio::spawn(async move {
         info!("Initiating fetch of recovery ISO");
 
-        let urls = Arc::from(vec![url.clone()]);
-        let dest = Arc::from(path_.clone());
+        let urls: Arc<[Box<str>]> = Arc::from(vec![url.clone()]);
+        let dest: Arc<Path> = Arc::from(path_.clone());
 
         rustix::fs::sync();
 
-        Fetcher::default()
-            // Timeout if a read takes more than 5 seconds.
-            .timeout(std::time::Duration::from_secs(5))
-            // Download at most 4 parts at a time.
-            .connections_per_file(4)
-            // 4 MiB partial files.
-            .max_part_size(4 * 1024 * 1024)
-            // Forward progress events to this sender.
-            .events(events_tx)
-            // Use this to watch for shutdown events.
-            .shutdown(cancel)
-            // Wrap in Arc
-            .build()
-            // Fetch the ISO to `dest`
-            .request(urls, dest, Arc::new(()))
-            .await
-            .map_err(|source| RecoveryError::Fetch { url: url.into(), source })?;
+        let settings = NetworkConfig::load().fetch.apply(FetchSettings::RECOVERY);
+
+        if let Some(throttle) = Throttle::configured() {
+            let event = |event| {
+                let _ = events_tx.send((dest.clone(), Arc::new(()), event));
+            };
+
+            fetch_throttled(&throttle, &cancel, &sender_, &url, &dest, settings, &event).await?;
+        } else {
+            let fetcher = async_fetcher::Fetcher::default()
+                // Failed fetches are retried below, after a backoff.
+                .retries(0)
+                // Timeout if a read takes longer than this.
+                .timeout(settings.timeout)
+                // Download at most this many parts at a time.
+                .connections_per_file(settings.connections_per_file)
+                // The size of each part.
+                .max_part_size(settings.part_size)
+                // Forward progress events to this sender.
+                .events(events_tx.clone())
+                // Use this to watch for shutdown events.
+                .shutdown(cancel.clone())
+                // Wrap in Arc
+                .build();
+
+            let mut attempt = 0;
+
+            // Fetch the ISO to `dest`, fetching it again after a backoff if the fetch fails.
+            loop {
+                match fetcher.clone().request(urls.clone(), dest.clone(), Arc::new(())).await {
+                    Ok(()) => break,
+                    Err(why) if attempt < settings.retries && !cancel.is_shutdown_triggered() => {
+                        attempt += 1;
+                        let why = crate::misc::format_error(&why);
+                        warn!("fetch of recovery ISO failed, and will be retried: {}", why);
+                        emit_retrying(&sender_, attempt, why);
+                        let _ = events_tx.send((dest.clone(), Arc::new(()), FetchEvent::Retrying));
+                        tokio::time::sleep(settings.backoff(attempt)).await;
+                    }
+                    Err(source) => return Err(RecoveryError::Fetch { url: url.into(), source }),
+                }
+            }
+        }
 
         info!("fetched recovery ISO. Now validating checksum.");
 
@@ -358,10 +393,49 @@ async fn from_remote(
     Ok(path)
 }
 
+/// Fetches the ISO through the bandwidth throttle, fetching it again if the fetch fails.
+async fn fetch_throttled(
+    throttle: &Throttle,
+    cancel: &Shutdown<()>,
+    sender: &UnboundedSender<SignalEvent>,
+    url: &str,
+    dest: &Path,
+    settings: FetchSettings,
+    event: &(dyn Fn(FetchEvent) + Send + Sync),
+) -> RecResult<()> {
+    let mut attempt = 0;
+
+    loop {
+        let result = tokio::select! {
+            result = throttle.fetch(url, dest, event) => result,
+            _ = cancel.wait_shutdown_triggered() => return Err(RecoveryError::Cancelled),
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(why) if attempt < settings.retries => {
+                attempt += 1;
+                let why = crate::misc::format_error(&why);
+                warn!("fetch of recovery ISO failed, and will be retried: {}", why);
+                emit_retrying(sender, attempt, why);
+                event(FetchEvent::Retrying);
+                tokio::time::sleep(settings.backoff(attempt)).await;
+            }
+            Err(source) => {
+                return Err(RecoveryError::ThrottledFetch { url: url.to_owned(), source })
+            }
+        }
+    }
+}
+
 fn emit_progress(sender: &UnboundedSender<SignalEvent>, progress: u64, total: u64) {
     let _ = sender.send(SignalEvent::RecoveryDownloadProgress(progress, total));
 }
 
+fn emit_retrying(sender: &UnboundedSender<SignalEvent>, attempt: u16, why: String) {
+    let _ = sender.send(SignalEvent::FetchRetrying("recovery.iso".into(), u32::from(attempt), why));
+}
+
 fn emit_recovery_event(sender: &UnboundedSender<SignalEvent>, event: RecoveryEvent) {
     let _ = sender.send(SignalEvent::RecoveryUpgradeEvent(event));
 }
@@ -1,5 +1,7 @@
+use super::quirks::QuirksError;
 use crate::{
-    release_architecture::ReleaseArchError, repair::RepairError, ubuntu_version::VersionError,
+    network::MeteredError, release_architecture::ReleaseArchError, repair::RepairError,
+    ubuntu_version::VersionError,
 };
 use std::io;
 
@@ -43,18 +45,47 @@ pub enum ReleaseError {
     #[error("failed to downgrade packages")]
     Downgrade(#[source] anyhow::Error),
 
+    #[error(
+        "not enough space on the EFI system partition: {} MiB free, but {} MiB is required{}",
+        free / 1024 / 1024,
+        required / 1024 / 1024,
+        stale_esp_files(stale)
+    )]
+    EspFull { free: u64, required: u64, stale: Vec<String> },
+
+    #[error("failed to measure the usage of the EFI system partition")]
+    EspUsage(#[source] io::Error),
+
     #[error("status for `apt-get install -f` failed")]
     FixBroken(#[source] io::Error),
 
+    #[error("failed to hold packages which were kept from removal")]
+    HoldKept(#[source] io::Error),
+
     #[error("failed to hold the pop-upgrade package")]
     HoldPopUpgrade(#[source] io::Error),
 
+    #[error("failed to regenerate initrds with update-initramfs")]
+    InitramfsUpdate(#[source] io::Error),
+
     #[error("unable to hold apt/dpkg lock files")]
     Lock(#[source] io::Error),
 
+    #[error("the connection is metered")]
+    Metered(#[source] MeteredError),
+
+    #[error("there are no package updates to install")]
+    NoUpdates,
+
     #[error("root is required for this action: rerun with `sudo`")]
     NotRoot,
 
+    #[error("failed to install package updates at boot: {}", _0)]
+    OfflineUpdate(String),
+
+    #[error("failed to prepare package updates to be installed at boot")]
+    OfflineUpdateCreation(#[source] io::Error),
+
     #[error("failed to switch Ubuntu repos to old-releases")]
     OldReleaseSwitch(#[source] io::Error),
 
@@ -64,6 +95,12 @@ pub enum ReleaseError {
     #[error("failed to apply pre-upgrade fixes")]
     PreUpgrade(#[source] RepairError),
 
+    #[error("upgrade would remove protected packages: {}", _0.join(", "))]
+    ProtectedRemoval(Vec<String>),
+
+    #[error("failed to load package quirks")]
+    Quirks(#[source] QuirksError),
+
     #[error("failed to read the /proc/partitions file")]
     ReadingPartitions(#[source] io::Error),
 
@@ -91,6 +128,9 @@ pub enum ReleaseError {
     #[error("failed to fetch release versions")]
     ReleaseVersion(#[from] VersionError),
 
+    #[error("a release upgrade is staged, which package updates cannot take the place of")]
+    ReleaseUpgradeStaged,
+
     #[error("failed to apply system repair before upgrade")]
     Repair(#[from] RepairError),
 
@@ -100,9 +140,18 @@ pub enum ReleaseError {
     #[error("files required for systemd upgrade are missing: {:?}", _0)]
     SystemdUpgradeFilesMissing(Vec<&'static str>),
 
+    #[error("the system may not be able to boot after the upgrade: {}", _0)]
+    Unbootable(String),
+
+    #[error("failed to undo the last package update")]
+    Undo(#[source] io::Error),
+
     #[error("failed to unhold the pop-upgrade package")]
     UnholdPopUpgrade(#[source] io::Error),
 
+    #[error("{} package updates must be fetched before they can be installed at boot", _0)]
+    UpdatesNotFetched(usize),
+
     #[error("failed to perform apt upgrade of the current release")]
     Upgrade(#[source] io::Error),
 
@@ -138,3 +187,12 @@ pub enum ReleaseError {
     #[error("recovery entry not found in systemd-boot loader config")]
     MissingRecoveryEntry,
 }
+
+/// Suggests the removal of stale files from the ESP, if any were found.
+fn stale_esp_files(stale: &[String]) -> String {
+    if stale.is_empty() {
+        return String::new();
+    }
+
+    format!("; `pop-upgrade release repair --only esp` would remove {}", stale.join(", "))
+}
@@ -1,5 +1,7 @@
 pub mod check;
 pub mod eol;
+pub mod quirks;
+pub mod removed;
 pub mod repos;
 pub mod systemd;
 
@@ -13,10 +15,15 @@ use self::systemd::LoaderEntry;
 pub use self::{
     check::{BuildStatus, ReleaseStatus},
     errors::{RelResult, ReleaseError},
+    quirks::Quirks,
+    removed::Removal,
 };
 use crate::{
-    fetch::apt::ExtraPackages,
-    repair::{self, RepairError},
+    fetch::{apt::ExtraPackages, throttle::Throttle},
+    network::{FetchSettings, NetworkConfig},
+    repair::{self, RepairReport},
+    system_environment::SystemEnvironment,
+    updates::{PhasedUpdates, UpdatesConfig},
 };
 
 use crate::ubuntu_version::{Codename, Version};
@@ -25,12 +32,14 @@ use apt_cmd::{
     lock::apt_lock_wait, request::Request as AptRequest, AptGet, AptMark, AptUpgradeEvent, Dpkg,
     DpkgQuery,
 };
+use as_result::MapResult;
 use async_shutdown::ShutdownManager as Shutdown;
-use futures::prelude::*;
+use futures::{future::BoxFuture, prelude::*};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     convert::TryFrom,
     fs::{self, File},
+    io,
     os::unix::fs::symlink,
     path::Path,
     sync::Arc,
@@ -39,27 +48,10 @@ use systemd_boot_conf::SystemdBootConf;
 
 pub const STARTUP_UPGRADE_FILE: &str = "/pop-upgrade";
 
-/// Packages which need to be removed *before* the sources are reset to defaults
-/// and updates are applied (e.g. DKMS packages that work with the Pop!_OS kernel when
-/// installed from third-party sources, but not when installed from the Ubuntu repo).
-const REMOVE_PACKAGES_EARLY: &[&str] = &[
-    "openrazer-driver-dkms",
-];
-
-/// Packages which should be removed before upgrading.
+/// Asks for approval to remove packages, returning the packages which should be kept.
 ///
-/// - `gnome-software` conflicts with `pop-desktop` and its `sessioninstaller` dependency
-/// - `ureadahead` was deprecated and removed from the repositories
-/// - `update-notifier-common` breaks debconf and it's not part of a Pop install
-/// - `nodejs` may have some dependencies which are unmet on 22.04
-const REMOVE_PACKAGES: &[&str] = &[
-    "irqbalance",
-    "ureadahead",
-    "backport-iwlwifi-dkms",
-    "update-notifier-common",
-    "nodejs",
-    "ttf-mscorefonts-installer",
-];
+/// Resolves to `None` if the removals were declined, which cancels the upgrade.
+pub type RemovalApproval<'a> = &'a dyn Fn(Vec<String>) -> BoxFuture<'static, Option<Vec<String>>>;
 
 /// Packages which should be installed before upgrading.
 ///
@@ -72,6 +64,16 @@ const CORE_PACKAGES: &[&str] = &["linux-generic", "pop-desktop", "sessioninstall
 #[cfg(target_arch = "aarch64")]
 const CORE_PACKAGES: &[&str] = &["pop-desktop-raspi"];
 
+/// Kernel and desktop meta packages which an upgrade must never remove, alongside the
+/// `CORE_PACKAGES` and any packages protected by the quirks.
+const PROTECTED_PACKAGES: &[&str] = &[
+    "linux-system76",
+    "linux-generic",
+    "linux-raspi",
+    "pop-desktop",
+    "pop-desktop-raspi",
+];
+
 const DPKG_LOCK: &str = "/var/lib/dpkg/lock";
 const LISTS_LOCK: &str = "/var/lib/apt/lists/lock";
 const RELEASE_FETCH_FILE: &str = "/pop_preparing_release_upgrade";
@@ -203,31 +205,78 @@ pub async fn apt_fetch(
     let _lock_files = hold_apt_locks()?;
 
     let task = async {
-        let mut result = Ok(HashSet::new());
+        let settings = NetworkConfig::load().fetch.apply(FetchSettings::APT);
+        let throttle = Throttle::configured();
+        let mut counter = ByteCounter::new(uris.iter().map(|request| request.size).sum());
+        (*func)(FetchEvent::Progress(counter.progress_at(std::time::Instant::now())));
 
-        for _ in 0..3 {
-            (*func)(FetchEvent::Init(uris.len()));
+        let mut result = Ok(());
 
-            result = apt_fetch_(shutdown.clone(), uris.clone(), func).await;
+        for attempt in 0..=settings.retries {
+            (*func)(FetchEvent::Init(uris.len()));
 
-            match result.as_mut() {
-                Ok(borrowed) => {
-                    let mut errored = HashSet::new();
+            let fetched = apt_fetch_(
+                shutdown.clone(),
+                uris.clone(),
+                settings,
+                throttle.as_ref(),
+                &mut counter,
+                func,
+            )
+            .await;
+
+            let errored = match fetched {
+                Ok(errored) if errored.is_empty() => {
+                    result = Ok(());
+                    break;
+                }
+                // Packages which could not be fetched are left for apt to fetch.
+                Ok(errored) => {
+                    result = Ok(());
+                    errored
+                }
+                Err(why) => {
+                    let why_str = crate::misc::format_error(&why);
+                    error!("package fetching failed: {}", why_str);
+                    result = Err(why);
+
+                    if attempt < settings.retries {
+                        let retry = attempt + 1;
+                        let backoff = settings.backoff(retry);
+                        info!("retrying the fetch of packages in {} seconds", backoff.as_secs());
+
+                        for request in &uris {
+                            let (request, why) = (request.clone(), why_str.clone());
+                            (*func)(FetchEvent::Retrying { request, attempt: retry, why });
+                        }
 
-                    std::mem::swap(&mut errored, borrowed);
-                    if errored.is_empty() {
-                        break;
+                        tokio::time::sleep(backoff).await;
                     }
 
-                    uris = errored;
-                }
-                Err(why) => {
-                    error!("package fetching failed: {}", why);
+                    continue;
                 }
+            };
+
+            if attempt == settings.retries {
+                break;
+            }
+
+            let retry = attempt + 1;
+            let backoff = settings.backoff(retry);
+            info!("retrying {} packages in {} seconds", errored.len(), backoff.as_secs());
+
+            // Partial downloads of failed packages were removed, and will be fetched again.
+            uris = HashSet::with_capacity(errored.len());
+            for (request, why) in errored {
+                counter.discard(&request.name);
+                uris.insert(request.clone());
+                (*func)(FetchEvent::Retrying { request, attempt: retry, why });
             }
+
+            tokio::time::sleep(backoff).await;
         }
 
-        result.map(|_| ())
+        result
     };
 
     let cancel = async {
@@ -247,79 +296,166 @@ pub async fn apt_fetch(
 async fn apt_fetch_(
     shutdown: Shutdown<()>,
     uris: HashSet<AptRequest, std::collections::hash_map::RandomState>,
+    settings: FetchSettings,
+    throttle: Option<&Throttle>,
+    counter: &mut ByteCounter,
     func: &dyn Fn(FetchEvent),
-) -> Result<HashSet<AptRequest, std::collections::hash_map::RandomState>, ReleaseError> {
+) -> Result<HashMap<AptRequest, String>, ReleaseError> {
     const ARCHIVES: &str = "/var/cache/apt/archives/";
     const PARTIAL: &str = "/var/cache/apt/archives/partial/";
 
-    let (fetch_tx, fetch_rx) = flume::unbounded();
+    if !Path::new(PARTIAL).exists() {
+        tokio::fs::create_dir_all(PARTIAL)
+            .await
+            .context("failed to create partial debian directory")
+            .map_err(ReleaseError::PackageFetch)?;
+    }
 
-    use apt_cmd::fetch::{EventKind, FetcherExt};
+    let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
 
-    let mut errored = HashSet::new();
+    let requests = futures::stream::iter(uris.into_iter().map(|request| {
+        let source = async_fetcher::Source::new(
+            Arc::from(vec![Box::from(&*request.uri)].into_boxed_slice()),
+            Arc::from(Path::new(ARCHIVES).join(&request.name)),
+        );
 
-    // The system which fetches packages we send requests to
-    let (fetcher, mut events) = async_fetcher::Fetcher::default()
-        .retries(3)
-        .connections_per_file(1)
-        .timeout(std::time::Duration::from_secs(15))
-        .shutdown(shutdown.clone())
-        .into_package_fetcher()
-        .concurrent(2)
-        .fetch(fetch_rx.into_stream(), Arc::from(Path::new(ARCHIVES)));
+        (source, Arc::new(request))
+    }));
+
+    // The system which fetches packages, and reports the packages that could not be fetched.
+    let mut results = match throttle {
+        // Throttled packages are fetched one at a time, as they share the bandwidth anyway.
+        Some(throttle) => requests
+            .then(move |(source, request)| {
+                let events_tx = events_tx.clone();
+                async move {
+                    let dest = source.dest;
+                    let event = |event| {
+                        let _ = events_tx.send((dest.clone(), request.clone(), event));
+                    };
+
+                    event(async_fetcher::FetchEvent::Fetching);
+                    let result = throttle.fetch(&request.uri, &dest, &event).await;
+
+                    if result.is_ok() {
+                        event(async_fetcher::FetchEvent::Fetched);
+                    }
 
-    tokio::spawn(fetcher);
+                    (dest.clone(), request.clone(), result.map_err(|why| crate::misc::format_error(&why)))
+                }
+            })
+            .boxed(),
+
+        None => async_fetcher::Fetcher::default()
+            .retries(settings.retries)
+            .connections_per_file(settings.connections_per_file)
+            .max_part_size(settings.part_size)
+            .timeout(settings.timeout)
+            .shutdown(shutdown.clone())
+            .events(events_tx)
+            .build()
+            .stream_from(requests, 2)
+            .map(|(dest, request, result)| (dest, request, result.map_err(|why| crate::misc::format_error(&why))))
+            .boxed(),
+    };
 
-    // The system which sends package-fetching requests
-    let sender = tokio::spawn(async move {
-        if !Path::new(PARTIAL).exists() {
-            tokio::fs::create_dir_all(PARTIAL)
-                .await
-                .context("failed to create partial debian directory")?;
-        }
+    let fetcher = async move {
+        let mut errored = HashMap::new();
 
-        for request in uris {
-            let _ = fetch_tx.send_async(Arc::new(request)).await;
+        while let Some((dest, request, result)) = results.next().await {
+            if let Err(why) = result {
+                error!("{}: fetch error: {}", request.name, why);
+                let _ = tokio::fs::remove_file(&dest).await;
+                errored.insert(request.as_ref().clone(), why);
+            }
         }
 
-        Ok::<(), anyhow::Error>(())
-    });
-
-    let sender = async move { sender.await.unwrap() };
+        errored
+    };
 
     // The system that handles events received from the package-fetcher
     let receiver = async {
-        while let Some(event) = events.recv().await {
-            match event.kind {
-                EventKind::Fetching => {
-                    func(FetchEvent::Fetching((*event.package.uri).to_owned()));
-                }
+        let mut errored = HashMap::new();
 
-                EventKind::Validated => {
-                    func(FetchEvent::Fetched((*event.package).clone()));
+        while let Some((dest, request, event)) = events.recv().await {
+            match event {
+                async_fetcher::FetchEvent::Fetching => {
+                    func(FetchEvent::Fetching(request.uri.clone()));
                 }
 
-                EventKind::Error(why) => {
-                    error!("{}: fetch error: {:?}", event.package.name, why);
-                    errored.insert(event.package.as_ref().clone());
+                async_fetcher::FetchEvent::Progress(bytes) => {
+                    func(FetchEvent::Progress(counter.add(&request.name, bytes)));
                 }
 
-                EventKind::Fetched => (),
+                async_fetcher::FetchEvent::Fetched => {
+                    let validate = {
+                        let request = request.clone();
+                        move || apt_cmd::hash::compare_hash(&dest, request.size, &request.checksum)
+                    };
+
+                    let why = match tokio::task::spawn_blocking(validate).await {
+                        Ok(Ok(())) => {
+                            func(FetchEvent::Fetched((*request).clone()));
+                            continue;
+                        }
+                        Ok(Err(why)) => crate::misc::format_error(&why),
+                        Err(why) => crate::misc::format_error(&why),
+                    };
+
+                    error!("{}: fetched package failed validation: {}", request.name, why);
+                    let _ =
+                        tokio::fs::remove_file(Path::new(ARCHIVES).join(&request.name)).await;
+                    errored.insert(request.as_ref().clone(), why);
+                }
 
-                EventKind::Retrying => {
-                    info!("{}: retrying fetch", event.package.name);
-                    func(FetchEvent::Retrying((*event.package).clone()));
+                async_fetcher::FetchEvent::Retrying => {
+                    info!("{}: reconnecting", request.name);
+                    counter.discard(&request.name);
                 }
+
+                async_fetcher::FetchEvent::ContentLength(_) => (),
             }
         }
 
-        Ok::<(), anyhow::Error>(())
+        errored
     };
 
-    let _ = futures::try_join!(sender, receiver).map(|_| ()).map_err(ReleaseError::PackageFetch)?;
+    let (mut errored, failed) = future::join(receiver, fetcher).await;
+    errored.extend(failed);
+
     Ok(errored)
 }
 
+/// Counts the bytes fetched for each package, across every attempt of a fetch.
+struct ByteCounter {
+    packages: HashMap<String, u64>,
+    total:    u64,
+    started:  std::time::Instant,
+}
+
+impl ByteCounter {
+    fn new(total: u64) -> Self {
+        Self { packages: Default::default(), total, started: std::time::Instant::now() }
+    }
+
+    /// Adds bytes fetched for a package, returning the progress of the fetch.
+    fn add(&mut self, package: &str, bytes: u64) -> FetchProgress {
+        *self.packages.entry(package.to_owned()).or_default() += bytes;
+        self.progress_at(std::time::Instant::now())
+    }
+
+    /// The partial download of a package was discarded.
+    fn discard(&mut self, package: &str) { self.packages.remove(package); }
+
+    fn progress_at(&self, now: std::time::Instant) -> FetchProgress {
+        let fetched = self.packages.values().sum::<u64>().min(self.total);
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 { (fetched as f64 / elapsed) as u64 } else { 0 };
+
+        FetchProgress { fetched, total: self.total, rate }
+    }
+}
+
 /// Check if release files can be upgraded, and then overwrite them with the new release.
 ///
 /// On failure, the original release files will be restored.
@@ -365,13 +501,24 @@ pub async fn release_upgrade<'b>(
 }
 
 /// Upgrades packages for the current release.
-pub async fn package_upgrade<C: Fn(AptUpgradeEvent)>(callback: C) -> RelResult<()> {
+///
+/// If `only` is given, only those packages are upgraded, with `apt-get install --only-upgrade`.
+/// The `phased` updates policy is applied to apt.
+pub async fn package_upgrade<C: Fn(AptUpgradeEvent)>(
+    only: Option<&[String]>,
+    phased: PhasedUpdates,
+    callback: C,
+) -> RelResult<()> {
     let callback = &callback;
 
     let apt_upgrade = || async {
         apt_lock_wait().await;
         info!("upgrading packages");
-        let (mut child, mut upgrade_events) = crate::misc::apt_get().stream_upgrade().await?;
+        let apt_get = phased.apply(crate::misc::apt_get());
+        let (mut child, mut upgrade_events) = match only {
+            Some(packages) => crate::misc::stream_only_upgrade(apt_get, packages).await?,
+            None => apt_get.stream_upgrade().await?,
+        };
 
         while let Some(event) = upgrade_events.next().await {
             callback(event);
@@ -426,6 +573,8 @@ pub async fn upgrade<'a>(
     logger: &'a dyn Fn(UpgradeEvent),
     fetch: &'a dyn Fn(FetchEvent),
     upgrade: &'a dyn Fn(AptUpgradeEvent),
+    report: &'a dyn Fn(&RepairReport),
+    approve: RemovalApproval<'a>,
 ) -> RelResult<()> {
     terminate_background_applications();
 
@@ -442,12 +591,18 @@ pub async fn upgrade<'a>(
 
     let version = codename_from_version(from);
 
+    // Package quirks specific to this upgrade, including those of the local overlay.
+    let quirks = Quirks::load().map_err(ReleaseError::Quirks)?.for_upgrade(from, to);
+
     // Check the system and perform any repairs necessary for success.
-    autorepair(version).await?;
+    autorepair(version, report).await?;
+
+    // Ensure that there is room on the ESP for the kernels of the new release.
+    esp_preflight().await?;
 
     // Remove packages that may've worked from third-party sources
     // but will cause problems if downgraded to the built-in sources' version.
-    remove_conflicting_packages(logger, REMOVE_PACKAGES_EARLY, false).await?;
+    remove_conflicting_packages(logger, &quirks, &quirks.removals(true), None).await?;
 
     info!("creating backup of source lists");
     repos::backup(version).await.map_err(ReleaseError::BackupPPAs)?;
@@ -459,29 +614,32 @@ pub async fn upgrade<'a>(
     (logger)(UpgradeEvent::UpdatingPackageLists);
     update_package_lists().await?;
 
+    // The current release is upgraded by the daemon, so it follows the policy for phased updates.
+    let phased = UpdatesConfig::load().phased_updates;
+
     // Upgrade the current release to the latest packages.
     (*logger)(UpgradeEvent::UpgradingPackages);
-    fetch_current_updates(fetch).await?;
+    fetch_current_updates(fetch, phased).await?;
 
     // Fetch required packages for upgrading the current release.
     (*logger)(UpgradeEvent::FetchingPackages);
-    package_upgrade(upgrade).await?;
+    package_upgrade(None, phased, upgrade).await?;
 
     // Ensure packages are not newer than what's in the repositories.
-    downgrade_packages().await?;
-    
-    // Replace problematic Wacom packages with supported ones.
-    remove_wacom_packages(logger).await?;
+    downgrade_packages(&quirks).await?;
+
+    // Replace problematic packages, such as Surface-tailored Wacom packages, with supported ones.
+    replace_packages(logger, &quirks).await?;
 
     // Remove packages that may conflict with the upgrade,
     // including remoteless (orphaned) packages.
-    remove_conflicting_packages(logger, REMOVE_PACKAGES, true).await?;
+    remove_conflicting_packages(logger, &quirks, &quirks.removals(false), Some(approve)).await?;
 
     (logger)(UpgradeEvent::InstallingPackages);
     install_essential_packages().await?;
 
     // Apply any fixes necessary before the upgrade.
-    repair::pre_upgrade().map_err(ReleaseError::PreUpgrade)?;
+    repair::pre_upgrade(version, report).await.map_err(ReleaseError::PreUpgrade)?;
     let _ = AptMark::new().unhold(&["pop-upgrade", "pop-system-updater"]).await;
 
     // Upgrade the apt sources to the new release.
@@ -489,7 +647,7 @@ pub async fn upgrade<'a>(
     release_upgrade(logger, from, to).await.map_err(ReleaseError::Check)?;
 
     // Update lists and fetch packages for the new release.
-    fetch_new_release_packages(logger, fetch, from, to).await?;
+    fetch_new_release_packages(logger, fetch, from, &quirks).await?;
 
     // Reset system76-power modprobe configurations to the system defaults.
     _ = switchable_graphics::reset_to_default();
@@ -508,19 +666,48 @@ pub async fn upgrade<'a>(
     Ok(())
 }
 
-async fn autorepair(version: &str) -> Result<(), ReleaseError> {
-    (async move {
-        repair::crypttab::repair().map_err(RepairError::Crypttab)?;
-        repair::fstab::repair().map_err(RepairError::Fstab)?;
-        repair::packaging::repair(version).await.map_err(RepairError::Packaging)?;
+async fn autorepair(version: &str, report: &dyn Fn(&RepairReport)) -> Result<(), ReleaseError> {
+    // Stale files on the ESP may belong to another installation, so they are only removed by
+    // `pop-upgrade release repair --only esp`.
+    let only = ["crypttab", "mounts", "fstab", "packaging"].map(String::from);
 
-        Ok(())
-    })
-    .await
-    .map_err(ReleaseError::Repair)
+    repair::run(&repair::select(version, &only)?, repair::Fix::Automatic, report).await.1?;
+
+    Ok(())
+}
+
+/// Refuses to upgrade if the ESP does not have enough space for a new kernel, listing the stale
+/// files that `pop-upgrade release repair --only esp` would remove to make room.
+async fn esp_preflight() -> Result<(), ReleaseError> {
+    if SystemEnvironment::detect() != SystemEnvironment::Efi || !Path::new("/boot/efi/EFI").exists()
+    {
+        return Ok(());
+    }
+
+    let required = repair::esp::required_space().await;
+    let usage = repair::esp::usage().map_err(ReleaseError::EspUsage)?;
+
+    if usage.free >= required {
+        return Ok(());
+    }
+
+    warn!("ESP has {} KiB free, but {} KiB is required", usage.free / 1024, required / 1024);
+
+    // Another installation sharing the ESP may still use the stale files, so they are only
+    // removed when requested.
+    let stale = match repair::esp::stale_entries().await {
+        Ok(stale) => stale.iter().map(|entry| entry.path.display().to_string()).collect(),
+        Err(why) => {
+            let why = crate::misc::format_error(why.as_ref());
+            warn!("failed to find stale files on the ESP: {}", why);
+            Vec::new()
+        }
+    };
+
+    Err(ReleaseError::EspFull { free: usage.free, required, stale })
 }
 
-async fn downgrade_packages() -> Result<(), ReleaseError> {
+async fn downgrade_packages(quirks: &Quirks) -> Result<(), ReleaseError> {
     info!("searching for packages that require downgrading");
     let downgradable =
         apt_cmd::apt::downgradable_packages().await.map_err(ReleaseError::Downgrade)?;
@@ -529,59 +716,34 @@ async fn downgrade_packages() -> Result<(), ReleaseError> {
 
     cmd.arg("install");
 
-    'downgrades: for (package, version) in &downgradable {
+    for (package, version) in &downgradable {
         if package.contains("pop-upgrade") || package.contains("pop-system-updater") {
             continue;
         }
-        
-        // Papirus's elementary variant must be removed prior to downgrading the main package.
-        if package.contains("papirus-icon-theme") {
-            info!("papirus-icon-theme will be downgraded, so removing epapirus-icon-theme");
-            let mut remove_epapirus_cmd = AptGet::new().allow_downgrades().force().noninteractive();
-            remove_epapirus_cmd.arg("remove");
-            remove_epapirus_cmd.arg("epapirus-icon-theme");
-            let _remove_epapirus = remove_epapirus_cmd.status().await
-                .context("apt-get remove epapirus-icon-theme").map_err(ReleaseError::Downgrade);
-        }
-        
-        // In Ubuntu 22.04, the `ansible` and `ansible-core` packages are not compatible.
-        // If `ansible-core` is downgradable, check if `ansible` is downgradable;
-        // if so, remove `ansible-core` and skip adding it to the downgrade command.
-        if package.contains("ansible-core") && version.contains("2.12") {
-            info!("ansible-core downgrade candidate version is from Ubuntu 22.04");
-            for (package, _version) in &downgradable {
-                if package.eq("ansible") {
-                    info!("ansible will also be downgraded, so removing ansible-core");
-                    let mut remove_ansible_core_cmd = AptGet::new().allow_downgrades().force().noninteractive();
-                        remove_ansible_core_cmd.arg("remove");
-                        remove_ansible_core_cmd.arg("ansible-core");
-                        let _remove_ansible_core = remove_ansible_core_cmd.status().await
-                            .context("apt-get remove ansible-core").map_err(ReleaseError::Downgrade);
-                    continue 'downgrades;
-                }
+
+        // Some packages conflict with others once they are downgraded, so the others must be
+        // removed first.
+        let others = downgradable.iter().map(|(package, _)| package.as_str());
+        if let Some((remove, with)) = quirks.downgrade_removal(package, version, others) {
+            let conflict = with.unwrap_or(package);
+            info!("{} will be downgraded, so removing {}", conflict, remove);
+            let mut remove_cmd = AptGet::new().allow_downgrades().force().noninteractive();
+            remove_cmd.arg("remove");
+            remove_cmd.arg(remove);
+            let removed = remove_cmd
+                .status()
+                .await
+                .with_context(|| format!("apt-get remove {}", remove))
+                .map_err(ReleaseError::Downgrade);
+
+            if removed.is_ok() {
+                let reason = format!("conflicts with {} once downgraded", conflict);
+                record_removals(Some(Removal::new(remove, reason)));
             }
-            info!("ansible is not installed, so ansible-core will be downgraded");
-        }
-        
-        // dotnet-sdk-8.0 depends on dotnet-host in Microsoft's repository,
-        // but dotnet-sdk-8.0 conflicts with dotnet-host in the Ubuntu repository.
-        // If both are installed, the're probably installed from the Microsoft repository,
-        // and the latter must be removed before the former's downgraded.
-        // (Need to check for this on dotnet-host since it's earlier than dotnet-sdk-8.0 alphabetically.)
-        if package.contains("dotnet-host") {
-            info!("dotnet-host is to be downgraded");
-            for (package, _version) in &downgradable {
-                if package.eq("dotnet-sdk-8.0") {
-                    info!("dotnet-sdk-8.0 will also be downgraded, so removing dotnet-host");
-                    let mut remove_dotnet_cmd = AptGet::new().allow_downgrades().force().noninteractive();
-                        remove_dotnet_cmd.arg("remove");
-                        remove_dotnet_cmd.arg("dotnet-host");
-                        let _remove_dotnet = remove_dotnet_cmd.status().await
-                            .context("apt-get remove dotnet-host").map_err(ReleaseError::Downgrade);
-                    continue 'downgrades;
-                }
+
+            if package.contains(remove) {
+                continue;
             }
-            info!("dotnet-sdk-8.0 is not installed, so dotnet-host will be downgraded");
         }
 
         if let Some(version) = version.split_ascii_whitespace().next() {
@@ -605,9 +767,12 @@ async fn update_package_lists() -> Result<(), ReleaseError> {
 }
 
 /// Fetch apt packages and retry if network connections are changed.
-async fn fetch_current_updates(fetch: &dyn Fn(FetchEvent)) -> Result<(), ReleaseError> {
+async fn fetch_current_updates(
+    fetch: &dyn Fn(FetchEvent),
+    phased: PhasedUpdates,
+) -> Result<(), ReleaseError> {
     let packages = Some(ExtraPackages::Static(CORE_PACKAGES));
-    let uris = crate::fetch::apt::fetch_uris(Shutdown::new(), packages, true)
+    let uris = crate::fetch::apt::fetch_uris(Shutdown::new(), packages, true, phased)
         .await
         .map_err(ReleaseError::AptList)?;
 
@@ -626,49 +791,66 @@ async fn old_releases_workaround(version: &str, codename: Codename) -> Result<()
     Ok(())
 }
 
-async fn remove_wacom_packages(logger: &dyn Fn(UpgradeEvent)) -> Result<(), ReleaseError> {
-    // libwacom-common-surface and libwacom9-surface can't just be removed;
-    // the standard versions must be manually installed.
-    // This must be done before checking for remoteless packages,
-    // as other related packages will also be removed.
-    let mut conflicting_surface = (async {
-        let (mut child, package_stream) = 
-        DpkgQuery::new().show_installed(["libwacom-common-surface", "libwacom9-surface"]).await?;
+async fn replace_packages(
+    logger: &dyn Fn(UpgradeEvent),
+    quirks: &Quirks,
+) -> Result<(), ReleaseError> {
+    // Some packages, such as libwacom-common-surface and libwacom9-surface, can't just be
+    // removed; their replacements must be manually installed. This must be done before
+    // checking for remoteless packages, as other related packages will also be removed.
+    for (packages, replacements) in quirks.replacements() {
+        let installed = (async {
+            let (mut child, package_stream) = DpkgQuery::new().show_installed(packages).await?;
 
-        futures_util::pin_mut!(package_stream);
+            futures_util::pin_mut!(package_stream);
 
-        let mut packages = Vec::new();
+            let mut packages = Vec::new();
 
-        while let Some(package) = package_stream.next().await {
-            packages.push(package);
-        }
+            while let Some(package) = package_stream.next().await {
+                packages.push(package);
+            }
 
-        // NOTE: This is okay to fail since it just means a package is not found
-        let _ = child.wait().await;
+            // NOTE: This is okay to fail since it just means a package is not found
+            let _ = child.wait().await;
 
-        Ok::<_, std::io::Error>(packages)
-    })
-    .await
-    .context("check for known-conflicting Wacom/Surface packages")
-    .map_err(ReleaseError::ConflictRemoval)?;
-    
-    if !conflicting_surface.is_empty() {
-        apt_lock_wait().await;
-        (logger)(UpgradeEvent::RemovingWacomConflicts);
-        let mut apt_get = crate::misc::apt_get();
+            Ok::<_, std::io::Error>(packages)
+        })
+        .await
+        .context("check for packages which require replacement")
+        .map_err(ReleaseError::ConflictRemoval)?;
 
-        apt_get.arg("--auto-remove");
-        apt_get
-            .install(["libwacom-common", "libwacom9"])
-            .await
-            .context("conflict removal (libwacom Surface packages)")
-            .map_err(ReleaseError::ConflictRemoval)?;
+        if !installed.is_empty() {
+            apt_lock_wait().await;
+            (logger)(UpgradeEvent::RemovingWacomConflicts);
+            let mut apt_get = crate::misc::apt_get();
+
+            apt_get.arg("--auto-remove");
+            apt_get
+                .install(replacements)
+                .await
+                .with_context(|| format!("conflict removal (replacing {:?})", installed))
+                .map_err(ReleaseError::ConflictRemoval)?;
+
+            let reason = format!("replaced by {}", replacements.join(", "));
+            record_removals(installed.iter().map(|package| Removal::new(package, &*reason)));
+        }
     }
-    
+
     Ok(())
 }
 
-async fn remove_conflicting_packages(logger: &dyn Fn(UpgradeEvent), packages: &[&str], remoteless: bool) -> Result<(), ReleaseError> {    
+/// Removes conflicting packages.
+///
+/// If an approval is given, packages without a remote are also removed, but only after the
+/// full set of removals has been approved. Packages which are kept will be held instead.
+///
+/// Every package which is removed is recorded, so that it may be reinstalled later.
+async fn remove_conflicting_packages(
+    logger: &dyn Fn(UpgradeEvent),
+    quirks: &Quirks,
+    packages: &[&str],
+    approve: Option<RemovalApproval<'_>>,
+) -> Result<(), ReleaseError> {
     let mut conflicting = (async {
         let (mut child, package_stream) = DpkgQuery::new().show_installed(packages).await?;
 
@@ -689,32 +871,134 @@ async fn remove_conflicting_packages(logger: &dyn Fn(UpgradeEvent), packages: &[
     .context("check for known-conflicting packages")
     .map_err(ReleaseError::ConflictRemoval)?;
 
-    if remoteless {
+    let known = conflicting.clone();
+
+    if approve.is_some() {
         // Add packages which have no remote to the conflict list
         if let Ok(mut packages) = apt_cmd::apt::remoteless_packages().await {
             // Add exemptions for specific packages that we know to be safe.
-            packages.retain(|name| name != "sentinelagent");
+            packages.retain(|name| !quirks.is_remoteless_exempt(name));
             // Add the packages that remain to our list of conflicting packages for removal.
             conflicting.extend_from_slice(&packages);
         }
     }
 
-    if !conflicting.is_empty() {
-        apt_lock_wait().await;
-        (logger)(UpgradeEvent::RemovingConflicts);
-        let mut apt_get = crate::misc::apt_get();
+    if conflicting.is_empty() {
+        return Ok(());
+    }
 
-        apt_get.arg("--auto-remove");
-        apt_get
-            .remove(conflicting)
-            .await
-            .context("conflict removal")
-            .map_err(ReleaseError::ConflictRemoval)?;
+    let mut removals =
+        simulate_removal(&conflicting).await.map_err(ReleaseError::ConflictRemoval)?;
+
+    if let (Some(approve), false) = (approve, removals.is_empty()) {
+        info!("awaiting approval to remove {:?}", removals);
+        let keep = approve(removals.clone()).await.ok_or(ReleaseError::Canceled)?;
+
+        if !keep.is_empty() {
+            info!("holding packages instead of removing them: {:?}", keep);
+            AptMark::new().hold(&keep).await.map_err(ReleaseError::HoldKept)?;
+            conflicting.retain(|package| !keep.contains(package));
+
+            // Kept packages may depend on the packages to remove, or be removed as they are
+            // no longer needed, so those packages must be kept as well.
+            let mut roots = Vec::with_capacity(conflicting.len());
+            for package in conflicting {
+                match simulate_removal(std::slice::from_ref(&package)).await {
+                    Ok(pulled) if !pulled.iter().any(|pulled| keep.contains(pulled)) => {
+                        roots.push(package);
+                    }
+                    _ => info!("keeping {}, as its removal would remove kept packages", package),
+                }
+            }
+
+            conflicting = roots;
+
+            if conflicting.is_empty() {
+                return Ok(());
+            }
+
+            removals =
+                simulate_removal(&conflicting).await.map_err(ReleaseError::ConflictRemoval)?;
+
+            if let Some(package) = removals.iter().find(|package| keep.contains(package)) {
+                return Err(ReleaseError::ConflictRemoval(anyhow!(
+                    "the removal of conflicting packages would remove {}, which was kept",
+                    package
+                )));
+            }
+        }
     }
 
+    apt_lock_wait().await;
+    (logger)(UpgradeEvent::RemovingConflicts);
+    let mut apt_get = crate::misc::apt_get();
+
+    apt_get.arg("--auto-remove");
+    apt_get
+        .remove(&conflicting)
+        .await
+        .context("conflict removal")
+        .map_err(ReleaseError::ConflictRemoval)?;
+
+    record_removals(removals.into_iter().map(|package| {
+        let reason = if known.contains(&package) {
+            removed::REASON_CONFLICT
+        } else if conflicting.contains(&package) {
+            removed::REASON_REMOTELESS
+        } else {
+            removed::REASON_DEPENDENCY
+        };
+
+        Removal::new(package, reason)
+    }));
+
     Ok(())
 }
 
+/// Records packages which were removed, so that they may be reinstalled after the upgrade.
+///
+/// The packages are already gone by then, so a failure to record them does not stop the upgrade.
+fn record_removals(removals: impl IntoIterator<Item = Removal>) {
+    if let Err(why) = removed::record(removals) {
+        warn!("{}", crate::misc::format_error(&why));
+    }
+}
+
+/// Every package that would be removed alongside the given packages, including those that
+/// `--auto-remove` would remove.
+async fn simulate_removal(packages: &[String]) -> anyhow::Result<Vec<String>> {
+    apt_lock_wait().await;
+
+    let mut apt_get = AptGet::new().noninteractive().simulate();
+    apt_get.arg("--auto-remove").arg("remove").args(packages);
+
+    let output = apt_get.output().await.context("failed to simulate package removal")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "simulated package removal failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(simulated_removals(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the packages removed in the output of a simulated `apt-get remove`.
+fn simulated_removals(output: &str) -> Vec<String> {
+    let mut removals = Vec::new();
+
+    for line in output.lines() {
+        if let Some(package) = line.strip_prefix("Remv ").and_then(|rest| rest.split(' ').next()) {
+            if !removals.iter().any(|removal| removal == package) {
+                removals.push(package.to_owned());
+            }
+        }
+    }
+
+    removals
+}
+
 /// Search for any active processes which are incompatible with the upgrade daemon,
 /// and terminate them.
 fn terminate_background_applications() {
@@ -765,7 +1049,9 @@ async fn attempt_fetch<'a>(
     info!("fetching updated packages for the new release");
     (*logger)(UpgradeEvent::FetchingPackagesForNewRelease);
 
-    let uris = crate::fetch::apt::fetch_uris(shutdown.clone(), None, true)
+    // The new release is installed offline by apt's default policy for phased updates.
+    let phased = PhasedUpdates::Follow;
+    let uris = crate::fetch::apt::fetch_uris(shutdown.clone(), None, true, phased)
         .await
         .map_err(ReleaseError::AptList)?;
 
@@ -776,13 +1062,14 @@ async fn additional_fetch<'a>(
     shutdown: &Shutdown<()>,
     logger: &'a dyn Fn(UpgradeEvent),
     fetch: &'a dyn Fn(FetchEvent),
-    new_packages: &'static [&str],
+    new_packages: Vec<String>,
 ) -> RelResult<()> {
     info!("fetching additional packages for the new release");
     (*logger)(UpgradeEvent::FetchingAdditionalPackagesForNewRelease);
 
-    let packages = Some(ExtraPackages::Static(new_packages));
-    let uris = crate::fetch::apt::fetch_uris(shutdown.clone(), packages, false)
+    let packages = Some(ExtraPackages::Dynamic(new_packages));
+    let phased = PhasedUpdates::Follow;
+    let uris = crate::fetch::apt::fetch_uris(shutdown.clone(), packages, false, phased)
         .await
         .map_err(ReleaseError::AptList)?;
 
@@ -796,7 +1083,7 @@ async fn fetch_new_release_packages<'b>(
     logger: &'b dyn Fn(UpgradeEvent),
     fetch: &'b dyn Fn(FetchEvent),
     current: &'b str,
-    to: &'b str,
+    quirks: &'b Quirks,
 ) -> RelResult<()> {
     // Use a closure to capture any early returns due to an error.
     let updated_list_ops = || async {
@@ -807,11 +1094,10 @@ async fn fetch_new_release_packages<'b>(
 
         attempt_fetch(&Shutdown::new(), logger, fetch).await?;
 
-        // If upgrading to 24.04, download an additional package.
-        if to == "24.04" {
-            //const NEW_PACKAGES &[&str] = &["gnome-online-accounts-gtk"];
-            //new_packages = Some(ExtraPackages::Static(NEW_PACKAGES));
-            additional_fetch(&Shutdown::new(), logger, fetch, &["gnome-online-accounts-gtk"]).await?;
+        // Download additional packages that the new release requires.
+        let new_packages = quirks.extra_fetch();
+        if !new_packages.is_empty() {
+            additional_fetch(&Shutdown::new(), logger, fetch, new_packages).await?;
         }
 
         snapd::hold_transitional_packages().await?;
@@ -820,7 +1106,7 @@ async fn fetch_new_release_packages<'b>(
 
         (*logger)(UpgradeEvent::Simulating);
 
-        simulate_upgrade().await
+        simulate_upgrade(quirks).await
     };
 
     // On any error, roll back the source lists.
@@ -834,23 +1120,94 @@ async fn fetch_new_release_packages<'b>(
     }
 }
 
-async fn simulate_upgrade() -> RelResult<()> {
+/// Simulates the upgrade, and refuses to continue if it would remove a protected package.
+async fn simulate_upgrade(quirks: &Quirks) -> RelResult<()> {
     apt_lock_wait().await;
-    crate::misc::apt_get().simulate().upgrade().await.map_err(ReleaseError::Simulation)
+
+    let mut apt_get = crate::misc::apt_get().simulate();
+    apt_get.arg("full-upgrade");
+
+    let output = apt_get.output().await.map_err(ReleaseError::Simulation)?;
+
+    if !output.status.success() {
+        return Err(ReleaseError::Simulation(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        )));
+    }
+
+    let protected = protected_removals(&String::from_utf8_lossy(&output.stdout), quirks);
+
+    if !protected.is_empty() {
+        return Err(ReleaseError::ProtectedRemoval(protected));
+    }
+
+    Ok(())
+}
+
+/// Protected packages which the output of a simulated upgrade would remove.
+fn protected_removals(output: &str, quirks: &Quirks) -> Vec<String> {
+    simulated_removals(output)
+        .into_iter()
+        .filter(|package| {
+            let package = package.as_str();
+            CORE_PACKAGES.contains(&package)
+                || PROTECTED_PACKAGES.contains(&package)
+                || quirks.is_protected(package)
+        })
+        .collect()
 }
 
-pub fn upgrade_finalize(action: UpgradeMethod, from: &str, to: &str) -> RelResult<()> {
+pub async fn upgrade_finalize(action: UpgradeMethod, from: &str, to: &str) -> RelResult<()> {
     // Ensure that the splash kernel option is enabled in case it was removed.
     // This kernel option is required to show the Plymouth splash on next boot.
     if Path::new("/usr/bin/kernelstub").exists() {
-        let _res = std::process::Command::new("kernelstub").arg("-a").arg("splash").status();
+        let _res = tokio::process::Command::new("kernelstub").args(["-a", "splash"]).status().await;
     }
 
+    verify_bootable().await?;
+
     match action {
         UpgradeMethod::Offline => systemd::upgrade_set(from, to),
     }
 }
 
+/// Refuse to reboot into the offline upgrade if the system would be unable to boot.
+///
+/// A failure will be met with an attempt to regenerate the initrds and boot entries.
+async fn verify_bootable() -> RelResult<()> {
+    use crate::health::{self, HealthStatus};
+
+    if health::verify_bootable().status() != HealthStatus::Failed {
+        return Ok(());
+    }
+
+    warn!("regenerating initrds and boot entries");
+    tokio::process::Command::new("update-initramfs")
+        .args(["-u", "-k", "all"])
+        .status()
+        .await
+        .map_result()
+        .map_err(ReleaseError::InitramfsUpdate)?;
+
+    if Path::new("/usr/bin/kernelstub").exists() {
+        let _res = tokio::process::Command::new("kernelstub").status().await;
+    }
+
+    let report = health::verify_bootable();
+    if report.status() != HealthStatus::Failed {
+        return Ok(());
+    }
+
+    let failures = report
+        .checks
+        .iter()
+        .filter(|check| check.status == HealthStatus::Failed)
+        .map(|check| fomat!((check.check) ": " (check.detail)))
+        .collect::<Vec<_>>();
+
+    Err(ReleaseError::Unbootable(failures.join("; ")))
+}
+
 async fn rollback(release: &str, why: &(dyn std::error::Error + 'static)) {
     error!("failed to fetch packages: {}", crate::misc::format_error(why));
     warn!("attempting to roll back apt release files");
@@ -865,8 +1222,20 @@ async fn rollback(release: &str, why: &(dyn std::error::Error + 'static)) {
 pub enum FetchEvent {
     Fetching(String),
     Fetched(AptRequest),
+    /// The number of packages to fetch.
     Init(usize),
-    Retrying(AptRequest),
+    /// Bytes have been fetched.
+    Progress(FetchProgress),
+    /// A package which failed to be fetched will be fetched again.
+    Retrying { request: AptRequest, attempt: u16, why: String },
+}
+
+/// The bytes fetched out of the total size of a fetch, and the rate in bytes per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FetchProgress {
+    pub fetched: u64,
+    pub total:   u64,
+    pub rate:    u64,
 }
 
 /// Check if certain files exist at the time of starting this daemon.
@@ -900,7 +1269,10 @@ pub async fn cleanup() {
         }
     }
 
-    let _ = fs::remove_file(SYSTEM_UPDATE);
+    // Package updates which were staged for the next startup are kept until they are installed.
+    if !systemd::offline_update_pending() {
+        let _ = fs::remove_file(SYSTEM_UPDATE);
+    }
 
     if Path::new(crate::TRANSITIONAL_SNAPS).exists() {
         if let Ok(packages) = fs::read_to_string(crate::TRANSITIONAL_SNAPS) {
@@ -977,4 +1349,65 @@ mod logins {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_byte_counter() {
+        let mut counter = ByteCounter::new(1000);
+        counter.add("a.deb", 300);
+        counter.add("b.deb", 200);
+        counter.add("a.deb", 100);
+
+        let now = counter.started + std::time::Duration::from_secs(2);
+        assert_eq!(
+            counter.progress_at(now),
+            FetchProgress { fetched: 600, total: 1000, rate: 300 }
+        );
+
+        // A failed download is fetched again from the beginning.
+        counter.discard("b.deb");
+        assert_eq!(counter.progress_at(now).fetched, 400);
+    }
+
+    #[test]
+    fn parse_simulated_removals() {
+        let output = "\
+NOTE: This is only a simulation!
+Reading package lists...
+The following packages will be REMOVED:
+  acme-tools libacme1 nodejs
+Remv acme-tools [1.2.0]
+Remv libacme1 [1.2.0] [acme-tools:amd64 ]
+Remv nodejs [12.22.9~dfsg-1ubuntu3]
+";
+
+        assert_eq!(simulated_removals(output), ["acme-tools", "libacme1", "nodejs"]);
+    }
+
+    #[test]
+    fn protected_simulated_removals() {
+        let output = "\
+Remv acme-desktop [1.0]
+Remv pop-desktop [1.7.0] [acme-desktop:amd64 ]
+Remv libacme1 [1.2.0]
+Remv linux-system76 [6.8.0]
+";
+
+        let quirks = Quirks {
+            rules: vec![quirks::Rule {
+                from: None,
+                to:   None,
+                kind: quirks::RuleKind::Protect { packages: vec!["acme-desktop".into()] },
+            }],
+        };
+
+        assert_eq!(
+            protected_removals(output, &quirks),
+            ["acme-desktop", "pop-desktop", "linux-system76"]
+        );
+    }
+}
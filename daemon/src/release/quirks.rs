@@ -0,0 +1,337 @@
+//! Package quirks which must be handled to upgrade from one release to another.
+//!
+//! The quirks shipped with the daemon may be extended by an administrator with JSON files
+//! placed in `/etc/pop-upgrade/quirks.d`, which are applied in lexical order. Packages which
+//! must never be removed by an upgrade may be declared there with `protect` rules.
+
+use serde_derive::Deserialize;
+use std::{fs, io};
+use thiserror::Error;
+
+/// The quirks shipped with the daemon.
+const DEFAULT_QUIRKS: &str = include_str!("../../../data/quirks.json");
+
+/// A directory of local quirks which are applied on top of the defaults.
+const QUIRKS_OVERLAY: &str = "/etc/pop-upgrade/quirks.d";
+
+/// The newest version of the quirks file that is supported.
+const QUIRKS_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum QuirksError {
+    #[error("failed to parse quirks from {}", _0)]
+    Parse(String, #[source] serde_json::Error),
+
+    #[error("failed to read quirks from {}", _0)]
+    Read(String, #[source] io::Error),
+
+    #[error(
+        "quirks in {} require version {} of the format, but only {} is supported",
+        _0,
+        _1,
+        QUIRKS_VERSION
+    )]
+    Unsupported(String, u32),
+}
+
+/// A versioned collection of quirks.
+#[derive(Debug, Deserialize)]
+struct QuirksFile {
+    version: u32,
+    #[serde(default)]
+    rules:   Vec<Rule>,
+}
+
+/// A quirk which applies to upgrades between the given releases.
+///
+/// A quirk without a `from` or `to` release applies to every upgrade.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Rule {
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to:   Option<String>,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum RuleKind {
+    /// Packages to remove before the upgrade. Early removals happen before the sources are
+    /// reset, while the rest happen alongside the removal of remoteless packages.
+    RemoveBefore {
+        packages: Vec<String>,
+        #[serde(default)]
+        early:    bool,
+    },
+    /// If any of the packages are installed, install their replacements instead.
+    ReplaceWith { packages: Vec<String>, with: Vec<String> },
+    /// If a package whose name contains `downgrade` is to be downgraded, remove `remove`
+    /// first. A downgraded package whose name contains `remove` is not downgraded.
+    ///
+    /// If given, `with` must also be downgraded, and `version` must be contained within the
+    /// downgrade candidate of the package.
+    RemoveIfBothDowngradable {
+        downgrade: String,
+        remove:    String,
+        #[serde(default)]
+        with:      Option<String>,
+        #[serde(default)]
+        version:   Option<String>,
+    },
+    /// Packages of the new release to fetch in addition to the upgradable packages.
+    ExtraFetch { packages: Vec<String> },
+    /// Packages which have no remote, but should not be removed.
+    ExemptFromRemoteless { packages: Vec<String> },
+    /// Packages which an upgrade must not remove.
+    Protect { packages: Vec<String> },
+}
+
+/// The quirks which apply to an upgrade.
+#[derive(Clone, Debug, Default)]
+pub struct Quirks {
+    pub rules: Vec<Rule>,
+}
+
+impl Quirks {
+    /// Loads the default quirks, followed by those of the local overlay.
+    pub fn load() -> Result<Self, QuirksError> {
+        let mut rules = parse("the default quirks", DEFAULT_QUIRKS)?;
+
+        if let Ok(dir) = fs::read_dir(QUIRKS_OVERLAY) {
+            let mut paths = dir
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect::<Vec<_>>();
+
+            paths.sort();
+
+            for path in paths {
+                let source = path.display().to_string();
+                let contents = fs::read_to_string(&path)
+                    .map_err(|why| QuirksError::Read(source.clone(), why))?;
+
+                rules.extend(parse(&source, &contents)?);
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// The quirks which apply to an upgrade from one release version to another.
+    pub fn for_upgrade(self, from: &str, to: &str) -> Self {
+        let applies = |release: &Option<String>, version: &str| {
+            release.as_deref().is_none_or(|release| release == version)
+        };
+
+        let rules = self
+            .rules
+            .into_iter()
+            .filter(|rule| applies(&rule.from, from) && applies(&rule.to, to))
+            .collect();
+
+        Self { rules }
+    }
+
+    /// Packages which should be removed before upgrading.
+    pub fn removals(&self, early_removal: bool) -> Vec<&str> {
+        self.kinds()
+            .filter_map(|kind| match kind {
+                RuleKind::RemoveBefore { packages, early } if *early == early_removal => {
+                    Some(packages)
+                }
+                _ => None,
+            })
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Packages which must be replaced, and the packages to replace them with.
+    pub fn replacements(&self) -> impl Iterator<Item = (&[String], &[String])> {
+        self.kinds().filter_map(|kind| match kind {
+            RuleKind::ReplaceWith { packages, with } => Some((&**packages, &**with)),
+            _ => None,
+        })
+    }
+
+    /// A package to remove before `package` is downgraded to `version`, alongside every other
+    /// package in `downgradable`, and the package that must also be downgraded for it, if any.
+    pub fn downgrade_removal<'a>(
+        &self,
+        package: &str,
+        version: &str,
+        downgradable: impl Iterator<Item = &'a str> + Clone,
+    ) -> Option<(&str, Option<&str>)> {
+        self.kinds().find_map(|kind| match kind {
+            RuleKind::RemoveIfBothDowngradable { downgrade, remove, with, version: required }
+                if package.contains(downgrade.as_str())
+                    && required.as_deref().is_none_or(|required| version.contains(required))
+                    && with
+                        .as_deref()
+                        .is_none_or(|with| downgradable.clone().any(|package| package == with)) =>
+            {
+                Some((remove.as_str(), with.as_deref()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Additional packages to fetch for the new release.
+    pub fn extra_fetch(&self) -> Vec<String> {
+        self.kinds()
+            .filter_map(|kind| match kind {
+                RuleKind::ExtraFetch { packages } => Some(packages),
+                _ => None,
+            })
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Checks if a package without a remote should be kept.
+    pub fn is_remoteless_exempt(&self, package: &str) -> bool {
+        self.kinds().any(|kind| match kind {
+            RuleKind::ExemptFromRemoteless { packages } => packages.iter().any(|p| p == package),
+            _ => false,
+        })
+    }
+
+    /// Checks if a package has been protected from removal.
+    pub fn is_protected(&self, package: &str) -> bool {
+        self.kinds().any(|kind| match kind {
+            RuleKind::Protect { packages } => packages.iter().any(|p| p == package),
+            _ => false,
+        })
+    }
+
+    fn kinds(&self) -> impl Iterator<Item = &RuleKind> + Clone {
+        self.rules.iter().map(|rule| &rule.kind)
+    }
+}
+
+fn parse(source: &str, contents: &str) -> Result<Vec<Rule>, QuirksError> {
+    let file = serde_json::from_str::<QuirksFile>(contents)
+        .map_err(|why| QuirksError::Parse(source.to_owned(), why))?;
+
+    if file.version > QUIRKS_VERSION {
+        return Err(QuirksError::Unsupported(source.to_owned(), file.version));
+    }
+
+    Ok(file.rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults(from: &str, to: &str) -> Quirks {
+        Quirks { rules: parse("defaults", DEFAULT_QUIRKS).unwrap() }.for_upgrade(from, to)
+    }
+
+    #[test]
+    fn default_quirks() {
+        let quirks = defaults("22.04", "24.04");
+
+        assert_eq!(quirks.removals(true), ["openrazer-driver-dkms"]);
+        assert!(quirks.removals(false).contains(&"ureadahead"));
+        assert_eq!(quirks.extra_fetch(), ["gnome-online-accounts-gtk"]);
+        assert!(quirks.is_remoteless_exempt("sentinelagent"));
+        assert!(!quirks.is_remoteless_exempt("nodejs"));
+
+        let (packages, with) = quirks.replacements().next().unwrap();
+        assert_eq!(packages, ["libwacom-common-surface", "libwacom9-surface"]);
+        assert_eq!(with, ["libwacom-common", "libwacom9"]);
+    }
+
+    #[test]
+    fn release_filter() {
+        assert!(defaults("21.04", "22.04").extra_fetch().is_empty());
+    }
+
+    #[test]
+    fn downgrade_removals() {
+        let quirks = defaults("22.04", "24.04");
+        let downgradable = ["ansible", "ansible-core", "dotnet-host-7.0", "papirus-icon-theme"];
+        let downgradable = downgradable.iter().copied();
+
+        assert_eq!(
+            Some(("ansible-core", Some("ansible"))),
+            quirks.downgrade_removal("ansible-core", "2.12.0-1", downgradable.clone())
+        );
+        assert_eq!(
+            None,
+            quirks.downgrade_removal("ansible-core", "2.16.3-0", downgradable.clone())
+        );
+        assert_eq!(
+            Some(("epapirus-icon-theme", None)),
+            quirks.downgrade_removal("papirus-icon-theme", "20231101", downgradable.clone())
+        );
+        assert_eq!(None, quirks.downgrade_removal("dotnet-host-7.0", "7.0.0", downgradable));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let result = parse("overlay", r#"{ "version": 2, "rules": [] }"#);
+        assert!(matches!(result, Err(QuirksError::Unsupported(_, 2))));
+    }
+
+    #[test]
+    fn overlay_rules() {
+        let rules = parse(
+            "overlay",
+            r#"{
+                "version": 1,
+                "rules": [
+                    { "kind": "exempt-from-remoteless", "packages": ["acme-agent"] },
+                    { "kind": "remove-before", "from": "22.04", "packages": ["acme-dkms"] },
+                    { "kind": "protect", "packages": ["acme-desktop"] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules[1],
+            Rule {
+                from: Some("22.04".into()),
+                to:   None,
+                kind: RuleKind::RemoveBefore { packages: vec!["acme-dkms".into()], early: false },
+            }
+        );
+
+        let quirks = Quirks { rules }.for_upgrade("22.04", "24.04");
+        assert!(quirks.is_protected("acme-desktop"));
+        assert!(!quirks.is_protected("acme-dkms"));
+    }
+
+    #[test]
+    fn overlay_downgrade_removals() {
+        let rules = parse(
+            "overlay",
+            r#"{
+                "version": 1,
+                "rules": [
+                    {
+                        "kind": "remove-if-both-downgradable",
+                        "downgrade": "acme-core",
+                        "remove": "acme-core",
+                        "with": "acme"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let quirks = Quirks { rules };
+        let downgradable = ["acme", "acme-core"].iter().copied();
+
+        assert_eq!(
+            Some(("acme-core", Some("acme"))),
+            quirks.downgrade_removal("acme-core", "1.0", downgradable)
+        );
+        assert_eq!(None, quirks.downgrade_removal("acme-core", "1.0", ["acme-core"].into_iter()));
+    }
+}
@@ -0,0 +1,190 @@
+//! A record of the packages which were removed by a release upgrade, so that those which
+//! are available in the new release may be reinstalled afterwards.
+
+use apt_cmd::{lock::apt_lock_wait, AptCache};
+use futures::StreamExt;
+use std::{fs, io};
+use thiserror::Error;
+
+pub const REASON_CONFLICT: &str = "conflicts with the new release";
+pub const REASON_DEPENDENCY: &str = "depended on a removed package";
+pub const REASON_REMOTELESS: &str = "not available from any repository";
+
+#[derive(Debug, Error)]
+pub enum RemovedError {
+    #[error("failed to fetch package policies")]
+    Policy(#[source] anyhow::Error),
+
+    #[error("failed to read the record of removed packages")]
+    Read(#[source] io::Error),
+
+    #[error("failed to reinstall removed packages")]
+    Reinstall(#[source] io::Error),
+
+    #[error("{} was not removed by an upgrade", _0)]
+    Unknown(String),
+
+    #[error("failed to write the record of removed packages")]
+    Write(#[source] io::Error),
+}
+
+/// A package which was removed by an upgrade, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Removal {
+    pub package: String,
+    pub reason:  String,
+}
+
+impl Removal {
+    pub fn new(package: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self { package: package.into(), reason: reason.into() }
+    }
+}
+
+/// A removed package, and the version of it which may now be installed.
+#[derive(Clone, Debug)]
+pub struct Reinstallable {
+    pub removal:   Removal,
+    pub candidate: String,
+}
+
+/// Adds packages to the record of removed packages.
+pub fn record(removals: impl IntoIterator<Item = Removal>) -> Result<(), RemovedError> {
+    let mut record = removed()?;
+    merge(&mut record, removals);
+
+    fs::write(crate::REMOVED_PACKAGES, serialize(&record)).map_err(RemovedError::Write)
+}
+
+/// Every package in the record of removed packages.
+pub fn removed() -> Result<Vec<Removal>, RemovedError> {
+    match fs::read_to_string(crate::REMOVED_PACKAGES) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(why) => Err(RemovedError::Read(why)),
+    }
+}
+
+/// Removed packages which are not installed, but have an installation candidate.
+pub async fn reinstallable() -> Result<Vec<Reinstallable>, RemovedError> {
+    let removed = removed()?;
+
+    if removed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let packages = removed.iter().map(|removal| removal.package.as_str()).collect::<Vec<_>>();
+
+    let (mut child, mut policies) =
+        AptCache::new().policy(&packages).await.map_err(RemovedError::Policy)?;
+
+    let mut reinstallable = Vec::new();
+
+    while let Some(policy) = policies.next().await {
+        if policy.installed != "(none)" || policy.candidate == "(none)" {
+            continue;
+        }
+
+        if let Some(removal) = removed.iter().find(|removal| removal.package == policy.package) {
+            reinstallable
+                .push(Reinstallable { removal: removal.clone(), candidate: policy.candidate });
+        }
+    }
+
+    let _ = child.wait().await;
+
+    Ok(reinstallable)
+}
+
+/// Reinstalls packages which were removed, and drops them from the record.
+pub async fn reinstall(packages: &[String]) -> Result<(), RemovedError> {
+    let mut record = removed()?;
+
+    if let Some(package) =
+        packages.iter().find(|package| !record.iter().any(|removal| &removal.package == *package))
+    {
+        return Err(RemovedError::Unknown(package.clone()));
+    }
+
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    apt_lock_wait().await;
+    crate::misc::apt_get().install(packages).await.map_err(RemovedError::Reinstall)?;
+
+    record.retain(|removal| !packages.contains(&removal.package));
+    fs::write(crate::REMOVED_PACKAGES, serialize(&record)).map_err(RemovedError::Write)
+}
+
+/// Adds removals to a record, replacing the reasons of packages which were already recorded.
+fn merge(record: &mut Vec<Removal>, removals: impl IntoIterator<Item = Removal>) {
+    for removal in removals {
+        match record.iter_mut().find(|recorded| recorded.package == removal.package) {
+            Some(recorded) => recorded.reason = removal.reason,
+            None => record.push(removal),
+        }
+    }
+}
+
+/// Each removal is recorded on its own line, with the package and reason separated by a tab.
+fn parse(contents: &str) -> Vec<Removal> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (package, reason) = line.split_once('\t').unwrap_or((line, ""));
+            let package = package.trim();
+
+            if package.is_empty() {
+                None
+            } else {
+                Some(Removal::new(package, reason.trim()))
+            }
+        })
+        .collect()
+}
+
+fn serialize(record: &[Removal]) -> String {
+    let mut buffer = String::new();
+
+    for removal in record {
+        buffer.push_str(&removal.package);
+        buffer.push('\t');
+        buffer.push_str(&removal.reason);
+        buffer.push('\n');
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let mut record = parse("nodejs\tconflicts with the new release\nlibacme1\n\n");
+
+        assert_eq!(
+            record,
+            [Removal::new("nodejs", REASON_CONFLICT), Removal::new("libacme1", "")]
+        );
+
+        merge(
+            &mut record,
+            vec![
+                Removal::new("libacme1", REASON_DEPENDENCY),
+                Removal::new("ttf-mscorefonts-installer", REASON_CONFLICT),
+            ],
+        );
+
+        assert_eq!(
+            parse(&serialize(&record)),
+            [
+                Removal::new("nodejs", REASON_CONFLICT),
+                Removal::new("libacme1", REASON_DEPENDENCY),
+                Removal::new("ttf-mscorefonts-installer", REASON_CONFLICT),
+            ]
+        );
+    }
+}
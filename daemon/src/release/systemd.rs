@@ -6,6 +6,15 @@ use std::fs;
 
 pub const PREVIOUS_DEFAULT: &str = "/var/lib/pop-upgrade/previous_default";
 
+/// Marks the offline update as an update of packages, rather than an upgrade of the release.
+pub const OFFLINE_UPDATE_FILE: &str = "/pop-upgrade-packages";
+
+/// The packages that the offline update upgrades, if it does not upgrade every package.
+pub const OFFLINE_UPDATE_ONLY_FILE: &str = "/pop-upgrade-packages-only";
+
+/// The result of the last offline update of packages, written by the upgrade script.
+pub const OFFLINE_UPDATE_REPORT: &str = "/var/lib/pop-upgrade/offline_update";
+
 pub struct BootConf(SystemdBootConf);
 
 impl BootConf {
@@ -109,11 +118,69 @@ pub fn upgrade_set(from: &str, to: &str) -> RelResult<()> {
         .map(<&'static str>::from)
         .unwrap_or(to);
 
+    // The release upgrade takes the place of any package updates which were staged.
+    offline_update_cancel();
+
     fs::write(STARTUP_UPGRADE_FILE, &format!("{} {}", current, new))
         .and_then(|_| symlink("/var/cache/apt/archives", SYSTEM_UPDATE))
         .map_err(ReleaseError::StartupFileCreation)
 }
 
+/// Stage the fetched package updates to be installed by systemd at the next startup.
+///
+/// The `apt_options`, one per line, are passed to apt when the updates are installed. If `only`
+/// is given, those packages are upgraded with `apt-get install --only-upgrade`, instead of a
+/// full upgrade.
+pub fn offline_update_set(apt_options: &[&str], only: Option<&[String]>) -> RelResult<()> {
+    let _ = fs::remove_file(SYSTEM_UPDATE);
+    let _ = fs::remove_file(OFFLINE_UPDATE_ONLY_FILE);
+
+    let lines = |lines: &[&str]| lines.iter().map(|line| [line, "\n"].concat()).collect::<String>();
+
+    let only = only.map(|packages| packages.iter().map(String::as_str).collect::<Vec<_>>());
+
+    fs::write(OFFLINE_UPDATE_FILE, lines(apt_options))
+        .and_then(|_| match only {
+            Some(packages) => fs::write(OFFLINE_UPDATE_ONLY_FILE, lines(&packages)),
+            None => Ok(()),
+        })
+        .and_then(|_| symlink("/var/cache/apt/archives", SYSTEM_UPDATE))
+        .map_err(ReleaseError::OfflineUpdateCreation)
+}
+
+/// Unstage package updates which were to be installed at the next startup.
+pub fn offline_update_cancel() {
+    if offline_update_pending() {
+        let _ = fs::remove_file(SYSTEM_UPDATE);
+        let _ = fs::remove_file(OFFLINE_UPDATE_FILE);
+        let _ = fs::remove_file(OFFLINE_UPDATE_ONLY_FILE);
+    }
+}
+
+pub fn offline_update_pending() -> bool { Path::new(OFFLINE_UPDATE_FILE).exists() }
+
+/// Take the result of the package updates which were installed at startup, if any were.
+pub fn take_offline_update_report() -> Option<RelResult<()>> {
+    let report = fs::read_to_string(OFFLINE_UPDATE_REPORT).ok()?;
+    let _ = fs::remove_file(OFFLINE_UPDATE_REPORT);
+
+    Some(parse_offline_update_report(&report))
+}
+
+/// Parses a report of the exit status of the update, followed by the reason that it failed.
+fn parse_offline_update_report(report: &str) -> RelResult<()> {
+    let report = report.trim();
+    let (status, why) = report.split_once(' ').unwrap_or((report, ""));
+
+    if status == "0" {
+        Ok(())
+    } else if why.is_empty() {
+        Err(ReleaseError::OfflineUpdate(["exited with status ", status].concat()))
+    } else {
+        Err(ReleaseError::OfflineUpdate(why.to_owned()))
+    }
+}
+
 /// Validate that the pre-required files for performing a system upgrade are in place.
 pub fn upgrade_prereq() -> RelResult<()> {
     const REQUIRED_UPGRADE_FILES: [&str; 3] = [
@@ -138,3 +205,19 @@ pub fn upgrade_prereq() -> RelResult<()> {
 fn remove_previous() -> anyhow::Result<()> {
     fs::remove_file(PREVIOUS_DEFAULT).with_context(|| fomat!("failed to remove "(PREVIOUS_DEFAULT)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_update_reports() {
+        assert!(parse_offline_update_report("0\n").is_ok());
+
+        let why = parse_offline_update_report("1 dpkg was interrupted").unwrap_err().to_string();
+        assert_eq!(why, "failed to install package updates at boot: dpkg was interrupted");
+
+        let why = parse_offline_update_report("100").unwrap_err().to_string();
+        assert_eq!(why, "failed to install package updates at boot: exited with status 100");
+    }
+}
@@ -1,12 +1,53 @@
+use super::{RepairCheck, RepairError};
 use anyhow::Context;
+use futures::future::{self, FutureExt, LocalBoxFuture};
 use std::{fs, path::Path};
 
 const CRYPTTAB: &str = "/etc/crypttab";
 const CRYPTTAB_TMP: &str = "/etc/crypttab.tmp";
 
-pub fn repair() -> anyhow::Result<()> {
+/// Encrypted swap entries must use plain mode.
+pub struct Crypttab;
+
+impl RepairCheck for Crypttab {
+    fn id(&self) -> &'static str { "crypttab" }
+
+    fn description(&self) -> &'static str { "encrypted swap in /etc/crypttab uses plain mode" }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let detected = (|| {
+            if !Path::new(CRYPTTAB).exists() {
+                return Ok(Vec::new());
+            }
+
+            let contents = fs::read_to_string(CRYPTTAB).context("cannot read the crypttab file")?;
+
+            Ok(match cryptswap_plain_warning(&contents) {
+                Some(_) => vec!["swap entries are missing the `plain` option".to_owned()],
+                None => Vec::new(),
+            })
+        })();
+
+        future::ready(detected.map_err(RepairError::Crypttab)).boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let fixed = repair().map(|changed| {
+            if changed {
+                vec!["added the `plain` option to swap entries in /etc/crypttab".to_owned()]
+            } else {
+                Vec::new()
+            }
+        });
+
+        future::ready(fixed.map_err(RepairError::Crypttab)).boxed_local()
+    }
+}
+
+/// Returns `true` if the crypttab was modified.
+pub fn repair() -> anyhow::Result<bool> {
     if !Path::new(CRYPTTAB).exists() {
-        return Ok(());
+        return Ok(false);
     }
 
     let contents = fs::read_to_string(CRYPTTAB).context("cannot read the crypttab file")?;
@@ -15,9 +56,11 @@ pub fn repair() -> anyhow::Result<()> {
         fs::write(CRYPTTAB_TMP, new_contents.as_bytes()).context("failed to write new crypttab")?;
 
         fs::rename(CRYPTTAB_TMP, CRYPTTAB).context("failed to overwrite crypttab")?;
+
+        return Ok(true);
     }
 
-    Ok(())
+    Ok(false)
 }
 
 fn cryptswap_plain_warning(input: &str) -> Option<String> {
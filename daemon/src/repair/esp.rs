@@ -0,0 +1,344 @@
+//! Measures the usage of the EFI system partition, and removes kernels, initrds, and
+//! recovery loaders which are no longer referenced by the systemd-boot configuration.
+
+use super::{RepairCheck, RepairError};
+use crate::{external::findmnt_uuid, system_environment::SystemEnvironment};
+use anyhow::Context;
+use futures::future::{FutureExt, LocalBoxFuture};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use systemd_boot_conf::SystemdBootConf;
+
+const ESP: &str = "/boot/efi";
+const ESP_EFI: &str = "/boot/efi/EFI";
+
+/// Links to every block device of the system, named by the UUIDs of their file systems.
+const DISK_BY_UUID: &str = "/dev/disk/by-uuid";
+
+/// Space to reserve when the size of the installed kernel copies cannot be determined.
+const DEFAULT_REQUIRED: u64 = 128 * 1024 * 1024;
+
+/// Files that kernelstub copies to the `Pop_OS-<uuid>` directory.
+const KERNELSTUB_FILES: &[&str] =
+    &["vmlinuz.efi", "initrd.img", "vmlinuz-previous.efi", "initrd.img-previous", "cmdline"];
+
+/// The capacity and free space of the ESP, in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct EspUsage {
+    pub total: u64,
+    pub free:  u64,
+}
+
+impl EspUsage {
+    pub fn used(&self) -> u64 { self.total.saturating_sub(self.free) }
+}
+
+/// A file or directory on the ESP which is safe to remove.
+#[derive(Clone, Debug)]
+pub struct StaleEntry {
+    pub path:   PathBuf,
+    pub size:   u64,
+    pub reason: &'static str,
+}
+
+/// Kernel copies and recovery loaders on the ESP which are no longer in use.
+pub struct Esp;
+
+impl RepairCheck for Esp {
+    fn id(&self) -> &'static str { "esp" }
+
+    fn description(&self) -> &'static str { "the ESP has no stale kernels or recovery loaders" }
+
+    // Another installation sharing the ESP may still use the files that were found.
+    fn automatic(&self) -> bool { false }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        async {
+            if !is_applicable() {
+                return Ok(Vec::new());
+            }
+
+            let stale = stale_entries().await.map_err(RepairError::Esp)?;
+
+            Ok(stale
+                .iter()
+                .map(|entry| {
+                    let size = entry.size / 1024;
+                    format!("{}: {} ({} KiB)", entry.path.display(), entry.reason, size)
+                })
+                .collect())
+        }
+        .boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        async {
+            if !is_applicable() {
+                return Ok(Vec::new());
+            }
+
+            let stale = stale_entries().await.map_err(RepairError::Esp)?;
+
+            clean(&stale)
+                .context("failed to remove stale files from the ESP")
+                .map_err(RepairError::Esp)?;
+
+            Ok(stale.iter().map(|entry| format!("removed {}", entry.path.display())).collect())
+        }
+        .boxed_local()
+    }
+}
+
+/// Measure the usage of the ESP.
+pub fn usage() -> io::Result<EspUsage> {
+    let stat = rustix::fs::statvfs(ESP)?;
+    Ok(EspUsage { total: stat.f_blocks * stat.f_frsize, free: stat.f_bavail * stat.f_frsize })
+}
+
+/// An estimate of the space required by kernelstub to install a new kernel and initrd.
+///
+/// kernelstub copies the current kernel and initrd over the `-previous` copies in place, so only
+/// the growth of the new copies over the `-previous` copies that they replace is required.
+pub async fn required_space() -> u64 {
+    let root_uuid = match findmnt_uuid("/").await {
+        Ok(uuid) => uuid,
+        Err(_) => return DEFAULT_REQUIRED,
+    };
+
+    let directory = Path::new(ESP_EFI).join(["Pop_OS-", &root_uuid].concat());
+
+    let size_of = |files: &[&str]| {
+        files
+            .iter()
+            .filter_map(|file| fs::metadata(directory.join(file)).ok())
+            .map(|meta| meta.len())
+            .sum::<u64>()
+    };
+
+    let installed = size_of(&["vmlinuz.efi", "initrd.img"]);
+    let previous = size_of(&["vmlinuz-previous.efi", "initrd.img-previous"]);
+
+    if installed == 0 {
+        DEFAULT_REQUIRED
+    } else {
+        // Leave room for the new initrd to be larger than the current one.
+        (installed + installed / 4).saturating_sub(previous)
+    }
+}
+
+/// Find kernel copies and recovery loaders on the ESP which are no longer in use.
+pub async fn stale_entries() -> anyhow::Result<Vec<StaleEntry>> {
+    let conf = SystemdBootConf::new(ESP).context("failed to load systemd-boot configuration")?;
+
+    let files_of = |entry: &systemd_boot_conf::entry::Entry| {
+        std::iter::once(&*entry.linux)
+            .chain(entry.initrd.as_deref())
+            .map(|path| path.trim_start_matches('/').to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let referenced = conf.entries.iter().flat_map(files_of).collect::<Vec<_>>();
+
+    let root_uuid = findmnt_uuid("/").await.context("cannot find UUID of the root partition")?;
+
+    let recovery_exists = crate::recovery::recovery_exists()
+        .context("cannot determine if the recovery partition exists")?;
+
+    let recovery_uuid = if recovery_exists {
+        let uuid = findmnt_uuid("/recovery")
+            .await
+            .context("cannot find UUID of the recovery partition")?;
+        Some(uuid)
+    } else {
+        None
+    };
+
+    // Loaders are only stale once the partition that they belong to no longer exists.
+    let uuids = partition_uuids().context("failed to list the partitions of the system")?;
+    let exists = |uuid: &str| {
+        Some(uuid) == recovery_uuid.as_deref() || uuids.iter().any(|u| u.eq_ignore_ascii_case(uuid))
+    };
+
+    let mut stale = Vec::new();
+
+    let efi = fs::read_dir(ESP_EFI).context("failed to read the ESP")?;
+    for entry in efi.flatten() {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        let relative = ["EFI/", &name].concat();
+
+        let reason = match loader_uuid(&name) {
+            Some(("Pop_OS", uuid)) if uuid == root_uuid => {
+                stale.extend(stale_kernelstub_files(&entry.path(), &relative, &referenced));
+                continue;
+            }
+            Some(("Pop_OS", uuid)) if !exists(uuid) => {
+                "kernels for a root partition that no longer exists"
+            }
+            Some(("Recovery", uuid)) if !exists(uuid) => {
+                // Recovery entries of the missing partition cannot boot, so they are removed
+                // alongside its loader, unless another entry still uses the loader.
+                let (recovery, others): (Vec<_>, Vec<_>) = conf
+                    .entries
+                    .iter()
+                    .filter(|entry| is_referenced(&relative, &files_of(entry)))
+                    .partition(|entry| entry.id.starts_with("Recovery"));
+
+                if !others.is_empty() {
+                    continue;
+                }
+
+                for entry in recovery {
+                    let path = conf.entries_path.join([&*entry.id, ".conf"].concat());
+                    let size = disk_usage(&path);
+                    let reason = "boot entry for a recovery partition that no longer exists";
+                    stale.push(StaleEntry { path, size, reason });
+                }
+
+                let path = entry.path();
+                let reason = "loader for a recovery partition that no longer exists";
+                stale.push(StaleEntry { size: disk_usage(&path), path, reason });
+                continue;
+            }
+            _ => continue,
+        };
+
+        // Another installation sharing this ESP may still use it.
+        if is_referenced(&relative, &referenced) {
+            continue;
+        }
+
+        stale.push(StaleEntry { size: disk_usage(&entry.path()), path: entry.path(), reason });
+    }
+
+    Ok(stale)
+}
+
+/// Remove the stale entries, and return the number of bytes which were freed.
+pub fn clean(entries: &[StaleEntry]) -> io::Result<u64> {
+    let mut freed = 0;
+
+    for entry in entries {
+        info!("removing {} from the ESP: {}", entry.path.display(), entry.reason);
+
+        if entry.path.is_dir() {
+            fs::remove_dir_all(&entry.path)?;
+        } else {
+            fs::remove_file(&entry.path)?;
+        }
+
+        freed += entry.size;
+    }
+
+    Ok(freed)
+}
+
+/// The UUIDs of the file systems of every block device of the system.
+fn partition_uuids() -> io::Result<Vec<String>> {
+    fs::read_dir(DISK_BY_UUID)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn is_applicable() -> bool {
+    SystemEnvironment::detect() == SystemEnvironment::Efi && Path::new(ESP_EFI).exists()
+}
+
+/// Splits a directory name such as `Pop_OS-<uuid>` into its prefix and UUID.
+fn loader_uuid(name: &str) -> Option<(&str, &str)> {
+    let (prefix, uuid) = name.split_once('-')?;
+
+    if uuid.is_empty() || !uuid.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return None;
+    }
+
+    Some((prefix, uuid))
+}
+
+/// Files which kernelstub does not manage, and no boot entry uses.
+fn stale_kernelstub_files(
+    directory: &Path,
+    relative: &str,
+    referenced: &[String],
+) -> Vec<StaleEntry> {
+    let files = match fs::read_dir(directory) {
+        Ok(files) => files,
+        Err(_) => return Vec::new(),
+    };
+
+    files
+        .flatten()
+        .filter_map(|file| {
+            let name = file.file_name().into_string().ok()?;
+
+            if KERNELSTUB_FILES.contains(&name.as_str())
+                || !(name.starts_with("vmlinuz") || name.starts_with("initrd"))
+                || is_referenced(&[relative, "/", &name].concat(), referenced)
+            {
+                return None;
+            }
+
+            let size = file.metadata().ok()?.len();
+            Some(StaleEntry { path: file.path(), size, reason: "orphaned kernel or initrd copy" })
+        })
+        .collect()
+}
+
+fn is_referenced(path: &str, referenced: &[String]) -> bool {
+    referenced.iter().any(|file| {
+        file.eq_ignore_ascii_case(path)
+            || (file.get(..path.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(path))
+                && file.as_bytes().get(path.len()) == Some(&b'/'))
+    })
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+
+    if !meta.is_dir() {
+        return meta.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| disk_usage(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loader_uuids() {
+        assert_eq!(
+            Some(("Pop_OS", "2c5a7e6f-7a9e-4d35-8d6b-1f4c9a9d5d11")),
+            loader_uuid("Pop_OS-2c5a7e6f-7a9e-4d35-8d6b-1f4c9a9d5d11")
+        );
+        assert_eq!(Some(("Recovery", "5A3F-9C21")), loader_uuid("Recovery-5A3F-9C21"));
+        assert_eq!(None, loader_uuid("systemd"));
+        assert_eq!(None, loader_uuid("Pop_OS-"));
+        assert_eq!(None, loader_uuid("Pop_OS-current"));
+    }
+
+    #[test]
+    fn referenced_paths() {
+        let referenced = vec![
+            String::from("EFI/Pop_OS-2c5a/vmlinuz.efi"),
+            String::from("EFI/Recovery-5A3F/vmlinuz.efi"),
+        ];
+
+        assert!(is_referenced("EFI/Pop_OS-2c5a", &referenced));
+        assert!(is_referenced("efi/recovery-5a3f", &referenced));
+        assert!(is_referenced("EFI/Pop_OS-2c5a/vmlinuz.efi", &referenced));
+        assert!(!is_referenced("EFI/Pop_OS-2c5", &referenced));
+        assert!(!is_referenced("EFI/Recovery-9C21", &referenced));
+    }
+}
@@ -1,39 +1,362 @@
 //! All code responsible for validating and repair the /etc/fstab file.
 
+use super::{RepairCheck, RepairError};
 use crate::system_environment::SystemEnvironment;
 use anyhow::Context;
-use std::process::Command;
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
 use thiserror::Error;
 
+const FSTAB: &str = "/etc/fstab";
+const FSTAB_TMP: &str = "/etc/fstab.tmp";
+const FSTAB_BACKUP: &str = "/etc/fstab.pop-upgrade";
+
+const REQUIRED_MOUNTS: &[&str] = &["/", "/boot/efi"];
+
 #[derive(Debug, Error)]
 pub enum FstabError {
     #[error("failed to mount devices with `mount -a`")]
     MountFailure(#[source] anyhow::Error),
+
+    #[error("failed to read /etc/fstab")]
+    Read(#[source] io::Error),
+
+    #[error("failed to write /etc/fstab")]
+    Write(#[source] io::Error),
+}
+
+/// A mount described by a line of the fstab.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FstabEntry {
+    /// The zero-indexed line of the fstab which describes this entry.
+    pub line:    usize,
+    pub source:  String,
+    pub target:  String,
+    pub fstype:  String,
+    pub options: Vec<String>,
+}
+
+impl FstabEntry {
+    pub fn has_option(&self, option: &str) -> bool { self.options.iter().any(|opt| opt == option) }
+}
+
+/// An entry of the fstab which may prevent the system from booting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FstabIssue {
+    pub line:   usize,
+    pub target: String,
+    pub reason: String,
+}
+
+impl FstabIssue {
+    /// Entries for partitions that the upgrade requires must be repaired by hand.
+    pub fn is_required(&self) -> bool { REQUIRED_MOUNTS.contains(&self.target.as_str()) }
+}
+
+/// The partitions required by the upgrade must be mounted.
+pub struct Mounts;
+
+impl RepairCheck for Mounts {
+    fn id(&self) -> &'static str { "mounts" }
+
+    fn description(&self) -> &'static str { "partitions required by the upgrade are mounted" }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let detected = if SystemEnvironment::detect() == SystemEnvironment::Efi {
+            unmounted_partitions().map(|unmounted| {
+                unmounted
+                    .iter()
+                    .map(|mount| format!("{} is not mounted", mount))
+                    .collect::<Vec<_>>()
+            })
+        } else {
+            Ok(Vec::new())
+        };
+
+        future::ready(detected.map_err(|why| RepairError::Fstab(FstabError::MountFailure(why))))
+            .boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let fixed = mount_required_partitions().map(|mounted| {
+            mounted.into_iter().map(|mount| format!("mounted {}", mount)).collect::<Vec<_>>()
+        });
+
+        future::ready(fixed.map_err(|why| RepairError::Fstab(FstabError::MountFailure(why))))
+            .boxed_local()
+    }
 }
 
-/// Performs the following Pop-specific actions:
+/// Entries of the fstab must not prevent the system from booting.
+pub struct Fstab;
+
+impl RepairCheck for Fstab {
+    fn id(&self) -> &'static str { "fstab" }
+
+    fn description(&self) -> &'static str { "fstab entries are valid" }
+
+    // Rewriting the fstab may change how the system boots, so issues are only reported.
+    fn automatic(&self) -> bool { false }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let detected = system_issues().map(|issues| {
+            issues
+                .into_iter()
+                .map(|issue| {
+                    let manual =
+                        if issue.is_required() { " (requires manual repair)" } else { "" };
+                    format!("line {}: {}{}", issue.line + 1, issue.reason, manual)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        future::ready(detected.map_err(RepairError::Fstab)).boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let fixed = disable_invalid_entries().map(|issues| {
+            issues
+                .into_iter()
+                .map(|issue| format!("commented out line {} for {}", issue.line + 1, issue.target))
+                .collect::<Vec<_>>()
+        });
+
+        future::ready(fixed.map_err(RepairError::Fstab)).boxed_local()
+    }
+}
+
+/// Comments out entries of the fstab which may prevent the system from booting, and returns
+/// the issues of the entries which were disabled.
 ///
-/// - Ensures that `/boot/efi` and `/` are mounted.
-pub fn repair() -> Result<(), FstabError> {
-    if SystemEnvironment::detect() != SystemEnvironment::Efi {
-        return Ok(());
+/// Entries for partitions required by the upgrade are left as they are.
+pub fn disable_invalid_entries() -> Result<Vec<FstabIssue>, FstabError> {
+    let contents = match fs::read_to_string(FSTAB) {
+        Ok(contents) => contents,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(FstabError::Read(why)),
+    };
+
+    let issues = find_issues(&parse(&contents), resolve_tag, is_removable)
+        .into_iter()
+        .filter(|issue| !issue.is_required())
+        .collect::<Vec<_>>();
+
+    if issues.is_empty() {
+        return Ok(issues);
     }
 
-    // Ensure that all devices have been mounted before proceeding.
-    mount_required_partitions().map_err(FstabError::MountFailure)
+    fs::copy(FSTAB, FSTAB_BACKUP).map_err(FstabError::Write)?;
+    fs::write(FSTAB_TMP, comment_out(&contents, &issues)).map_err(FstabError::Write)?;
+    fs::rename(FSTAB_TMP, FSTAB).map_err(FstabError::Write)?;
+
+    Ok(issues)
+}
+
+/// Entries of the system's fstab which may prevent the system from booting.
+pub fn system_issues() -> Result<Vec<FstabIssue>, FstabError> {
+    match fs::read_to_string(FSTAB) {
+        Ok(contents) => Ok(find_issues(&parse(&contents), resolve_tag, is_removable)),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(why) => Err(FstabError::Read(why)),
+    }
 }
 
-/// Ensure that the necessary mount points are mounted.
-fn mount_required_partitions() -> anyhow::Result<()> {
+/// Parses each mount entry of a fstab, skipping comments and malformed lines.
+pub fn parse(contents: &str) -> Vec<FstabEntry> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                return None;
+            }
+
+            let mut fields = text.split_whitespace();
+            let source = unescape(fields.next()?);
+            let target = unescape(fields.next()?);
+            let fstype = fields.next()?.to_owned();
+            let options =
+                fields.next().unwrap_or("defaults").split(',').map(String::from).collect();
+
+            Some(FstabEntry { line, source, target, fstype, options })
+        })
+        .collect()
+}
+
+/// Finds entries whose device no longer exists, entries which duplicate the mount point of
+/// an earlier entry, and entries for removable disks which would hang the boot if removed.
+pub fn find_issues(
+    entries: &[FstabEntry],
+    resolve: impl Fn(&str) -> Option<Option<PathBuf>>,
+    removable: impl Fn(&Path) -> bool,
+) -> Vec<FstabIssue> {
+    let mut issues = Vec::new();
+    let mut targets = HashSet::new();
+
+    for entry in entries {
+        let issue =
+            |reason: String| FstabIssue { line: entry.line, target: entry.target.clone(), reason };
+
+        let may_fail = entry.has_option("nofail") || entry.has_option("noauto");
+
+        match resolve(&entry.source) {
+            Some(None) => {
+                issues.push(issue(format!("{} does not exist", entry.source)));
+                continue;
+            }
+            Some(Some(device)) if !may_fail && removable(&device) => {
+                issues.push(issue(format!(
+                    "{} is on a removable disk, but is missing the `nofail` option",
+                    entry.source
+                )));
+                continue;
+            }
+            _ => (),
+        }
+
+        if entry.fstype != "swap" && entry.target != "none" && !targets.insert(&entry.target) {
+            issues.push(issue(format!("{} is already mounted by an earlier entry", entry.target)));
+        }
+    }
+
+    issues
+}
+
+/// Comments out the lines of the given issues, noting why each line was disabled.
+fn comment_out(contents: &str, issues: &[FstabIssue]) -> String {
+    let mut output = String::with_capacity(contents.len() + issues.len() * 64);
+
+    for (line, text) in contents.lines().enumerate() {
+        if let Some(issue) = issues.iter().find(|issue| issue.line == line) {
+            output.push_str("# disabled by pop-upgrade: ");
+            output.push_str(&issue.reason);
+            output.push_str("\n# ");
+        }
+
+        output.push_str(text);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Resolves a `UUID=`, `PARTUUID=`, or `LABEL=` device tag to the device it refers to.
+///
+/// Returns `None` for sources which are not device tags, and `Some(None)` if no device has
+/// the tag.
+fn resolve_tag(source: &str) -> Option<Option<PathBuf>> {
+    let (directory, value) = tag_path(source)?;
+    let path = Path::new("/dev/disk").join(directory).join(value);
+    Some(fs::canonicalize(path).ok())
+}
+
+/// The `/dev/disk` directory and file name of a device tag.
+fn tag_path(source: &str) -> Option<(&'static str, String)> {
+    let (tag, value) = source.split_once('=')?;
+
+    let value = value.trim_matches('"');
+    if value.is_empty() {
+        return None;
+    }
+
+    match tag {
+        "UUID" => Some(("by-uuid", value.to_owned())),
+        "PARTUUID" => Some(("by-partuuid", value.to_owned())),
+        "LABEL" => Some(("by-label", udev_escape(value))),
+        _ => None,
+    }
+}
+
+/// Checks if the device, or the disk that the partition belongs to, is removable.
+fn is_removable(device: &Path) -> bool {
+    let name = match device.file_name() {
+        Some(name) => name,
+        None => return false,
+    };
+
+    let sysfs = match fs::canonicalize(Path::new("/sys/class/block").join(name)) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+
+    // Partitions are nested within the directory of their disk.
+    let disk = if sysfs.join("partition").exists() {
+        sysfs.parent().map(Path::to_path_buf).unwrap_or(sysfs)
+    } else {
+        sysfs
+    };
+
+    fs::read_to_string(disk.join("removable")).is_ok_and(|value| value.trim() == "1")
+}
+
+/// Decodes the octal escapes used by fstab for whitespace, such as `\040`.
+fn unescape(field: &str) -> String {
+    let mut output = String::with_capacity(field.len());
+    let mut rest = field;
+
+    while let Some(pos) = rest.find('\\') {
+        output.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let decoded =
+            rest.get(1..4).and_then(|octal| u8::from_str_radix(octal, 8).ok()).filter(u8::is_ascii);
+
+        match decoded {
+            Some(byte) => {
+                output.push(byte as char);
+                rest = &rest[4..];
+            }
+            None => {
+                output.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Encodes a label the way that udev names its links in `/dev/disk/by-label`.
+fn udev_escape(label: &str) -> String {
+    let mut output = String::with_capacity(label.len());
+
+    for byte in label.bytes() {
+        if byte.is_ascii_alphanumeric() || b"#+-.:=@_".contains(&byte) || !byte.is_ascii() {
+            output.push(byte as char);
+        } else {
+            output.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+
+    output
+}
+
+/// Mount points required by the upgrade which are not mounted.
+fn unmounted_partitions() -> anyhow::Result<Vec<&'static str>> {
     // Check /proc/mounts for existing mountpoints rather than relying entirely
     // on mount(1), which gets confused by ZFS filesystems (which might be
     // managed by e.g. zfs-mount.service).
     let mounts = proc_mounts::MountList::new().context("failed to read /proc/mounts")?;
 
-    for mount_point in &["/", "/boot/efi"] {
-        if let Some(_) = mounts.get_mount_by_dest(mount_point) {
-            continue; // Already mounted.
-        }
+    Ok(REQUIRED_MOUNTS
+        .iter()
+        .copied()
+        .filter(|mount_point| mounts.get_mount_by_dest(mount_point).is_none())
+        .collect())
+}
+
+/// Ensure that the necessary mount points are mounted, returning those which were mounted.
+fn mount_required_partitions() -> anyhow::Result<Vec<&'static str>> {
+    let unmounted = unmounted_partitions()?;
+
+    for mount_point in &unmounted {
         Command::new("mount")
             .arg(mount_point)
             .status()
@@ -48,5 +371,72 @@ fn mount_required_partitions() -> anyhow::Result<()> {
             })?;
     }
 
-    Ok(())
+    Ok(unmounted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FSTAB: &str = r#"# /etc/fstab: static file system information.
+PARTUUID=0a6e8c52-5a51-4d8e-9f2b-91c3c2f1a0d1  /boot/efi  vfat  umask=0077  0  0
+UUID=2c5a7e6f-7a9e-4d35-8d6b-1f4c9a9d5d11  /  ext4  noatime,errors=remount-ro  0  0
+/dev/mapper/cryptswap  none  swap  defaults  0  0
+UUID=5f1c0b6e-0000-4000-8000-000000000000  /mnt/old\040drive  ext4  defaults  0  2
+LABEL=Backups  /mnt/backups  ext4  defaults  0  2
+LABEL=Photos  /mnt/photos  ext4  defaults,nofail  0  2
+UUID=2c5a7e6f-7a9e-4d35-8d6b-1f4c9a9d5d11  /  ext4  defaults  0  0
+"#;
+
+    fn resolve(source: &str) -> Option<Option<PathBuf>> {
+        match source {
+            "UUID=5f1c0b6e-0000-4000-8000-000000000000" => Some(None),
+            "LABEL=Backups" => Some(Some(PathBuf::from("/dev/sdb1"))),
+            "LABEL=Photos" => Some(Some(PathBuf::from("/dev/sdc1"))),
+            _ if source.contains('=') => Some(Some(PathBuf::from("/dev/nvme0n1p1"))),
+            _ => None,
+        }
+    }
+
+    fn removable(device: &Path) -> bool { device.to_string_lossy().starts_with("/dev/sd") }
+
+    #[test]
+    fn parse_entries() {
+        let entries = parse(FSTAB);
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[0].line, 1);
+        assert_eq!(entries[0].target, "/boot/efi");
+        assert_eq!(entries[3].target, "/mnt/old drive");
+        assert!(entries[5].has_option("nofail"));
+    }
+
+    #[test]
+    fn issues() {
+        let issues = find_issues(&parse(FSTAB), resolve, removable);
+
+        let lines = issues.iter().map(|issue| issue.line).collect::<Vec<_>>();
+        assert_eq!(lines, [4, 5, 7]);
+        assert!(!issues[0].is_required());
+        assert!(issues[2].is_required());
+    }
+
+    #[test]
+    fn comment_out_lines() {
+        let issues = find_issues(&parse(FSTAB), resolve, removable);
+        let output = comment_out(FSTAB, &issues[..1]);
+
+        assert!(output.contains(
+            "# disabled by pop-upgrade: UUID=5f1c0b6e-0000-4000-8000-000000000000 does not \
+             exist\n# UUID=5f1c0b6e"
+        ));
+        assert_eq!(parse(&output).len(), 6);
+    }
+
+    #[test]
+    fn tag_paths() {
+        assert_eq!(Some(("by-uuid", "abcd-1234".into())), tag_path("UUID=abcd-1234"));
+        assert_eq!(Some(("by-label", "My\\x20Disk".into())), tag_path(r#"LABEL="My Disk""#));
+        assert_eq!(None, tag_path("/dev/sda1"));
+        assert_eq!(None, tag_path("UUID="));
+    }
 }
@@ -1,21 +1,102 @@
+use super::{RepairCheck, RepairError, Stage};
+use futures::future::{self, FutureExt, LocalBoxFuture};
 use std::{
     cmp::Ordering,
     fs::{self, File},
     io::{self, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+const UNAFFECTED: &str = "5.3.0";
+const BADFLAGS: [&str; 2] = [" -mindirect-branch=thunk-extern", " -mindirect-branch=thunk-inline"];
+
+/// Kernel sources with flags that GCC 9 does not support.
+pub struct DkmsGcc9;
+
+impl RepairCheck for DkmsGcc9 {
+    fn id(&self) -> &'static str { "dkms-gcc9" }
+
+    fn description(&self) -> &'static str { "old kernel sources can be built by dkms with GCC 9" }
+
+    fn stage(&self) -> Stage { Stage::PreUpgrade }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let detected = affected_makefiles()
+            .map(|makefiles| {
+                makefiles
+                    .iter()
+                    .map(|makefile| {
+                        format!("{} has flags incompatible with GCC 9", makefile.display())
+                    })
+                    .collect()
+            })
+            .map_err(RepairError::DkmsGcc9);
+
+        future::ready(detected).boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let fixed = dkms_gcc9_fix()
+            .map(|makefiles| {
+                makefiles.iter().map(|makefile| format!("patched {}", makefile.display())).collect()
+            })
+            .map_err(RepairError::DkmsGcc9);
+
+        future::ready(fixed).boxed_local()
+    }
+}
+
+/// PulseAudio settings which may be incompatible with the new release.
+pub struct WipePulse;
+
+impl RepairCheck for WipePulse {
+    fn id(&self) -> &'static str { "wipe-pulse" }
+
+    fn description(&self) -> &'static str { "users have no stale pulseaudio settings" }
+
+    fn stage(&self) -> Stage { Stage::PreUpgrade }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let found = pulse_settings()
+            .iter()
+            .map(|path| format!("{} will be reset", path.display()))
+            .collect();
+
+        future::ready(Ok(found)).boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        let fixed = wipe_pulse()
+            .map(|paths| paths.iter().map(|path| format!("removed {}", path.display())).collect())
+            .map_err(RepairError::WipePulse);
+
+        future::ready(fixed).boxed_local()
+    }
+}
+
 // On the upgrade to 19.10, dkms is unable to build modules for kernels older than 5.3
 // because of incompatible options in GCC 9. This can be fixed by removing the
 // incompatible options in the `Makefile` of these kernels.
 //
 // Canonical will be releasing updated kernels, but it is possible that someone upgrading
 // from either 18.04 or 19.04 may still have sources for an older kernel.
-pub fn dkms_gcc9_fix() -> io::Result<()> {
-    const UNAFFECTED: &str = "5.3.0";
-    const BADFLAGS: [&str; 2] =
-        [" -mindirect-branch=thunk-extern", " -mindirect-branch=thunk-inline"];
+pub fn dkms_gcc9_fix() -> io::Result<Vec<PathBuf>> {
+    let makefiles = affected_makefiles()?;
+
+    for makefile in &makefiles {
+        let mut data = fs::read_to_string(makefile)?;
+        let mut file = File::create(makefile)?;
+
+        data = data.replace(BADFLAGS[0], "");
+        data = data.replace(BADFLAGS[1], "");
+        file.write_all(data.as_bytes())?;
+    }
 
+    Ok(makefiles)
+}
+
+/// Makefiles of kernels older than 5.3 which contain flags that GCC 9 rejects.
+fn affected_makefiles() -> io::Result<Vec<PathBuf>> {
     let lib_modules_dir: &'static Path = ["/lib/modules", "/usr/lib/modules"]
         .iter()
         .map(Path::new)
@@ -27,37 +108,43 @@ pub fn dkms_gcc9_fix() -> io::Result<()> {
             )
         })?;
 
+    let mut makefiles = Vec::new();
+
     for dir in fs::read_dir(lib_modules_dir)?.flatten() {
         if let Some(file_name) = dir.file_name().to_str() {
             if human_sort::compare(file_name, UNAFFECTED) == Ordering::Less {
                 let makefile = dir.path().join("build/Makefile");
                 if makefile.exists() {
-                    let mut data = fs::read_to_string(&makefile)?;
-                    let mut file = File::create(&makefile)?;
-
-                    data = data.replace(BADFLAGS[0], "");
-                    data = data.replace(BADFLAGS[1], "");
-                    file.write_all(data.as_bytes())?;
+                    let data = fs::read_to_string(&makefile)?;
+                    if BADFLAGS.iter().any(|flag| data.contains(flag)) {
+                        makefiles.push(makefile);
+                    }
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(makefiles)
 }
 
-/// Remove the pulse directory from each user.
-pub fn wipe_pulse() -> io::Result<()> {
-    for user in pwd::Passwd::iter() {
-        let path = Path::new(&*user.dir).join(".config/pulse");
-        if path.exists() {
-            if path.is_dir() {
-                std::fs::remove_dir_all(&path)?;
-            } else {
-                std::fs::remove_file(&path)?;
-            }
+/// Remove the pulse directory from each user, and return the paths which were removed.
+pub fn wipe_pulse() -> io::Result<Vec<PathBuf>> {
+    let paths = pulse_settings();
+
+    for path in &paths {
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_file(path)?;
         }
     }
 
-    Ok(())
+    Ok(paths)
+}
+
+fn pulse_settings() -> Vec<PathBuf> {
+    pwd::Passwd::iter()
+        .map(|user| Path::new(&*user.dir).join(".config/pulse"))
+        .filter(|path| path.exists())
+        .collect()
 }
@@ -1,9 +1,11 @@
 pub mod crypttab;
+pub mod esp;
 pub mod fstab;
 pub mod misc;
 pub mod packaging;
 
 use self::fstab::FstabError;
+use futures::future::LocalBoxFuture;
 use std::io;
 use thiserror::Error;
 
@@ -15,6 +17,9 @@ pub enum RepairError {
     #[error("unable to apply dkms gcc9 fix")]
     DkmsGcc9(#[source] io::Error),
 
+    #[error("failed to clean stale files from the EFI system partition")]
+    Esp(#[source] anyhow::Error),
+
     #[error("error checking and fixing fstab")]
     Fstab(#[source] FstabError),
 
@@ -24,23 +29,240 @@ pub enum RepairError {
     #[error("packaging error")]
     Packaging(#[source] anyhow::Error),
 
+    #[error("no repair check exists with the ID `{}`", _0)]
+    UnknownCheck(String),
+
     #[error("failed to wipe pulseaudio settings for users")]
     WipePulse(#[source] io::Error),
 }
 
-pub async fn repair() -> Result<(), RepairError> {
+/// When a repair check is applied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// Applied by `release repair`, and before each release upgrade.
+    Repair,
+    /// Applied immediately before the sources are switched to the new release.
+    PreUpgrade,
+}
+
+impl From<Stage> for &'static str {
+    fn from(stage: Stage) -> Self {
+        match stage {
+            Stage::Repair => "repair",
+            Stage::PreUpgrade => "pre-upgrade",
+        }
+    }
+}
+
+/// Which of the issues that were detected are fixed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fix {
+    /// Issues are only reported.
+    None,
+    /// Issues are fixed by the checks whose fixes are safe to apply unattended.
+    Automatic,
+    /// Issues are fixed by every check, as requested by `release repair --only`.
+    All,
+}
+
+/// An issue which may be detected on a system, and the means to fix it.
+pub trait RepairCheck: Send + Sync {
+    /// A unique name for the check, which may be given to `release repair --only`.
+    fn id(&self) -> &'static str;
+
+    /// A short description of what is checked.
+    fn description(&self) -> &'static str;
+
+    fn stage(&self) -> Stage { Stage::Repair }
+
+    /// Whether the fix may be applied without being requested, such as when an upgrade fails.
+    fn automatic(&self) -> bool { true }
+
+    /// Whether the fix is applied even when no issues were detected.
+    fn unconditional(&self) -> bool { false }
+
+    /// Describes each issue that was found, without modifying the system.
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>>;
+
+    /// Fixes the issues that were detected, and describes each change that was made.
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>>;
+}
+
+/// What a repair check found, and what it changed.
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    pub id:      String,
+    pub found:   Vec<String>,
+    pub changed: Vec<String>,
+    pub error:   Option<String>,
+}
+
+impl RepairReport {
+    /// Convert the report into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> (String, Vec<String>, Vec<String>, String) {
+        (self.id, self.found, self.changed, self.error.unwrap_or_default())
+    }
+
+    /// Reconstruct a report that was transferred over DBus.
+    pub fn from_dbus(
+        (id, found, changed, error): (String, Vec<String>, Vec<String>, String),
+    ) -> Self {
+        Self { id, found, changed, error: if error.is_empty() { None } else { Some(error) } }
+    }
+}
+
+/// Every repair check known to the daemon, in the order that they are applied.
+///
+/// The `release` is the codename of the release which is currently installed.
+pub fn checks(release: &str) -> Vec<Box<dyn RepairCheck>> {
+    vec![
+        Box::new(crypttab::Crypttab),
+        Box::new(fstab::Mounts),
+        Box::new(fstab::Fstab),
+        Box::new(esp::Esp),
+        Box::new(packaging::Packaging { release: release.to_owned() }),
+        Box::new(misc::DkmsGcc9),
+        Box::new(misc::WipePulse),
+    ]
+}
+
+/// Selects the checks with the given IDs, or every check of the repair stage if none are given.
+pub fn select(release: &str, only: &[String]) -> Result<Vec<Box<dyn RepairCheck>>, RepairError> {
+    let mut checks = checks(release);
+
+    if only.is_empty() {
+        checks.retain(|check| check.stage() == Stage::Repair);
+        return Ok(checks);
+    }
+
+    if let Some(unknown) = only.iter().find(|id| !checks.iter().any(|check| check.id() == *id)) {
+        return Err(RepairError::UnknownCheck(unknown.clone()));
+    }
+
+    checks.retain(|check| only.iter().any(|id| id == check.id()));
+    Ok(checks)
+}
+
+/// Applies each check in order, reporting what each check found and changed.
+///
+/// A check which fails does not prevent the remaining checks from being applied. The first
+/// error encountered is returned alongside the reports.
+pub async fn run(
+    checks: &[Box<dyn RepairCheck>],
+    fix: Fix,
+    report: &dyn Fn(&RepairReport),
+) -> (Vec<RepairReport>, Result<(), RepairError>) {
+    let mut reports = Vec::with_capacity(checks.len());
+    let mut result = Ok(());
+
+    for check in checks {
+        let mut outcome = RepairReport { id: check.id().to_owned(), ..RepairReport::default() };
+
+        info!("repair check {}: {}", check.id(), check.description());
+
+        let applied = async {
+            outcome.found = check.detect().await?;
+
+            let fixed = match fix {
+                Fix::None => false,
+                Fix::Automatic => check.automatic(),
+                Fix::All => true,
+            };
+
+            if fixed && (check.unconditional() || !outcome.found.is_empty()) {
+                outcome.changed = check.fix().await?;
+            } else if fix == Fix::Automatic && !outcome.found.is_empty() {
+                info!(
+                    "repair check {} was not fixed: run `pop-upgrade release repair --only {}`",
+                    check.id(),
+                    check.id()
+                );
+            }
+
+            Ok::<(), RepairError>(())
+        }
+        .await;
+
+        for found in &outcome.found {
+            info!("repair check {} found: {}", check.id(), found);
+        }
+
+        for changed in &outcome.changed {
+            info!("repair check {} changed: {}", check.id(), changed);
+        }
+
+        if let Err(why) = applied {
+            let why_str = crate::misc::format_error(&why);
+            error!("repair check {} failed: {}", check.id(), why_str);
+            outcome.error = Some(why_str);
+
+            if result.is_ok() {
+                result = Err(why);
+            }
+        }
+
+        report(&outcome);
+        reports.push(outcome);
+    }
+
+    (reports, result)
+}
+
+/// Applies every check of the repair stage to the system, only reporting the issues of checks
+/// which must be fixed by request.
+pub async fn repair(report: &dyn Fn(&RepairReport)) -> Result<(), RepairError> {
     info!("performing release repair");
 
     let release = &os_release::OS_RELEASE.as_ref().unwrap().version_codename;
 
-    crypttab::repair().map_err(RepairError::Crypttab)?;
-    fstab::repair().map_err(RepairError::Fstab)?;
-    packaging::repair(release).await.map_err(RepairError::Packaging)?;
+    run(&select(release, &[])?, Fix::Automatic, report).await.1
+}
+
+/// Applies the checks which must be performed before switching to the new release.
+pub async fn pre_upgrade(release: &str, report: &dyn Fn(&RepairReport)) -> Result<(), RepairError> {
+    let mut checks = checks(release);
+    checks.retain(|check| check.stage() == Stage::PreUpgrade);
 
-    Ok(())
+    run(&checks, Fix::Automatic, report).await.1
 }
 
-pub fn pre_upgrade() -> Result<(), RepairError> {
-    misc::dkms_gcc9_fix().map_err(RepairError::DkmsGcc9)?;
-    misc::wipe_pulse().map_err(RepairError::WipePulse)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(checks: &[Box<dyn RepairCheck>]) -> Vec<&'static str> {
+        checks.iter().map(|check| check.id()).collect()
+    }
+
+    #[test]
+    fn select_repair_stage() {
+        let checks = select("jammy", &[]).unwrap();
+        assert_eq!(ids(&checks), ["crypttab", "mounts", "fstab", "esp", "packaging"]);
+    }
+
+    #[test]
+    fn select_only() {
+        let only = ["wipe-pulse".to_owned(), "fstab".to_owned()];
+        let checks = select("jammy", &only).unwrap();
+        assert_eq!(ids(&checks), ["fstab", "wipe-pulse"]);
+
+        let only = ["nonexistent".to_owned()];
+        let result = select("jammy", &only);
+        assert!(matches!(result, Err(RepairError::UnknownCheck(id)) if id == "nonexistent"));
+    }
+
+    #[test]
+    fn report_dbus() {
+        let report = RepairReport {
+            id:      "mounts".into(),
+            found:   vec!["/boot/efi is not mounted".into()],
+            changed: vec!["mounted /boot/efi".into()],
+            error:   None,
+        };
+
+        let report = RepairReport::from_dbus(report.into_dbus());
+        assert_eq!(report.id, "mounts");
+        assert_eq!(report.changed, ["mounted /boot/efi"]);
+        assert!(report.error.is_none());
+    }
 }
@@ -1,42 +1,95 @@
+use super::{RepairCheck, RepairError};
 use crate::{
     release::repos::{iter_files, PPA_DIR},
     ubuntu_version::Codename,
 };
 use anyhow::Context;
 use apt_cmd::{lock::apt_lock_wait, AptGet, Dpkg};
-use futures::StreamExt;
-use std::fs;
+use futures::{future::LocalBoxFuture, FutureExt, StreamExt};
+use std::{fs, path::PathBuf, process::Command};
+
+const CODENAMES: &[Codename] = &[
+    Codename::Focal,
+    Codename::Groovy,
+    Codename::Hirsute,
+    Codename::Impish,
+    Codename::Jammy,
+    Codename::Noble,
+];
+
+/// Broken packages, sources for other releases, and outdated prerequisites.
+pub struct Packaging {
+    pub release: String,
+}
+
+impl RepairCheck for Packaging {
+    fn id(&self) -> &'static str { "packaging" }
+
+    fn description(&self) -> &'static str { "packages are configured and sources are current" }
+
+    // Not every interrupted or broken install is visible to `dpkg --audit` and `apt-get check`.
+    fn unconditional(&self) -> bool { true }
+
+    fn detect(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        async move {
+            let mut found = Vec::new();
+
+            if !dpkg_audit().is_empty() {
+                found.push("some packages are not fully installed or configured".to_owned());
+            }
+
+            if !Command::new("apt-get").arg("check").output().is_ok_and(|out| out.status.success())
+            {
+                found.push("package dependencies are broken".to_owned());
+            }
+
+            for path in mismatched_sources(&self.release) {
+                found.push(format!("{} refers to another release", path.display()));
+            }
+
+            if crate::release::repos::is_old_release(&self.release).await {
+                found.push(format!("{} has moved to old-releases", self.release));
+            }
+
+            let outdated = outdated_prerequisites().await.map_err(RepairError::Packaging)?;
+            for package in outdated {
+                found.push(format!("{} is not at its candidate version", package));
+            }
+
+            Ok(found)
+        }
+        .boxed_local()
+    }
+
+    fn fix(&self) -> LocalBoxFuture<'_, Result<Vec<String>, RepairError>> {
+        async move { repair(&self.release).await.map_err(RepairError::Packaging) }.boxed_local()
+    }
+}
+
+/// Repairs the packaging state of the system, and describes each change that was made.
+pub async fn repair(release: &str) -> anyhow::Result<Vec<String>> {
+    let mut changed = Vec::new();
 
-pub async fn repair(release: &str) -> anyhow::Result<()> {
     apt_lock_wait().await;
-    if let Ok(ppas) = std::fs::read_dir(PPA_DIR) {
-        for file in iter_files(ppas) {
-            let path = file.path();
-            if let Ok(contents) = fs::read_to_string(&path) {
-                let modified = contents
-                    .replace(<&str>::from(Codename::Focal), release)
-                    .replace(<&str>::from(Codename::Groovy), release)
-                    .replace(<&str>::from(Codename::Hirsute), release)
-                    .replace(<&str>::from(Codename::Impish), release)
-                    .replace(<&str>::from(Codename::Jammy), release)
-                    .replace(<&str>::from(Codename::Noble), release);
-
-                if modified != contents {
-                    let _ = fs::write(&path, modified.as_bytes());
-                }
+    for path in mismatched_sources(release) {
+        if let Ok(contents) = fs::read_to_string(&path) {
+            if fs::write(&path, replace_codenames(&contents, release).as_bytes()).is_ok() {
+                changed.push(format!("switched {} to {}", path.display(), release));
             }
         }
     }
 
     if crate::release::repos::is_old_release(release).await {
         info!("switching to old-releases repositories");
-        let _ = crate::release::repos::replace_with_old_releases();
+        if crate::release::repos::replace_with_old_releases().is_ok() {
+            changed.push("switched Ubuntu repositories to old-releases".to_owned());
+        }
     }
 
     apt_lock_wait().await;
     let _ = AptGet::new().update().await;
 
-    let mut last_error = Ok(());
+    let mut last_error = Ok(Vec::new());
 
     for _ in 0..3i32 {
         apt_lock_wait().await;
@@ -62,7 +115,43 @@ pub async fn repair(release: &str) -> anyhow::Result<()> {
         }
     }
 
-    last_error
+    let installed = last_error?;
+
+    changed.push("reconfigured packages and fixed broken dependencies".to_owned());
+    changed.extend(installed.into_iter().map(|package| format!("installed {}", package)));
+
+    Ok(changed)
+}
+
+/// Output of `dpkg --audit`, which lists packages that are not fully installed or configured.
+fn dpkg_audit() -> String {
+    Command::new("dpkg")
+        .arg("--audit")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .unwrap_or_default()
+}
+
+/// Sources in the PPA directory which refer to a release other than the one installed.
+fn mismatched_sources(release: &str) -> Vec<PathBuf> {
+    let ppas = match fs::read_dir(PPA_DIR) {
+        Ok(ppas) => ppas,
+        Err(_) => return Vec::new(),
+    };
+
+    iter_files(ppas)
+        .map(|file| file.path())
+        .filter(|path| {
+            fs::read_to_string(path)
+                .is_ok_and(|contents| replace_codenames(&contents, release) != contents)
+        })
+        .collect()
+}
+
+fn replace_codenames(contents: &str, release: &str) -> String {
+    CODENAMES.iter().fold(contents.to_owned(), |contents, &codename| {
+        contents.replace(<&str>::from(codename), release)
+    })
 }
 
 const PROBLEMATIC_PACKAGES: &[&str] = &[
@@ -76,27 +165,37 @@ const PROBLEMATIC_PACKAGES: &[&str] = &[
     "libmount1:i386",
 ];
 
-async fn base_requirements() -> anyhow::Result<()> {
-    info!("ensuring prerequisites are installed");
-
+/// Problematic packages whose installed version differs from their candidate.
+async fn outdated_prerequisites() -> anyhow::Result<Vec<String>> {
     // Fetch apt-cache policies for each of the problematic packages.
     let (mut child, policies) = apt_cmd::AptCache::new().policy(PROBLEMATIC_PACKAGES).await?;
 
     // Remember which packages are installed with candidates.
-    let mut to_install = Vec::new();
+    let mut outdated = Vec::new();
 
     futures_util::pin_mut!(policies);
 
     while let Some(policy) = policies.next().await {
         if policy.installed != "(none)" && policy.installed != policy.candidate {
-            to_install.push(format!("{}={}", policy.package, policy.candidate));
+            outdated.push(format!("{}={}", policy.package, policy.candidate));
         }
     }
 
     let _ = child.wait();
 
+    Ok(outdated)
+}
+
+/// Installs the candidate versions of problematic packages, and returns those installed.
+async fn base_requirements() -> anyhow::Result<Vec<String>> {
+    info!("ensuring prerequisites are installed");
+
+    let to_install = outdated_prerequisites().await?;
+
     info!("installing required prerequisites: {:?}", to_install);
 
     // Ensure that the packages have their candidate versions installed.
-    crate::misc::apt_get().install(to_install).await.context("failed to install prerequisites")
+    crate::misc::apt_get().install(&to_install).await.context("failed to install prerequisites")?;
+
+    Ok(to_install)
 }
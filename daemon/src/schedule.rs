@@ -0,0 +1,380 @@
+//! When the daemon should install package updates by itself.
+//!
+//! Updates are either installed within a weekly maintenance window, such as Sundays from 02:00
+//! to 04:00, or staged when the system is shutting down, to be installed at the next startup.
+//! Windows which end before they start continue past midnight, into the following day.
+
+use crate::{
+    predownload::{NotReady, SystemStatus, LOGIND_IFACE, LOGIND_NAME, LOGIND_PATH},
+    VAR_LIB_DIR,
+};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use const_format::concatcp;
+use dbus::{arg::OwnedFd, blocking::Connection};
+use num_traits::FromPrimitive;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs, io,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+pub const SCHEDULE: &str = concatcp!(VAR_LIB_DIR, "/schedule.json");
+
+/// How often the daemon checks if it is ready to install updates within a window.
+const RECHECK: Duration = Duration::from_secs(60);
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("maintenance window has no days")]
+    NoDays,
+
+    #[error("maintenance window must not start and end at the same time")]
+    EmptyWindow,
+
+    #[error("{} is not a day of the week", _0)]
+    InvalidDay(String),
+
+    #[error("{} is not a time of day, such as 02:00", _0)]
+    InvalidTime(String),
+
+    #[error("{} is out of range", _0)]
+    OutOfRange(&'static str),
+
+    #[error("failed to read schedule from {}", SCHEDULE)]
+    Read(#[source] io::Error),
+
+    #[error("failed to parse schedule")]
+    Parse(#[source] serde_json::Error),
+
+    #[error("failed to serialize schedule")]
+    Serialize(#[source] serde_json::Error),
+
+    #[error("failed to write schedule to {}", SCHEDULE)]
+    Write(#[source] io::Error),
+}
+
+/// When updates are installed.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, FromPrimitive, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum When {
+    #[default]
+    Never = 0,
+    Window = 1,
+    Shutdown = 2,
+}
+
+/// The schedule of package updates, whose days and times only apply to windows.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Schedule {
+    pub when:  When,
+    /// Days of the week, where the lowest bit is Monday.
+    pub days:  u8,
+    /// Minutes since midnight that the window starts at.
+    pub start: u16,
+    /// Minutes since midnight that the window ends at.
+    pub end:   u16,
+}
+
+impl Schedule {
+    pub fn window(days: u8, start: u16, end: u16) -> Result<Self, ScheduleError> {
+        let schedule = Self { when: When::Window, days, start, end };
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn on_shutdown() -> Self { Self { when: When::Shutdown, ..Self::default() } }
+
+    pub fn from_dbus(
+        (when, days, start, end): (u8, u8, u16, u16),
+    ) -> Result<Self, ScheduleError> {
+        let when = When::from_u8(when).ok_or(ScheduleError::OutOfRange("when"))?;
+        let schedule = Self { when, days, start, end };
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn to_dbus(self) -> (u8, u8, u16, u16) {
+        (self.when as u8, self.days, self.start, self.end)
+    }
+
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        if self.when != When::Window {
+            return Ok(());
+        }
+
+        if self.days & 0x7F == 0 || self.days & !0x7F != 0 {
+            return Err(ScheduleError::NoDays);
+        }
+
+        if self.start >= MINUTES_PER_DAY {
+            return Err(ScheduleError::OutOfRange("start"));
+        }
+
+        if self.end >= MINUTES_PER_DAY {
+            return Err(ScheduleError::OutOfRange("end"));
+        }
+
+        if self.start == self.end {
+            return Err(ScheduleError::EmptyWindow);
+        }
+
+        Ok(())
+    }
+
+    /// The schedule set by the administrator, if any.
+    pub fn load() -> Result<Self, ScheduleError> {
+        let schedule = match fs::read_to_string(SCHEDULE) {
+            Ok(schedule) => schedule,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(why) => return Err(ScheduleError::Read(why)),
+        };
+
+        let schedule: Self = serde_json::from_str(&schedule).map_err(ScheduleError::Parse)?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn save(&self) -> Result<(), ScheduleError> {
+        let schedule = serde_json::to_string(self).map_err(ScheduleError::Serialize)?;
+
+        fs::create_dir_all(VAR_LIB_DIR)
+            .and_then(|_| fs::write(SCHEDULE, schedule))
+            .map_err(ScheduleError::Write)
+    }
+
+    /// The date that the window containing `now` began on, if `now` is within a window.
+    pub fn window_at(&self, now: NaiveDateTime) -> Option<NaiveDate> {
+        if self.when != When::Window {
+            return None;
+        }
+
+        let today = now.date();
+        let minute = (now.hour() * 60 + now.minute()) as u16;
+        let scheduled = |date: NaiveDate| self.days & day_bit(date) != 0;
+
+        if self.start < self.end {
+            (scheduled(today) && (self.start..self.end).contains(&minute)).then_some(today)
+        } else if minute >= self.start {
+            scheduled(today).then_some(today)
+        } else {
+            let yesterday = today.pred_opt()?;
+            (minute < self.end && scheduled(yesterday)).then_some(yesterday)
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.when {
+            When::Never => f.write_str("never"),
+            When::Shutdown => f.write_str("on shutdown"),
+            When::Window => {
+                let days = DAYS
+                    .iter()
+                    .enumerate()
+                    .filter(|(day, _)| self.days & (1 << day) != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                write!(f, "{} from {} to {}", days, Time(self.start), Time(self.end))
+            }
+        }
+    }
+}
+
+struct Time(u16);
+
+impl Display for Time {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// Parses a comma-separated list of days, such as `sat,sun`, into the days of a schedule.
+pub fn parse_days(input: &str) -> Result<u8, ScheduleError> {
+    input.split(',').try_fold(0, |days, day| {
+        let day = day.trim().to_ascii_lowercase();
+        DAYS.iter()
+            .position(|name| day.starts_with(name))
+            .map(|position| days | 1 << position)
+            .ok_or(ScheduleError::InvalidDay(day))
+    })
+}
+
+/// Parses a time of day, such as `02:00`, into minutes since midnight.
+pub fn parse_time(input: &str) -> Result<u16, ScheduleError> {
+    let invalid = || ScheduleError::InvalidTime(input.to_owned());
+    let (hour, minute) = input.trim().split_once(':').ok_or_else(invalid)?;
+    let hour = hour.parse::<u16>().ok().filter(|&hour| hour < 24).ok_or_else(invalid)?;
+    let minute = minute.parse::<u16>().ok().filter(|&minute| minute < 60).ok_or_else(invalid)?;
+
+    Ok(hour * 60 + minute)
+}
+
+/// Whether the system is ready for scheduled updates to be installed.
+pub fn ready(system: &dyn SystemStatus) -> Result<(), NotReady> {
+    if system.on_battery() {
+        Err(NotReady::OnBattery)
+    } else if system.idle_inhibited() {
+        Err(NotReady::InUse)
+    } else {
+        Ok(())
+    }
+}
+
+/// Delays shutdowns until the inhibitor is dropped, so that updates may be staged first.
+pub fn inhibit_shutdown() -> Result<OwnedFd, dbus::Error> {
+    inhibit("shutdown", "staging scheduled updates", "delay")
+}
+
+/// Prevents shutting down and suspending until the inhibitor is dropped, so that updates which
+/// are being installed are not interrupted.
+pub fn block_shutdown() -> Result<OwnedFd, dbus::Error> {
+    inhibit("shutdown:sleep", "installing scheduled updates", "block")
+}
+
+fn inhibit(what: &str, why: &str, mode: &str) -> Result<OwnedFd, dbus::Error> {
+    Connection::new_system()?
+        .with_proxy(LOGIND_NAME, LOGIND_PATH, Duration::from_millis(1000))
+        .method_call(LOGIND_IFACE, "Inhibit", (what, "pop-upgrade", why, mode))
+        .map(|(fd,): (OwnedFd,)| fd)
+}
+
+fn day_bit(date: NaiveDate) -> u8 { 1 << date.weekday().num_days_from_monday() }
+
+/// What became of an attempt to install updates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attempt {
+    /// The system was not ready, or updates could not be checked for.
+    Postponed,
+    /// There were no updates to install.
+    UpToDate,
+    /// Updates are being installed, and the result is yet to be known.
+    Installing,
+}
+
+/// Tracks the windows that updates were installed within.
+pub struct Scheduler {
+    pub schedule: Schedule,
+    installed:    Option<NaiveDate>,
+    installing:   Option<NaiveDate>,
+    next_check:   Instant,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule) -> Self {
+        Self { schedule, installed: None, installing: None, next_check: Instant::now() }
+    }
+
+    /// The window that updates should now be installed within, if any.
+    pub fn due(&self, now: NaiveDateTime, instant: Instant) -> Option<NaiveDate> {
+        if instant < self.next_check {
+            return None;
+        }
+
+        self.schedule
+            .window_at(now)
+            .filter(|&window| self.installed != Some(window) && self.installing != Some(window))
+    }
+
+    /// Marks the window as handled if it is up to date, or checks again shortly if postponed.
+    pub fn checked(&mut self, window: NaiveDate, attempt: Attempt, instant: Instant) {
+        match attempt {
+            Attempt::Postponed => (),
+            Attempt::UpToDate => self.installed = Some(window),
+            Attempt::Installing => self.installing = Some(window),
+        }
+
+        self.next_check = instant + RECHECK;
+    }
+
+    /// Marks the window that updates were being installed within as handled if they were
+    /// installed, or to be checked again if they failed to install.
+    pub fn finished(&mut self, installed: bool) {
+        if let Some(window) = self.installing.take() {
+            if installed {
+                self.installed = Some(window);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&[date, " ", time].concat(), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parse_schedule() {
+        assert_eq!(parse_days("sat,Sunday").unwrap(), 0b110_0000);
+        assert!(matches!(parse_days("someday"), Err(ScheduleError::InvalidDay(_))));
+        assert_eq!(parse_time("02:30").unwrap(), 150);
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("2").is_err());
+
+        assert!(matches!(Schedule::window(0, 120, 240), Err(ScheduleError::NoDays)));
+        assert!(matches!(Schedule::window(1, 120, 120), Err(ScheduleError::EmptyWindow)));
+        assert!(Schedule::from_dbus((3, 0, 0, 0)).is_err());
+
+        let schedule = Schedule::window(0b100_0001, 120, 240).unwrap();
+        assert_eq!(schedule.to_string(), "mon,sun from 02:00 to 04:00");
+        assert_eq!(Schedule::from_dbus(schedule.to_dbus()).unwrap(), schedule);
+    }
+
+    #[test]
+    fn windows() {
+        // 2026-10-18 is a Sunday.
+        let sunday = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let schedule = Schedule::window(parse_days("sun").unwrap(), 120, 240).unwrap();
+
+        assert_eq!(schedule.window_at(at("2026-10-18", "02:00")), Some(sunday));
+        assert_eq!(schedule.window_at(at("2026-10-18", "03:59")), Some(sunday));
+        assert_eq!(schedule.window_at(at("2026-10-18", "04:00")), None);
+        assert_eq!(schedule.window_at(at("2026-10-19", "02:30")), None);
+
+        let overnight = Schedule::window(parse_days("sun").unwrap(), 23 * 60, 60).unwrap();
+        assert_eq!(overnight.window_at(at("2026-10-18", "23:30")), Some(sunday));
+        assert_eq!(overnight.window_at(at("2026-10-19", "00:30")), Some(sunday));
+        assert_eq!(overnight.window_at(at("2026-10-19", "01:00")), None);
+        assert_eq!(overnight.window_at(at("2026-10-18", "00:30")), None);
+
+        assert_eq!(Schedule::on_shutdown().window_at(at("2026-10-18", "02:00")), None);
+    }
+
+    #[test]
+    fn once_per_window() {
+        let sunday = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let mut scheduler = Scheduler::new(Schedule::window(0b100_0000, 120, 240).unwrap());
+        let instant = Instant::now();
+
+        let now = at("2026-10-18", "02:00");
+        assert_eq!(scheduler.due(now, instant), Some(sunday));
+
+        scheduler.checked(sunday, Attempt::Postponed, instant);
+        assert_eq!(scheduler.due(now, instant), None);
+        assert_eq!(scheduler.due(now, instant + RECHECK), Some(sunday));
+
+        scheduler.checked(sunday, Attempt::Installing, instant);
+        assert_eq!(scheduler.due(now, instant + RECHECK), None);
+        scheduler.finished(false);
+        assert_eq!(scheduler.due(now, instant + RECHECK), Some(sunday));
+
+        scheduler.checked(sunday, Attempt::Installing, instant);
+        scheduler.finished(true);
+        assert_eq!(scheduler.due(at("2026-10-18", "03:00"), instant + RECHECK), None);
+        let next_sunday = sunday + chrono::Days::new(7);
+        assert_eq!(scheduler.due(at("2026-10-25", "02:00"), instant + RECHECK), Some(next_sunday));
+    }
+}
@@ -0,0 +1,275 @@
+//! The last package update transaction of the daemon, recorded so that it may be undone.
+//!
+//! The versions of the packages are recorded before they are updated, and once the update is
+//! complete, are replaced by the changes that apt reports in `/var/log/apt/history.log`. An
+//! update is undone by installing the previous versions again, from the apt cache if they are
+//! still there, or else from the archive. Packages which were newly installed are kept, and
+//! may be removed with `apt autoremove` once the packages which depended on them are undone,
+//! and packages which were removed are installed again.
+//!
+//! Only an update that succeeds replaces the last transaction, so that a failed update leaves
+//! the last good one to undo. Updates which are installed at startup are staged with the
+//! offline update, and recorded once the daemon learns that they were installed.
+
+use crate::{
+    history::{self, Action},
+    updates::Upgrade,
+    VAR_LIB_DIR,
+};
+use chrono::Local;
+use const_format::concatcp;
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+use thiserror::Error;
+
+pub const TRANSACTION: &str = concatcp!(VAR_LIB_DIR, "/transaction.json");
+
+/// The transaction of updates which are staged to be installed at the next startup.
+const STAGED: &str = concatcp!(VAR_LIB_DIR, "/transaction-staged.json");
+
+const APT_HISTORY: &str = "/var/log/apt/history.log";
+
+const ARCHIVES: &str = "/var/cache/apt/archives";
+
+#[derive(Debug, Error)]
+pub enum TransactionError {
+    #[error("there is no package update to undo")]
+    NotFound,
+
+    #[error("failed to read the transaction from {}", _0)]
+    Read(&'static str, #[source] io::Error),
+
+    #[error("failed to parse the last transaction")]
+    Parse(#[source] serde_json::Error),
+
+    #[error("failed to serialize the transaction")]
+    Serialize(#[source] serde_json::Error),
+
+    #[error("failed to write the transaction to {}", _0)]
+    Write(&'static str, #[source] io::Error),
+}
+
+/// A change to the version of a package.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    /// The name of the package, qualified by its architecture.
+    pub package: String,
+    /// The version before the change, if the package was installed.
+    pub from:    Option<String>,
+    /// The version after the change, if the package was not removed.
+    pub to:      Option<String>,
+}
+
+impl Change {
+    /// The argument of `apt-get install` that restores the previous version, preferring the
+    /// package in the apt cache.
+    fn undo_arg(&self) -> Option<String> {
+        let from = self.from.as_ref()?;
+        let (name, arch) = self.package.split_once(':').unwrap_or((&self.package, ""));
+
+        let deb = format!("{}/{}_{}_{}.deb", ARCHIVES, name, from.replace(':', "%3a"), arch);
+
+        if Path::new(&deb).exists() {
+            Some(deb)
+        } else {
+            Some([&self.package, "=", from].concat())
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Transaction {
+    /// When the update began, in seconds since the Unix epoch.
+    pub started: i64,
+    pub changes: Vec<Change>,
+}
+
+impl Transaction {
+    /// Records the installed versions of the packages which are about to be updated.
+    pub fn begin(upgrades: &[Upgrade]) -> Self {
+        let changes = upgrades
+            .iter()
+            .map(|upgrade| Change {
+                package: [&upgrade.package, ":", &upgrade.arch].concat(),
+                from:    upgrade.installed.clone(),
+                to:      Some(upgrade.version.clone()),
+            })
+            .collect();
+
+        Self { started: Local::now().timestamp(), changes }
+    }
+
+    /// Replaces the expected changes with those that apt reports that it made, if any.
+    pub fn complete(&mut self) {
+        match fs::read_to_string(APT_HISTORY) {
+            Ok(history) => {
+                let changes = history_since(&history, self.started);
+
+                if !changes.is_empty() {
+                    self.changes = changes;
+                }
+            }
+            Err(why) => warn!("failed to read {}: {}", APT_HISTORY, why),
+        }
+    }
+
+    /// Completes the transaction of an update which succeeded, and records it as the last
+    /// transaction, unless no packages were changed.
+    pub fn commit(mut self) -> Result<(), TransactionError> {
+        self.complete();
+
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        self.write(TRANSACTION)
+    }
+
+    pub fn load() -> Result<Self, TransactionError> {
+        Self::read(TRANSACTION)
+    }
+
+    /// Records the transaction of updates which will be installed at the next startup.
+    pub fn stage(&self) -> Result<(), TransactionError> {
+        self.write(STAGED)
+    }
+
+    /// Takes the transaction of the updates which were staged to be installed at startup.
+    pub fn take_staged() -> Result<Self, TransactionError> {
+        let transaction = Self::read(STAGED);
+        let _ = fs::remove_file(STAGED);
+        transaction
+    }
+
+    fn read(path: &'static str) -> Result<Self, TransactionError> {
+        let transaction = fs::read_to_string(path).map_err(|why| {
+            if why.kind() == io::ErrorKind::NotFound {
+                TransactionError::NotFound
+            } else {
+                TransactionError::Read(path, why)
+            }
+        })?;
+
+        serde_json::from_str(&transaction).map_err(TransactionError::Parse)
+    }
+
+    fn write(&self, path: &'static str) -> Result<(), TransactionError> {
+        let transaction = serde_json::to_string(self).map_err(TransactionError::Serialize)?;
+
+        fs::create_dir_all(VAR_LIB_DIR)
+            .and_then(|_| fs::write(path, transaction))
+            .map_err(|why| TransactionError::Write(path, why))
+    }
+
+    /// Forgets the transaction, once it has been undone.
+    pub fn remove() {
+        if let Err(why) = fs::remove_file(TRANSACTION) {
+            if why.kind() != io::ErrorKind::NotFound {
+                warn!("failed to remove {}: {}", TRANSACTION, why);
+            }
+        }
+    }
+
+    /// The arguments of `apt-get install` that restore the previous versions.
+    pub fn undo_args(&self) -> Vec<String> {
+        self.changes.iter().filter_map(Change::undo_arg).collect()
+    }
+
+    /// Convert the changes into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> Vec<(String, String, String)> {
+        self.changes
+            .into_iter()
+            .map(|change| {
+                (change.package, change.from.unwrap_or_default(), change.to.unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// Reconstruct the changes that were transferred over DBus.
+    pub fn from_dbus(changes: Vec<(String, String, String)>) -> Self {
+        let changes = changes
+            .into_iter()
+            .map(|(package, from, to)| Change {
+                package,
+                from: if from.is_empty() { None } else { Some(from) },
+                to: if to.is_empty() { None } else { Some(to) },
+            })
+            .collect();
+
+        Self { started: 0, changes }
+    }
+}
+
+/// The changes of every entry of apt's history which began at or after `since`.
+fn history_since(history: &str, since: i64) -> Vec<Change> {
+    history::parse_apt_history(history)
+        .into_iter()
+        .filter(|entry| entry.time >= since)
+        .flat_map(|entry| entry.changes)
+        .filter(|change| change.action != Action::Reinstall)
+        .map(|change| Change { package: change.package, from: change.from, to: change.to })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDateTime, TimeZone};
+
+    #[test]
+    fn changes_from_history() {
+        let history = "
+Start-Date: 2023-10-20  10:00:00
+Commandline: apt-get install htop
+Install: htop:amd64 (3.0.5-7build2)
+End-Date: 2023-10-20  10:00:02
+
+Start-Date: 2023-10-24  09:12:01
+Commandline: apt-get --show-progress full-upgrade
+Install: linux-image-6.5.0-10-generic:amd64 (6.5.0-10.10, automatic)
+Upgrade: libssl3:amd64 (3.0.2-0ubuntu1.10, 3.0.2-0ubuntu1.12), mesa-va-drivers:amd64 \
+(23.0.4-0ubuntu1~22.04.1, 23.2.1-1ubuntu3~22.04.1)
+Purge: gnome-shell-extension-ubuntu-dock:amd64 (72~ubuntu5.22.04.2)
+End-Date: 2023-10-24  09:13:40
+";
+
+        let since = Local
+            .from_local_datetime(
+                &NaiveDateTime::parse_from_str("2023-10-24 09:00:00", "%Y-%m-%d %H:%M:%S")
+                    .unwrap(),
+            )
+            .unwrap()
+            .timestamp();
+
+        let transaction = Transaction { started: since, changes: history_since(history, since) };
+
+        assert_eq!(transaction.changes, [
+            Change {
+                package: "linux-image-6.5.0-10-generic:amd64".into(),
+                from:    None,
+                to:      Some("6.5.0-10.10".into()),
+            },
+            Change {
+                package: "libssl3:amd64".into(),
+                from:    Some("3.0.2-0ubuntu1.10".into()),
+                to:      Some("3.0.2-0ubuntu1.12".into()),
+            },
+            Change {
+                package: "mesa-va-drivers:amd64".into(),
+                from:    Some("23.0.4-0ubuntu1~22.04.1".into()),
+                to:      Some("23.2.1-1ubuntu3~22.04.1".into()),
+            },
+            Change {
+                package: "gnome-shell-extension-ubuntu-dock:amd64".into(),
+                from:    Some("72~ubuntu5.22.04.2".into()),
+                to:      None,
+            },
+        ]);
+
+        assert_eq!(transaction.undo_args(), [
+            "libssl3:amd64=3.0.2-0ubuntu1.10",
+            "mesa-va-drivers:amd64=23.0.4-0ubuntu1~22.04.1",
+            "gnome-shell-extension-ubuntu-dock:amd64=72~ubuntu5.22.04.2"
+        ]);
+    }
+}
@@ -0,0 +1,614 @@
+//! Which package updates are fetched and installed.
+//!
+//! The selection may be configured by an administrator in `/etc/pop-upgrade/updates.json`:
+//!
+//! ```json
+//! {
+//!     "security_origins": ["LP-PPA-system76-pop", "jammy-updates"],
+//!     "exclude": ["nvidia-*", "linux-*-6.2.*"],
+//!     "phased_updates": "follow",
+//!     "stall_timeout": 900
+//! }
+//! ```
+//!
+//! - `security_origins` are sources whose updates are installed by security-only updates, in
+//!   addition to the `-security` pockets. Each matches the origin, the archive, or the whole
+//!   `origin:release/archive` of a source, as apt reports them.
+//! - `exclude` are globs of packages which are never updated by the daemon, without having to
+//!   hold them with apt. A package may still be updated if an update of another package
+//!   depends on it. While any package is excluded, updates are installed with
+//!   `apt-get install --only-upgrade` instead of `apt-get full-upgrade`, so updates which
+//!   require new packages to be installed, or obsolete packages to be removed, are held back.
+//! - `phased_updates` decides if updates which Ubuntu is phasing in are installed: `follow`
+//!   installs them once the system is part of the phase, as apt does by default, `always`
+//!   installs them immediately, and `never` waits until they are no longer phased.
+//! - `stall_timeout` is how many seconds apt and dpkg may go without making progress before the
+//!   operation is reported as stalled, or `0` to never report it. It is read when the daemon
+//!   starts.
+
+use anyhow::Context;
+use apt_cmd::{lock::apt_lock_wait, AptGet};
+use futures::StreamExt;
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs, io,
+};
+use tokio::process::Command;
+
+const UPDATES_CONFIG: &str = "/etc/pop-upgrade/updates.json";
+
+const ARCHIVES: &str = "/var/cache/apt/archives";
+
+/// The most lines of a changelog that are shown for an update.
+const CHANGELOG_LINES: usize = 40;
+
+/// How many seconds apt and dpkg may go without making progress, by default.
+const STALL_TIMEOUT: u64 = 900;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct UpdatesConfig {
+    pub security_origins: Vec<String>,
+    pub exclude:          Vec<String>,
+    pub phased_updates:   PhasedUpdates,
+    pub stall_timeout:    u64,
+}
+
+impl Default for UpdatesConfig {
+    fn default() -> Self {
+        Self {
+            security_origins: Vec::new(),
+            exclude:          Vec::new(),
+            phased_updates:   PhasedUpdates::default(),
+            stall_timeout:    STALL_TIMEOUT,
+        }
+    }
+}
+
+impl UpdatesConfig {
+    /// Loads the configuration of the administrator, or the defaults if there is none.
+    pub fn load() -> Self {
+        let config = match fs::read_to_string(UPDATES_CONFIG) {
+            Ok(config) => config,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(why) => {
+                warn!("failed to read {}: {}", UPDATES_CONFIG, why);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&config).unwrap_or_else(|why| {
+            warn!("failed to parse {}: {}", UPDATES_CONFIG, why);
+            Self::default()
+        })
+    }
+}
+
+/// Whether updates that are phased in, by their `Phased-Update-Percentage`, are installed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PhasedUpdates {
+    #[default]
+    Follow,
+    Always,
+    Never,
+}
+
+impl PhasedUpdates {
+    /// The options that apply the policy to apt.
+    pub fn apt_options(self) -> &'static [&'static str] {
+        match self {
+            PhasedUpdates::Follow => &[],
+            PhasedUpdates::Always => &["-o", "APT::Get::Always-Include-Phased-Updates=true"],
+            PhasedUpdates::Never => &["-o", "APT::Get::Never-Include-Phased-Updates=true"],
+        }
+    }
+
+    pub fn apply(self, mut apt_get: AptGet) -> AptGet {
+        apt_get.args(self.apt_options());
+        apt_get
+    }
+}
+
+impl Display for PhasedUpdates {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            PhasedUpdates::Follow => "follow",
+            PhasedUpdates::Always => "always",
+            PhasedUpdates::Never => "never",
+        })
+    }
+}
+
+/// Which of the available package updates to fetch and install.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    /// Only updates which come from security sources.
+    pub security_only: bool,
+    /// If not empty, only packages which match one of these globs.
+    pub include:       Vec<String>,
+    /// Packages which match one of these globs are not updated.
+    pub exclude:       Vec<String>,
+}
+
+impl Selection {
+    /// Adds the packages that the administrator always excludes from updates.
+    pub fn with_config(mut self, config: &UpdatesConfig) -> Self {
+        self.exclude.extend(config.exclude.iter().cloned());
+        self
+    }
+
+    /// Whether every available update is selected.
+    pub fn is_everything(&self) -> bool {
+        !self.security_only && self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn selects(&self, upgrade: &Upgrade, security_origins: &[String]) -> bool {
+        let matches = |globs: &[String]| globs.iter().any(|g| glob_match(g, &upgrade.package));
+
+        (!self.security_only || upgrade.is_security(security_origins))
+            && (self.include.is_empty() || matches(&self.include))
+            && !matches(&self.exclude)
+    }
+}
+
+/// Matches a package name against a glob, in which `*` matches any text, and `?` any character.
+pub fn glob_match(glob: &str, package: &str) -> bool {
+    let pattern = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+
+    regex::Regex::new(&["^", &pattern, "$"].concat())
+        .is_ok_and(|pattern| pattern.is_match(package))
+}
+
+/// A package that an upgrade would install, and the sources of the version it installs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upgrade {
+    pub package:   String,
+    /// The version that is installed, if the package is not newly installed.
+    pub installed: Option<String>,
+    pub version:   String,
+    pub arch:      String,
+    pub sources:   Vec<String>,
+}
+
+impl Upgrade {
+    /// Parses an `Inst` line of a simulated `apt-get full-upgrade`, such as:
+    ///
+    /// `Inst libssl3 [3.0.2-0ubuntu1.10] (3.0.2-0ubuntu1.12 Ubuntu:22.04/jammy-security [amd64])`
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.strip_prefix("Inst ")?;
+        let (package, rest) = line.split_once(' ')?;
+
+        let installed = rest
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .map(|(installed, _)| installed.to_owned());
+
+        let candidate = &line[line.find('(')? + 1..line.rfind(')')?];
+        let (version, sources) = candidate.split_once(' ').unwrap_or((candidate, ""));
+        let (sources, arch) = sources
+            .rsplit_once(" [")
+            .map_or((sources, ""), |(sources, arch)| (sources, arch.trim_end_matches(']')));
+
+        Some(Self {
+            package: package.to_owned(),
+            installed,
+            version: version.to_owned(),
+            arch: arch.to_owned(),
+            sources: sources.split(", ").filter(|s| !s.is_empty()).map(String::from).collect(),
+        })
+    }
+
+    /// Whether the candidate version, which the update installs, comes from a security pocket,
+    /// or from one of the additional origins of security updates.
+    pub fn is_security(&self, origins: &[String]) -> bool {
+        self.sources.iter().any(|source| {
+            let origin = source.split(':').next().unwrap_or(source);
+            let archive = source.rsplit('/').next().unwrap_or(source);
+
+            archive.ends_with("-security")
+                || origins.iter().any(|o| o == source || o == origin || o == archive)
+        })
+    }
+}
+
+/// The result of a simulated full upgrade.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Upgrades {
+    /// Every package that the upgrade would install, which may already be fetched.
+    pub packages: Vec<Upgrade>,
+    /// Packages with updates that are held back because they are being phased in.
+    pub deferred: Vec<String>,
+}
+
+impl Upgrades {
+    pub fn parse(output: &str) -> Self {
+        let mut upgrades = Self::default();
+        let mut lines = output.lines().peekable();
+
+        while let Some(line) = lines.next() {
+            if line.starts_with("The following upgrades have been deferred due to phasing") {
+                while let Some(packages) = lines.next_if(|line| line.starts_with(' ')) {
+                    upgrades.deferred.extend(packages.split_whitespace().map(String::from));
+                }
+            } else if let Some(upgrade) = Upgrade::parse(line) {
+                upgrades.packages.push(upgrade);
+            }
+        }
+
+        upgrades
+    }
+
+    /// The packages which have updates available that are selected.
+    pub fn selected(&self, selection: &Selection, config: &UpdatesConfig) -> Vec<String> {
+        self.packages
+            .iter()
+            .filter(|upgrade| selection.selects(upgrade, &config.security_origins))
+            .map(|upgrade| upgrade.package.clone())
+            .collect()
+    }
+}
+
+/// Simulates a full upgrade, following the policy for phased updates.
+pub async fn upgrades(phased: PhasedUpdates) -> anyhow::Result<Upgrades> {
+    apt_lock_wait().await;
+
+    let mut apt_get = phased.apply(AptGet::new().noninteractive().simulate());
+    apt_get.arg("full-upgrade");
+
+    let output = apt_get.output().await.context("failed to simulate `apt-get full-upgrade`")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "simulated full upgrade failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(Upgrades::parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The packages to upgrade in place of a full upgrade, which are every package with an update
+/// except those that the administrator excludes, or `None` if no packages are excluded.
+pub async fn excluding_config(phased: PhasedUpdates) -> anyhow::Result<Option<Vec<String>>> {
+    let config = UpdatesConfig::load();
+
+    if config.exclude.is_empty() {
+        return Ok(None);
+    }
+
+    let selection = Selection::default().with_config(&config);
+    let packages = upgrades(phased).await?.selected(&selection, &config);
+
+    info!("upgrading only {:?}, which excludes {:?}", packages, config.exclude);
+
+    Ok(Some(packages))
+}
+
+/// An update that is pending, as it is shown before it is installed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PendingUpdate {
+    pub package:   String,
+    /// The version that is installed, which is empty if the package is newly installed.
+    pub installed: String,
+    pub candidate: String,
+    pub origin:    String,
+    /// The size of the package to fetch, in bytes.
+    pub size:      u64,
+    /// The entries of the changelog since the installed version.
+    pub changelog: String,
+}
+
+impl PendingUpdate {
+    /// Convert the update into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> (String, String, String, String, u64, String) {
+        (self.package, self.installed, self.candidate, self.origin, self.size, self.changelog)
+    }
+
+    /// Reconstruct an update that was transferred over DBus.
+    pub fn from_dbus(
+        (package, installed, candidate, origin, size, changelog): (
+            String,
+            String,
+            String,
+            String,
+            u64,
+            String,
+        ),
+    ) -> Self {
+        Self { package, installed, candidate, origin, size, changelog }
+    }
+}
+
+/// The updates that a full upgrade would install, with their changelogs.
+pub async fn pending(phased: PhasedUpdates) -> anyhow::Result<Vec<PendingUpdate>> {
+    let upgrades = upgrades(phased).await?.packages;
+    let sizes = sizes(&upgrades).await;
+
+    let updates = futures::stream::iter(&upgrades)
+        .map(|upgrade| async {
+            PendingUpdate {
+                package:   upgrade.package.clone(),
+                installed: upgrade.installed.clone().unwrap_or_default(),
+                candidate: upgrade.version.clone(),
+                origin:    upgrade.sources.first().cloned().unwrap_or_default(),
+                size:      sizes
+                    .get(&(upgrade.package.as_str(), upgrade.version.as_str()))
+                    .copied()
+                    .unwrap_or(0),
+                changelog: changelog(upgrade).await,
+            }
+        })
+        .buffered(8)
+        .collect()
+        .await;
+
+    Ok(updates)
+}
+
+/// The sizes of the packages to fetch, by their package name and version.
+async fn sizes(upgrades: &[Upgrade]) -> HashMap<(&str, &str), u64> {
+    if upgrades.is_empty() {
+        return HashMap::new();
+    }
+
+    let output = Command::new("apt-cache")
+        .args(["show", "--no-all-versions"])
+        .args(upgrades.iter().map(|upgrade| [&upgrade.package, "=", &upgrade.version].concat()))
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(why) => {
+            warn!("failed to get the sizes of updates from apt-cache: {}", why);
+            return HashMap::new();
+        }
+    };
+
+    parse_sizes(&output)
+        .into_iter()
+        .filter_map(|(package, version, size)| {
+            upgrades
+                .iter()
+                .find(|upgrade| upgrade.package == package && upgrade.version == version)
+                .map(|upgrade| ((upgrade.package.as_str(), upgrade.version.as_str()), size))
+        })
+        .collect()
+}
+
+/// Parses the package, version, and size of each record from `apt-cache show`.
+fn parse_sizes(output: &str) -> Vec<(&str, &str, u64)> {
+    let mut sizes = Vec::new();
+    let (mut package, mut version) = ("", "");
+
+    for line in output.lines() {
+        if let Some(value) = line.strip_prefix("Package: ") {
+            package = value;
+        } else if let Some(value) = line.strip_prefix("Version: ") {
+            version = value;
+        } else if let Some(Ok(size)) = line.strip_prefix("Size: ").map(str::parse) {
+            sizes.push((package, version, size));
+        }
+    }
+
+    sizes
+}
+
+/// The changelog of an update since the installed version, which is read from the fetched
+/// package if it is cached, or else is fetched by apt.
+async fn changelog(upgrade: &Upgrade) -> String {
+    let changelog = match cached_changelog(upgrade).await {
+        Some(changelog) => changelog,
+        None => {
+            let package = [&upgrade.package, "=", &upgrade.version].concat();
+
+            match Command::new("apt-get").args(["changelog", &package]).output().await {
+                Ok(output) if output.status.success() => {
+                    String::from_utf8_lossy(&output.stdout).into_owned()
+                }
+                _ => return String::new(),
+            }
+        }
+    };
+
+    changelog_excerpt(&changelog, upgrade.installed.as_deref())
+}
+
+/// Reads the changelog from the package in apt's cache, if it was already fetched.
+async fn cached_changelog(upgrade: &Upgrade) -> Option<String> {
+    let deb = format!(
+        "{}/{}_{}_{}.deb",
+        ARCHIVES,
+        upgrade.package,
+        upgrade.version.replace(':', "%3a"),
+        upgrade.arch
+    );
+
+    if !std::path::Path::new(&deb).exists() {
+        return None;
+    }
+
+    let changelog = ["./usr/share/doc/", &upgrade.package, "/changelog.Debian.gz"].concat();
+
+    let output = Command::new("sh")
+        .args(["-c", "dpkg-deb --fsys-tarfile \"$0\" | tar -xOf - \"$1\" | zcat", &deb])
+        .arg(&changelog)
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The entries of a Debian changelog which are newer than the installed version, or the latest
+/// entry if the package is not installed.
+pub fn changelog_excerpt(changelog: &str, installed: Option<&str>) -> String {
+    let mut excerpt = Vec::new();
+    let mut entries = 0;
+
+    for line in changelog.lines() {
+        // Each entry begins with a line such as `openssl (3.0.2-0ubuntu1.12) jammy; urgency=medium`
+        let version = (!line.starts_with(char::is_whitespace))
+            .then(|| line.split_once(" (")?.1.split_once(')'))
+            .flatten()
+            .map(|(version, _)| version);
+
+        if let Some(version) = version {
+            entries += 1;
+
+            if installed.map_or(entries > 1, |installed| installed == version) {
+                break;
+            }
+        }
+
+        if excerpt.len() == CHANGELOG_LINES {
+            break;
+        }
+
+        excerpt.push(line);
+    }
+
+    excerpt.join("\n").trim_end().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_upgrades() {
+        let security = Upgrade::parse(
+            "Inst libssl3 [3.0.2-0ubuntu1.10] (3.0.2-0ubuntu1.12 Ubuntu:22.04/jammy-updates, \
+             Ubuntu:22.04/jammy-security [amd64])",
+        )
+        .unwrap();
+
+        assert_eq!(security.package, "libssl3");
+        assert_eq!(security.installed.as_deref(), Some("3.0.2-0ubuntu1.10"));
+        assert_eq!(security.version, "3.0.2-0ubuntu1.12");
+        assert_eq!(security.arch, "amd64");
+        assert_eq!(security.sources, ["Ubuntu:22.04/jammy-updates", "Ubuntu:22.04/jammy-security"]);
+        assert!(security.is_security(&[]));
+
+        let feature =
+            Upgrade::parse("Inst pop-shop (3.1.0 pop-os-release:22.04/jammy [amd64]) []").unwrap();
+
+        assert_eq!(feature.sources, ["pop-os-release:22.04/jammy"]);
+        assert_eq!(feature.installed, None);
+        assert!(!feature.is_security(&[]));
+        assert!(feature.is_security(&["pop-os-release".into()]));
+        assert!(!feature.is_security(&["jammy-updates".into()]));
+
+        assert_eq!(Upgrade::parse("Conf libssl3 (3.0.2-0ubuntu1.12 Ubuntu:22.04/jammy)"), None);
+    }
+
+    #[test]
+    fn selection() {
+        assert!(glob_match("nvidia-*", "nvidia-driver-535"));
+        assert!(glob_match("linux-image-6.?.*", "linux-image-6.2.0-76060200-generic"));
+        assert!(!glob_match("nvidia-*", "libnvidia-gl-535"));
+        assert!(!glob_match("lib?", "libc6"));
+        assert!(glob_match("g++", "g++"));
+
+        let upgrade = |package: &str, source: &str| Upgrade {
+            package:   package.into(),
+            installed: None,
+            version:   "1.0".into(),
+            arch:      "amd64".into(),
+            sources:   vec![source.into()],
+        };
+
+        let nvidia = upgrade("nvidia-driver-535", "pop-os-release:22.04/jammy");
+        let openssl = upgrade("openssl", "Ubuntu:22.04/jammy-security");
+
+        assert!(Selection::default().is_everything());
+
+        let config = UpdatesConfig { exclude: vec!["nvidia-*".into()], ..Default::default() };
+        let selection = Selection::default().with_config(&config);
+        assert!(!selection.is_everything());
+        assert!(!selection.selects(&nvidia, &[]));
+        assert!(selection.selects(&openssl, &[]));
+
+        let selection = Selection { include: vec!["nvidia-*".into()], ..Default::default() };
+        assert!(selection.selects(&nvidia, &[]));
+        assert!(!selection.selects(&openssl, &[]));
+
+        let selection = Selection { security_only: true, ..Default::default() };
+        assert!(!selection.selects(&nvidia, &[]));
+        assert!(selection.selects(&openssl, &[]));
+    }
+
+    #[test]
+    fn phased_updates() {
+        let output = "Reading package lists...
+Calculating upgrade...
+The following upgrades have been deferred due to phasing:
+  gnome-shell gnome-shell-common
+  mutter-common
+The following packages will be upgraded:
+  libssl3
+1 upgraded, 0 newly installed, 0 to remove and 3 not upgraded.
+Inst libssl3 [3.0.2-0ubuntu1.10] (3.0.2-0ubuntu1.12 Ubuntu:22.04/jammy-security [amd64])
+Conf libssl3 (3.0.2-0ubuntu1.12 Ubuntu:22.04/jammy-security [amd64])
+";
+
+        let upgrades = Upgrades::parse(output);
+        assert_eq!(upgrades.deferred, ["gnome-shell", "gnome-shell-common", "mutter-common"]);
+        assert_eq!(upgrades.packages.len(), 1);
+        assert_eq!(upgrades.packages[0].package, "libssl3");
+
+        let config: UpdatesConfig = serde_json::from_str(r#"{"phased_updates":"never"}"#).unwrap();
+        assert_eq!(config.phased_updates, PhasedUpdates::Never);
+        assert_eq!(UpdatesConfig::default().phased_updates.apt_options(), &[] as &[&str]);
+    }
+
+    #[test]
+    fn pending_updates() {
+        let show = "Package: libssl3
+Architecture: amd64
+Version: 3.0.2-0ubuntu1.12
+Size: 1903240
+
+Package: openssl
+Version: 3.0.2-0ubuntu1.12
+Size: 1184010
+";
+
+        assert_eq!(parse_sizes(show), [
+            ("libssl3", "3.0.2-0ubuntu1.12", 1903240),
+            ("openssl", "3.0.2-0ubuntu1.12", 1184010)
+        ]);
+
+        let changelog = "openssl (3.0.2-0ubuntu1.12) jammy-security; urgency=medium
+
+  * SECURITY UPDATE: denial of service
+
+ -- Security Team <security@ubuntu.com>  Tue, 24 Oct 2023 09:00:00 -0400
+
+openssl (3.0.2-0ubuntu1.11) jammy-security; urgency=medium
+
+  * SECURITY UPDATE: excessive time spent checking DH keys
+
+ -- Security Team <security@ubuntu.com>  Mon, 18 Sep 2023 09:00:00 -0400
+
+openssl (3.0.2-0ubuntu1.10) jammy-security; urgency=medium
+
+  * SECURITY UPDATE: AES-SIV implementation ignores empty associated data
+";
+
+        let excerpt = changelog_excerpt(changelog, Some("3.0.2-0ubuntu1.10"));
+        assert!(excerpt.starts_with("openssl (3.0.2-0ubuntu1.12)"));
+        assert!(excerpt.contains("excessive time spent checking DH keys"));
+        assert!(!excerpt.contains("AES-SIV"));
+
+        let excerpt = changelog_excerpt(changelog, None);
+        assert!(excerpt.ends_with("Tue, 24 Oct 2023 09:00:00 -0400"));
+    }
+}
@@ -0,0 +1,299 @@
+//! Detects apt and dpkg operations which have stalled.
+//!
+//! An operation has stalled when dpkg is running, but apt has not written any output and dpkg
+//! has not changed the status of a package for the `stall_timeout` of the configuration of
+//! updates. This is most often a maintainer script which is waiting on a prompt or on the
+//! network, and which would otherwise leave the daemon waiting on it forever. Only the processes
+//! which the daemon started itself are considered. The script may be killed, after which the
+//! packages that it interrupted are configured with `dpkg --configure -a`.
+
+use apt_cmd::{lock::apt_lock_wait, Dpkg};
+use std::{collections::HashMap, fs, io, process::Command, time::Duration, time::SystemTime};
+use thiserror::Error;
+
+const DPKG_STATUS: &str = "/var/lib/dpkg/status";
+
+const DPKG_UPDATES: &str = "/var/lib/dpkg/updates";
+
+const MAINTAINER_SCRIPTS: &str = "/var/lib/dpkg/info/";
+
+const SCRIPTS: &[&str] = &["preinst", "postinst", "prerm", "postrm"];
+
+/// How long a process is given to exit after each signal.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum WatchdogError {
+    #[error("no apt or dpkg operation has stalled")]
+    NotStalled,
+
+    #[error("process {} is no longer the process which stalled", _0)]
+    Replaced(u32),
+
+    #[error("failed to kill process {}", _0)]
+    Kill(u32, #[source] io::Error),
+
+    #[error("process {} did not exit after being killed", _0)]
+    Survived(u32),
+
+    #[error("failed to configure the interrupted packages")]
+    Configure(#[source] io::Error),
+}
+
+/// A process of dpkg which has stopped making progress.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stall {
+    /// The package whose maintainer script is running, which is empty if none is.
+    pub package: String,
+    /// The maintainer script, such as `postinst`.
+    pub script:  String,
+    /// The process of the maintainer script, or of dpkg if no script is running.
+    pub pid:     u32,
+    /// How many seconds have passed since apt or dpkg last made progress.
+    pub idle:    u64,
+}
+
+impl Stall {
+    /// Convert the stall into a form that can be transferred over DBus.
+    pub fn into_dbus(self) -> (String, String, u32, u64) {
+        (self.package, self.script, self.pid, self.idle)
+    }
+
+    /// Reconstruct a stall that was transferred over DBus.
+    pub fn from_dbus((package, script, pid, idle): (String, String, u32, u64)) -> Self {
+        Self { package, script, pid, idle }
+    }
+}
+
+/// The package and script of an argument that runs a maintainer script, such as
+/// `/var/lib/dpkg/info/openssh-server.postinst`.
+pub fn maintainer_script(arg: &str) -> Option<(&str, &str)> {
+    let (package, script) = arg.strip_prefix(MAINTAINER_SCRIPTS)?.rsplit_once('.')?;
+
+    if package.is_empty() || !SCRIPTS.contains(&script) {
+        return None;
+    }
+
+    Some((package, script))
+}
+
+/// When dpkg last changed the status of a package.
+pub fn dpkg_activity() -> Option<SystemTime> {
+    [DPKG_STATUS, DPKG_UPDATES]
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .max()
+}
+
+/// A running process, with what is needed to tell whether it has stalled.
+#[derive(Clone, Debug, Default)]
+struct ProcessInfo {
+    pid:     u32,
+    ppid:    u32,
+    comm:    String,
+    cmdline: Vec<String>,
+}
+
+fn processes() -> Vec<ProcessInfo> {
+    let processes = match procfs::process::all_processes() {
+        Ok(processes) => processes,
+        Err(why) => {
+            warn!("failed to fetch running processes: {}", why);
+            return Vec::new();
+        }
+    };
+
+    processes
+        .filter_map(Result::ok)
+        .filter_map(|process| {
+            let stat = process.stat().ok()?;
+            Some(ProcessInfo {
+                pid:     process.pid() as u32,
+                ppid:    stat.ppid as u32,
+                comm:    stat.comm,
+                cmdline: process.cmdline().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Finds the process of dpkg which the daemon is running, and of the maintainer script that it
+/// is running, if it is.
+pub fn find_stall(idle: u64) -> Option<Stall> {
+    find_in_tree(&processes(), std::process::id(), idle)
+}
+
+/// Walks down the tree of processes from `root` to the first dpkg, and the maintainer script
+/// beneath it which is deepest, as a script which is run by another script is the one that the
+/// other is waiting on.
+fn find_in_tree(processes: &[ProcessInfo], root: u32, idle: u64) -> Option<Stall> {
+    let mut children = HashMap::<u32, Vec<&ProcessInfo>>::new();
+    for process in processes {
+        children.entry(process.ppid).or_default().push(process);
+    }
+
+    let below = |level: &[&ProcessInfo]| -> Vec<&ProcessInfo> {
+        level.iter().filter_map(|process| children.get(&process.pid)).flatten().copied().collect()
+    };
+
+    let mut level = below(&[&ProcessInfo { pid: root, ..ProcessInfo::default() }]);
+
+    let dpkg = loop {
+        if level.is_empty() {
+            return None;
+        }
+
+        if let Some(dpkg) = level.iter().find(|process| process.comm == "dpkg") {
+            break *dpkg;
+        }
+
+        level = below(&level);
+    };
+
+    let mut stall = Stall { pid: dpkg.pid, idle, ..Stall::default() };
+    let mut level = below(&[dpkg]);
+
+    while !level.is_empty() {
+        for process in &level {
+            if let Some((package, script)) =
+                process.cmdline.iter().find_map(|arg| maintainer_script(arg))
+            {
+                let (package, script) = (package.to_owned(), script.to_owned());
+                stall = Stall { package, script, pid: process.pid, idle };
+            }
+        }
+
+        level = below(&level);
+    }
+
+    Some(stall)
+}
+
+/// Whether the process is still the one which stalled, and was started by the daemon.
+fn is_stalled(stall: &Stall) -> bool {
+    let processes = processes();
+    let Some(process) = processes.iter().find(|process| process.pid == stall.pid) else {
+        return false;
+    };
+
+    let matches = if stall.package.is_empty() {
+        process.comm == "dpkg"
+    } else {
+        process.cmdline.iter().find_map(|arg| maintainer_script(arg))
+            == Some((stall.package.as_str(), stall.script.as_str()))
+    };
+
+    let daemon = std::process::id();
+    let mut ancestor = process.ppid;
+    let mut descends = false;
+
+    for _ in 0..processes.len() {
+        if ancestor == daemon {
+            descends = true;
+            break;
+        }
+
+        match processes.iter().find(|process| process.pid == ancestor) {
+            Some(process) => ancestor = process.ppid,
+            None => break,
+        }
+    }
+
+    matches && descends
+}
+
+/// Kills the stalled process, after verifying that it is still the process which stalled.
+pub async fn recover(stall: &Stall) -> Result<(), WatchdogError> {
+    for signal in [libc::SIGTERM, libc::SIGKILL] {
+        if !is_stalled(stall) {
+            if signal == libc::SIGTERM {
+                return Err(WatchdogError::Replaced(stall.pid));
+            }
+
+            return Ok(());
+        }
+
+        info!("sending signal {} to process {}", signal, stall.pid);
+
+        if unsafe { libc::kill(stall.pid as libc::pid_t, signal) } == -1 {
+            return Err(WatchdogError::Kill(stall.pid, io::Error::last_os_error()));
+        }
+
+        tokio::time::sleep(KILL_TIMEOUT).await;
+    }
+
+    if is_stalled(stall) {
+        return Err(WatchdogError::Survived(stall.pid));
+    }
+
+    Ok(())
+}
+
+/// Configures the packages which were interrupted by a process that was killed, if any were.
+///
+/// This must only be called while the daemon is not otherwise using apt or dpkg.
+pub async fn configure_interrupted() -> Result<(), WatchdogError> {
+    let audit = Command::new("dpkg").arg("--audit").output().map_err(WatchdogError::Configure)?;
+
+    if audit.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+
+    apt_lock_wait().await;
+    info!("dpkg --configure -a");
+
+    Dpkg::new().configure_all().status().await.map_err(WatchdogError::Configure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintainer_scripts() {
+        assert_eq!(
+            maintainer_script("/var/lib/dpkg/info/openssh-server.postinst"),
+            Some(("openssh-server", "postinst"))
+        );
+        assert_eq!(
+            maintainer_script("/var/lib/dpkg/info/libc6:amd64.preinst"),
+            Some(("libc6:amd64", "preinst"))
+        );
+        assert_eq!(maintainer_script("/var/lib/dpkg/info/libc6:amd64.list"), None);
+        assert_eq!(maintainer_script("/var/lib/dpkg/info/.postinst"), None);
+        assert_eq!(maintainer_script("configure"), None);
+    }
+
+    #[test]
+    fn process_tree() {
+        let process = |pid, ppid, comm: &str, cmdline: &[&str]| ProcessInfo {
+            pid,
+            ppid,
+            comm: comm.to_owned(),
+            cmdline: cmdline.iter().map(|arg| (*arg).to_owned()).collect(),
+        };
+
+        let postinst = "/var/lib/dpkg/info/foo.postinst";
+        let preinst = "/var/lib/dpkg/info/bar.preinst";
+
+        let mut processes = vec![
+            process(10, 1, "pop-upgrade", &[]),
+            process(20, 10, "apt-get", &["apt-get"]),
+            process(30, 20, "dpkg", &["dpkg"]),
+            // A script of another dpkg, which the daemon did not start.
+            process(5, 2, "sh", &["/bin/sh", preinst]),
+        ];
+
+        let dpkg = find_in_tree(&processes, 10, 60).unwrap();
+        assert_eq!(dpkg, Stall { pid: 30, idle: 60, ..Stall::default() });
+
+        processes.push(process(40, 30, "sh", &["/bin/sh", postinst, "configure"]));
+        processes.push(process(35, 40, "sh", &["/bin/sh", preinst]));
+
+        let script = find_in_tree(&processes, 10, 60).unwrap();
+        assert_eq!((script.pid, script.package.as_str()), (35, "bar"));
+        assert_eq!(script.script, "preinst");
+
+        assert_eq!(find_in_tree(&processes, 2, 60), None);
+    }
+}
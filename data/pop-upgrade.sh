@@ -3,6 +3,17 @@
 export LANG=C
 export DEBIAN_FRONTEND="noninteractive"
 
+# Package updates staged by the daemon, which are installed instead of a release upgrade.
+OFFLINE_UPDATE=/pop-upgrade-packages
+OFFLINE_UPDATE_REPORT=/var/lib/pop-upgrade/offline_update
+
+# Options for apt, such as the policy for phased updates, staged with the package updates
+APT_OPTIONS=()
+
+# Packages which are upgraded in place of a full upgrade, when some packages are excluded
+OFFLINE_UPDATE_ONLY=/pop-upgrade-packages-only
+UPGRADE=(full-upgrade)
+
 # Prevent apt sources from being reverted once this script launches
 rm -rf /pop-upgrade /pop_preparing_release_upgrade
 
@@ -80,7 +91,8 @@ apt_full_upgrade () {
         -o Dpkg::Options::="--force-conflicts" \
         -o Dpkg::Options::="--force-depends" \
         -o Dpkg::Options::="--force-depends-version" \
-        full-upgrade -y --allow-downgrades --show-progress \
+        "${APT_OPTIONS[@]}" \
+        "${UPGRADE[@]}" -y --allow-downgrades --show-progress \
         --no-download --ignore-missing | while read -r line; do
             if test "Progress: [" = "$(echo ${line} | cut -c-11)"; then
                 percent=$(echo "${line}" | cut -c12-14)
@@ -107,7 +119,8 @@ apt_full_upgrade () {
 
     # Validate the exit status
     apt-get -o Dpkg::Options::="--force-overwrite" \
-        full-upgrade -y --allow-downgrades \
+        "${APT_OPTIONS[@]}" \
+        "${UPGRADE[@]}" -y --allow-downgrades \
         --no-download --ignore-missing
 }
 
@@ -172,6 +185,55 @@ attempt_repair () {
     done
 }
 
+# Verifies that the system can boot, and repairs the boot configuration if it cannot.
+verify_boot () {
+    message -i "$1 Verifying that the system can boot..."
+    if ! pop-upgrade check-boot; then
+        message -i "$1 Repairing boot configuration..."
+        update-initramfs -u -k all
+        test -x /usr/bin/kernelstub && kernelstub
+        pop-upgrade check-boot || {
+            message -f "Unable to verify that the system can boot. See the journal for details."
+            sleep 5
+        }
+    fi
+}
+
+# Installs the package updates staged by the daemon, and reports the result to it.
+#
+# The staged updates are kept if the system restarts before they are installed, so that the
+# installation is tried again, but not if the installation fails.
+attempt_package_update () {
+    plymouth change-mode --system-upgrade
+    plymouth system-update --progress="0"
+    message -i "Installing Updates (0%)"
+
+    systemctl mask acpid pop-upgrade
+
+    mapfile -t APT_OPTIONS < "$OFFLINE_UPDATE"
+
+    if test -e "$OFFLINE_UPDATE_ONLY"; then
+        mapfile -t ONLY_UPGRADE < "$OFFLINE_UPDATE_ONLY"
+        UPGRADE=(install --only-upgrade "${ONLY_UPGRADE[@]}")
+    fi
+
+    if (upgrade || attempt_repair); then
+        verify_boot "Updates installed."
+        plymouth system-update --progress="100"
+        echo "0" > "$OFFLINE_UPDATE_REPORT"
+        message -i "Updates installed. Now rebooting..."
+    else
+        echo "1 apt was unable to install the updates: see /var/log/upgrade.log" > "$OFFLINE_UPDATE_REPORT"
+        message -f "Updates failed to install. Restarting the system..."
+    fi
+
+    rm -rf /system-update "$OFFLINE_UPDATE" "$OFFLINE_UPDATE_ONLY"
+    rm -f /etc/systemd/system/{acpid,pop-upgrade}.service
+    sync
+    sleep 3
+    systemctl reboot
+}
+
 # Attempts the upgrade the system, and if the upgrade fails, tries to repair it.
 attempt_upgrade () {
     plymouth change-mode --system-upgrade
@@ -206,6 +268,8 @@ attempt_upgrade () {
 
         message -i "Upgrade complete. Updating initramfs for all kernels..."
         update-initramfs -c -k all
+
+        verify_boot "Upgrade complete."
         plymouth system-update --progress="100"
 
         efi_rename
@@ -225,6 +289,11 @@ attempt_upgrade () {
     fi
 }
 
+if test -e "$OFFLINE_UPDATE"; then
+    attempt_package_update
+    exit
+fi
+
 ATTEMPTED=/upgrade-attempted
 
 test -e "$ATTEMPTED" && (message -i "System rebooted before upgrade was completed. Trying again..."; sleep 6)
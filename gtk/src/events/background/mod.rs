@@ -8,7 +8,7 @@ use self::scan::scan;
 
 use num_traits::cast::FromPrimitive;
 use pop_upgrade::{
-    client::{Client, ReleaseInfo, Status},
+    client::{self, Client, ReleaseInfo, Status},
     daemon::{DaemonStatus, DismissEvent},
     release::RefreshOp,
 };
@@ -114,6 +114,14 @@ pub fn run(
     }
 }
 
+fn status_package_upgrade(client: &Client) -> Option<UiEvent> {
+    match client.package_upgrade_status() {
+        Ok(status) if status.status == 0 => None,
+        Ok(status) => Some(UiEvent::Error(UiError::Updates(status.why.into()))),
+        Err(why) => Some(UiEvent::Error(UiError::Updates(why.into()))),
+    }
+}
+
 fn status_recovery_upgrade(client: &Client) -> UiEvent {
     match client.recovery_upgrade_release_status() {
         Ok(status) => {
@@ -142,6 +150,11 @@ fn status_release_upgrade(client: &Client) -> UiEvent {
 
 fn get_status(client: &Client, send: &dyn Fn(UiEvent), from: DaemonStatus) {
     match from {
+        DaemonStatus::PackageUpgrade => {
+            if let Some(event) = status_package_upgrade(client) {
+                send(event);
+            }
+        }
         DaemonStatus::RecoveryUpgrade => send(status_recovery_upgrade(client)),
         DaemonStatus::ReleaseUpgrade => send(status_release_upgrade(client)),
         _ => (),
@@ -163,3 +176,18 @@ fn status_changed(send: &dyn Fn(UiEvent), new_status: Status, expected: DaemonSt
     let status = DaemonStatus::from_u8(new_status.status).expect("unknown daemon status value");
     send(UiEvent::StatusChanged(expected, status, new_status.why));
 }
+
+/// Asks the UI to approve a download over a metered connection, and cancels the task if declined.
+fn approve_metered_download(
+    client: &Client,
+    send: &dyn Fn(UiEvent),
+    bytes: u64,
+) -> Result<(), client::Error> {
+    let (approval, response) = mpsc::sync_channel(1);
+    send(UiEvent::MeteredDownload(bytes, approval));
+
+    match response.recv() {
+        Ok(true) => client.approve_metered_download(),
+        _ => client.cancel(),
+    }
+}
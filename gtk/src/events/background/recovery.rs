@@ -3,7 +3,7 @@ use crate::{
     events::{CompletedEvent, InitiatedEvent, OsRecoveryEvent, ProgressEvent, UiEvent},
 };
 
-use super::status_changed;
+use super::{approve_metered_download, status_changed};
 
 use pop_upgrade::{
     client::{self, Client, Signal},
@@ -29,9 +29,10 @@ pub fn upgrade_listen(client: &Client, send: &dyn Fn(UiEvent)) -> bool {
     let result = client.event_listen(
         Client::recovery_upgrade_release_status,
         |status| status_changed(send, status, DaemonStatus::RecoveryUpgrade),
-        |_client, signal| {
+        |client, signal| {
             use pop_upgrade::client::Progress;
             match signal {
+                Signal::MeteredDownload(bytes) => approve_metered_download(client, send, bytes)?,
                 Signal::RecoveryDownloadProgress(Progress { progress, total }) => {
                     send(UiEvent::Progress(ProgressEvent::Recovery(progress, total)));
                 }
@@ -1,4 +1,4 @@
-use super::status_changed;
+use super::{approve_metered_download, status_changed};
 use crate::events::*;
 
 use apt_cmd::AptUpgradeEvent;
@@ -6,6 +6,7 @@ use pop_upgrade::{
     client::{self, Client, ReleaseInfo, Signal},
     daemon::DaemonStatus,
     release::UpgradeMethod,
+    updates::Selection,
 };
 
 pub fn download(client: &Client, send: &dyn Fn(UiEvent), info: &ReleaseInfo) {
@@ -32,25 +33,28 @@ pub fn download(client: &Client, send: &dyn Fn(UiEvent), info: &ReleaseInfo) {
         |status| {
             status_changed(send, status, DaemonStatus::ReleaseUpgrade);
         },
-        |_client, signal| {
+        |client, signal| {
             match signal {
-                Signal::PackageFetched(status) => {
-                    send(UiEvent::Progress(ProgressEvent::Fetching(
-                        status.completed.into(),
-                        status.total.into(),
-                    )));
-                }
                 Signal::PackageUpgrade(event) => {
-                    match AptUpgradeEvent::from_dbus_map(event.into_iter()) {
-                        Ok(AptUpgradeEvent::Progress { percent }) => {
-                            send(UiEvent::Progress(ProgressEvent::Updates(percent)));
-                        }
-                        Ok(AptUpgradeEvent::WaitingOnLock) => {
-                            send(UiEvent::WaitingOnLock);
-                        }
-                        _ => (),
+                    if let Ok(AptUpgradeEvent::WaitingOnLock) =
+                        AptUpgradeEvent::from_dbus_map(event.into_iter())
+                    {
+                        send(UiEvent::WaitingOnLock);
                     }
                 }
+                Signal::MeteredDownload(bytes) => approve_metered_download(client, send, bytes)?,
+                Signal::PendingRemovals(packages) => {
+                    let (approval, response) = std::sync::mpsc::sync_channel(1);
+                    send(UiEvent::Upgrade(OsUpgradeEvent::PendingRemovals(packages, approval)));
+
+                    match response.recv() {
+                        Ok(Some(keep)) => client.approve_removals(&keep)?,
+                        _ => client.cancel()?,
+                    }
+                }
+                Signal::Progress(progress) => {
+                    send(UiEvent::Progress(ProgressEvent::Overall(progress.percent, progress.eta)));
+                }
                 Signal::RecoveryDownloadProgress(Progress { progress, total }) => {
                     println!("Progress {}/{}", progress, total);
                     send(UiEvent::Progress(ProgressEvent::Recovery(progress, total)));
@@ -90,7 +94,7 @@ pub fn download(client: &Client, send: &dyn Fn(UiEvent), info: &ReleaseInfo) {
 
 pub fn update(client: &Client, send: &dyn Fn(UiEvent)) -> bool {
     info!("checking if updates are required");
-    let updates = match client.fetch_updates(Vec::new(), false) {
+    let updates = match client.fetch_updates(Vec::new(), false, Selection::default()) {
         Ok(updates) => updates,
         Err(why) => {
             send(UiEvent::Error(UiError::Updates(why.into())));
@@ -99,42 +103,40 @@ pub fn update(client: &Client, send: &dyn Fn(UiEvent)) -> bool {
     };
 
     if updates.updates_available {
-        send(UiEvent::Progress(ProgressEvent::Fetching(
-            updates.completed.into(),
-            updates.total.into(),
-        )));
+        if let Ok(progress) = client.progress() {
+            send(UiEvent::Progress(ProgressEvent::Overall(progress.percent, progress.eta)));
+        }
 
         let error = &mut None;
 
         let _ = client.event_listen(
             Client::fetch_updates_status,
             |status| status_changed(send, status, DaemonStatus::FetchingPackages),
-            |_client, signal| {
+            |client, signal| {
                 match signal {
-                    Signal::PackageFetchResult(status) => {
+                    Signal::MeteredDownload(bytes) => {
+                        approve_metered_download(client, send, bytes)?;
+                    }
+                    Signal::PackageFetchResult(status) | Signal::PackageUpgradeResult(status) => {
                         if status.status != 0 {
                             *error = Some(status.why);
                         }
 
                         return Ok(client::Continue::False);
                     }
-                    Signal::PackageFetched(status) => {
-                        send(UiEvent::Progress(ProgressEvent::Fetching(
-                            status.completed.into(),
-                            status.total.into(),
-                        )));
-                    }
                     Signal::PackageUpgrade(event) => {
-                        match AptUpgradeEvent::from_dbus_map(event.into_iter()) {
-                            Ok(AptUpgradeEvent::Progress { percent }) => {
-                                send(UiEvent::Progress(ProgressEvent::Updates(percent)));
-                            }
-                            Ok(AptUpgradeEvent::WaitingOnLock) => {
-                                send(UiEvent::WaitingOnLock);
-                            }
-                            _ => (),
+                        if let Ok(AptUpgradeEvent::WaitingOnLock) =
+                            AptUpgradeEvent::from_dbus_map(event.into_iter())
+                        {
+                            send(UiEvent::WaitingOnLock);
                         }
                     }
+                    Signal::Progress(progress) => {
+                        send(UiEvent::Progress(ProgressEvent::Overall(
+                            progress.percent,
+                            progress.eta,
+                        )));
+                    }
                     Signal::ReleaseEvent(event) => {
                         send(UiEvent::Upgrade(OsUpgradeEvent::Event(event)));
                     }
@@ -7,7 +7,7 @@ use crate::{
     fl, get_dismiss_row, get_upgrade_row, notify, reboot,
     state::State,
     widgets::{
-        dialogs::{RefreshDialog, UpgradeDialog},
+        dialogs::{MeteredDialog, RefreshDialog, RemovalsDialog, UpgradeDialog},
         permissions::PermissionDenied,
         Dismisser, UpgradeSection,
     },
@@ -31,6 +31,7 @@ use std::{
     path::Path,
     sync::{self, mpsc::SyncSender},
     thread,
+    time::Duration,
 };
 
 /// Events received for the UI to handle.
@@ -39,6 +40,8 @@ pub enum UiEvent {
     Completed(CompletedEvent),
     Error(UiError),
     Initiated(InitiatedEvent),
+    /// A download of the given bytes over a metered connection, which must be approved.
+    MeteredDownload(u64, SyncSender<bool>),
     Progress(ProgressEvent),
     Recovery(OsRecoveryEvent),
     Shutdown,
@@ -59,6 +62,8 @@ pub enum OsUpgradeEvent {
     Dismissed(bool),
     Event(UpgradeEvent),
     Notification,
+    /// Packages to be removed, which must be approved or declined.
+    PendingRemovals(Vec<String>, SyncSender<Option<Vec<String>>>),
     Upgrade,
 }
 
@@ -88,9 +93,9 @@ pub enum CompletedEvent {
 
 #[derive(Debug)]
 pub enum ProgressEvent {
-    Fetching(u64, u64),
+    /// The overall progress of the daemon's task, and the estimated time remaining.
+    Overall(u8, Option<Duration>),
     Recovery(u64, u64),
-    Updates(u8),
 }
 
 #[repr(u8)]
@@ -127,9 +132,15 @@ pub async fn on_event(widgets: &mut EventWidgets, state: &mut State, event: UiEv
     debug!("{:?}", event);
     match event {
         UiEvent::Progress(event) => match event {
-            ProgressEvent::Fetching(progress, total) => {
-                let progress = state.calculate_fetching_progress(progress, total);
-                widgets.upgrade.options[0].progress_exact(progress as u8).show_progress();
+            ProgressEvent::Overall(percent, eta) => {
+                let remaining = eta.map(|eta| {
+                    fl!("download-remaining", minutes = (eta.as_secs().div_ceil(60)))
+                });
+
+                widgets.upgrade.options[0]
+                    .progress_exact(percent)
+                    .sublabel(remaining.as_deref())
+                    .show_progress();
             }
 
             ProgressEvent::Recovery(progress, total) => {
@@ -143,10 +154,6 @@ pub async fn on_event(widgets: &mut EventWidgets, state: &mut State, event: UiEv
                     .progress(progress, total)
                     .show_progress();
             }
-
-            ProgressEvent::Updates(percent) => {
-                widgets.upgrade.options[0].progress_exact(percent / 4 + 25).show_progress();
-            }
         },
 
         // Signals that a process in the background has begun.
@@ -210,19 +217,22 @@ pub async fn on_event(widgets: &mut EventWidgets, state: &mut State, event: UiEv
                 }
             }
 
-            OsUpgradeEvent::Event(UpgradeEvent::UpgradingPackages) => {
-                widgets.upgrade.options[0].progress_exact(25);
-            }
-
-            OsUpgradeEvent::Event(UpgradeEvent::UpdatingSourceLists) => {
-                widgets.upgrade.options[0].progress_exact(50);
-                state.fetching_release = true;
-            }
-
             OsUpgradeEvent::Event(_) => (),
 
             OsUpgradeEvent::Notification => (state.callback_ready.borrow())(),
 
+            OsUpgradeEvent::PendingRemovals(packages, approval) => {
+                let dialog = RemovalsDialog::new(packages);
+
+                let answer = dialog.run();
+                dialog.close();
+                let _ = approval.send(if gtk::ResponseType::Accept == answer {
+                    Some(dialog.keep())
+                } else {
+                    None
+                });
+            }
+
             OsUpgradeEvent::Upgrade => release_upgrade_dialog(state, widgets),
         },
 
@@ -296,6 +306,14 @@ pub async fn on_event(widgets: &mut EventWidgets, state: &mut State, event: UiEv
 
         UiEvent::Error(why) => error(state, widgets, &why),
 
+        UiEvent::MeteredDownload(bytes, approval) => {
+            let dialog = MeteredDialog::new(bytes);
+
+            let answer = dialog.run();
+            dialog.close();
+            let _ = approval.send(gtk::ResponseType::Accept == answer);
+        }
+
         UiEvent::WaitingOnLock => (),
 
         UiEvent::Shutdown => return false,
@@ -531,8 +549,6 @@ fn release_upgrade_dialog(state: &mut State, widgets: &EventWidgets) {
 
 /// Resets widgets and state
 fn reset(state: &mut State, widgets: &EventWidgets) {
-    state.fetching_release = false;
-
     if state.recovery_urgent {
         widgets.recovery.options[RECOVERY_PARTITION].show_button();
         widgets.recovery.show();
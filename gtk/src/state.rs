@@ -10,8 +10,6 @@ use std::sync::{self, mpsc::SyncSender};
 
 #[derive(new)]
 pub struct State {
-    #[new(default)]
-    pub fetching_release:   bool,
     #[new(default)]
     pub recovery_urgent:    bool,
     #[new(default)]
@@ -42,16 +40,3 @@ pub struct State {
     pub callback_event: EventCallback,
     pub callback_ready: ReadyCallback,
 }
-
-impl State {
-    pub fn calculate_fetching_progress(&self, mut progress: u64, total: u64) -> u64 {
-        progress = if self.fetching_release { progress / 2 } else { progress / 4 };
-        progress = progress * 100 / total;
-
-        if self.fetching_release {
-            progress += 50;
-        }
-
-        progress
-    }
-}
@@ -0,0 +1,41 @@
+use gtk::prelude::*;
+
+use super::DialogTemplate;
+use crate::fl;
+
+/// Asks to approve a download over a metered connection.
+#[derive(AsRef, Deref)]
+#[as_ref]
+#[deref]
+pub struct MeteredDialog(DialogTemplate);
+
+impl MeteredDialog {
+    /// The size of the download is unknown if `bytes` is `0`.
+    pub fn new(bytes: u64) -> Self {
+        let description = if bytes == 0 {
+            fl!("dialog-metered-description-unknown")
+        } else {
+            fl!("dialog-metered-description", size = glib::format_size(bytes).to_string())
+        };
+
+        Self(cascade! {
+            DialogTemplate::new(
+                "network-cellular-symbolic",
+                &fl!("dialog-metered-title"),
+                &fl!("button-download"),
+                &gtk::STYLE_CLASS_SUGGESTED_ACTION,
+                |content| {
+                    let description = gtk::Label::builder()
+                        .label(&description)
+                        .wrap(true)
+                        .xalign(0.0)
+                        .build();
+
+                    content.add(&description);
+                },
+            );
+            ..set_size_request(400, 200);
+            ..set_valign(gtk::Align::Start);
+        })
+    }
+}
@@ -1,7 +1,12 @@
+mod metered;
 mod refresh;
+mod removals;
 mod upgrade;
 
-pub use self::{refresh::RefreshDialog, upgrade::UpgradeDialog};
+pub use self::{
+    metered::MeteredDialog, refresh::RefreshDialog, removals::RemovalsDialog,
+    upgrade::UpgradeDialog,
+};
 
 use crate::fl;
 use gtk::prelude::*;
@@ -0,0 +1,66 @@
+use super::DialogTemplate;
+use crate::fl;
+use gtk::prelude::*;
+
+/// Lists the packages that a release upgrade will remove, and allows some to be kept.
+#[derive(AsRef, Deref)]
+pub struct RemovalsDialog {
+    #[as_ref]
+    #[deref]
+    dialog:   DialogTemplate,
+    packages: Vec<(String, gtk::CheckButton)>,
+}
+
+impl RemovalsDialog {
+    pub fn new(packages: Vec<String>) -> Self {
+        let packages = packages
+            .into_iter()
+            .map(|package| {
+                let keep = gtk::CheckButton::with_label(&package);
+                (package, keep)
+            })
+            .collect::<Vec<_>>();
+
+        let dialog = DialogTemplate::new(
+            "dialog-warning",
+            &fl!("dialog-removals-title"),
+            &fl!("button-remove-packages"),
+            &gtk::STYLE_CLASS_DESTRUCTIVE_ACTION,
+            |content| {
+                let description = gtk::Label::builder()
+                    .label(&fl!("dialog-removals-description"))
+                    .wrap(true)
+                    .xalign(0.0)
+                    .build();
+
+                let list = gtk::Box::new(gtk::Orientation::Vertical, 6);
+                for (_, keep) in &packages {
+                    list.add(keep);
+                }
+
+                let scroller = cascade! {
+                    gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+                    ..set_hexpand(true);
+                    ..set_vexpand(true);
+                    ..add(&list);
+                };
+
+                content.add(&description);
+                content.add(&scroller);
+            },
+        );
+
+        dialog.set_size_request(480, 360);
+
+        Self { dialog, packages }
+    }
+
+    /// The packages which were selected to be kept.
+    pub fn keep(&self) -> Vec<String> {
+        self.packages
+            .iter()
+            .filter(|(_, keep)| keep.is_active())
+            .map(|(package, _)| package.clone())
+            .collect()
+    }
+}
@@ -4,14 +4,22 @@ battery-notice = <b>Plug into power</b> before you begin.
 
 button-cancel = Cancel
 button-dismiss = Dismiss
+button-download = Download
 button-perform-refresh = Restart & Refresh
 button-perform-upgrade = Restart & Install
 button-refresh = Refresh
+button-remove-packages = Remove & Continue
 button-update = Update
 button-upgrade = Start Upgrade
 
 checking-for-updates = Checking for updates ...
 
+dialog-metered-title = Metered Connection
+dialog-metered-description =
+ This connection is metered. Downloading {$size} may count against your data plan.
+dialog-metered-description-unknown =
+ This connection is metered. The download may be large, and count against your data plan.
+
 dialog-refresh-title = Refresh OS Install
 dialog-refresh-description =
  When you refresh the OS:
@@ -28,11 +36,17 @@ dialog-refresh-description =
 
  Please be sure to save all of your work before clicking to reboot.
 
+dialog-removals-title = Packages to Remove
+dialog-removals-description =
+ These packages are not available in the new release, or depend on packages which are not.
+ Select any packages which should be kept. Kept packages will be held at their current version.
+
 daemon-checking = Checking for updates to daemon
 daemon-updating = Updating the upgrade daemon
 
 download-os = Downloading {-os} {$version}
 download-os-complete = {-os} {$version} download complete
+download-remaining = About {$minutes} minutes remaining
 
 eol-exceeded = Support for {-os} {$current} has ended. Security and application updates are no longer provided for {-os} {$current}. Upgrade to {-os} {$next} to keep your computer secure.
 eol-imminent = Support for {-os} {$current} ends {$date}. Upgrade for security and application updates